@@ -1,22 +1,20 @@
 use env_logger;
-use i2p::net::I2pStream;
+use i2p::http::HttpClient;
+use i2p::sam::DEFAULT_API;
+use i2p::Session;
 use std::env;
-use std::io::{BufReader, Read, Write};
 
 fn help() {
 	println!("Usage: eepget <host.i2p> [port]")
 }
 
 fn print_homepage(host: &str, port: u16) {
-	let mut stream = I2pStream::connect(format!("{}:{}", host, port)).unwrap();
+	let session = Session::transient(DEFAULT_API).unwrap();
+	let client = HttpClient::new(session);
+	let res = client.get(&format!("http://{}:{}/", host, port)).unwrap();
 
-	let msg = "GET / HTTP/1.1\r\n\r\n";
-	let _ = stream.write(msg.as_bytes());
-	let mut reader = BufReader::new(stream);
-	let mut buffer = String::new();
-	let _ = reader.read_to_string(&mut buffer);
-
-	println!("{}", buffer);
+	println!("{} {}", res.status, res.reason);
+	println!("{}", res.text());
 }
 
 fn main() {
@@ -14,7 +14,7 @@ use std::net::Shutdown;
 use std::str::from_utf8;
 use std::{thread, time};
 
-use i2p::sam::{SamConnection, SessionStyle, DEFAULT_API};
+use i2p::sam::{SamConnection, DEFAULT_API};
 
 // Run with RUST_LOG=debug to see the action
 #[tokio::main]
@@ -28,16 +28,21 @@ async fn main() {
 	};
 	info!("New public key: {}", pubkey);
 	info!("New secret key: {}", seckey);
-	let mut watcher = i2p::session_watcher::SamSessionWatcher::new(
+	let supervisor = i2p::session_watcher::SessionSupervisor::new(
 		DEFAULT_API,
 		&seckey,
-		SessionStyle::Stream,
 		Default::default(),
 	)
 	.unwrap();
+	let states = supervisor.states();
+	thread::spawn(move || {
+		for state in states {
+			info!("session is {:?}", state);
+		}
+	});
 
 	loop {
-		match watcher.accept() {
+		match supervisor.accept() {
 			Ok((conn, addr)) => {
 				info!("receiving incoming connection {}", addr);
 				let _ = conn.shutdown(Shutdown::Both).unwrap();
@@ -1,21 +1,30 @@
 use nom;
 
 use std::io;
+use std::ops::Deref;
+use std::sync::Arc;
 use thiserror::Error as ThisError;
 
+use crate::sam_options::InvalidOption;
+
+/// Result of the crate's operations.
+pub type Result<T, E = I2PError> = std::result::Result<T, E>;
+
 /// Kinds of I2P/SAM errors
 #[derive(Clone, Eq, PartialEq, Debug, ThisError)]
 pub enum I2PError {
 	/// Wraps io errors
-	#[error("IO error occurred (is i2p running?): {0}")]
-	Io(String),
+	#[error("IO error occurred (is i2p running?)")]
+	Io(#[source] IoError),
 	/// Wraps nom parser errors
 	#[error("Failed to parse an I2P/SAM message")]
 	MessageParsing,
-	#[error("Failed to parse an I2P/SAM message")]
+	#[error("Address didn't resolve to any I2P or SAM bridge address")]
 	UnresolvableAddress,
 	#[error("Invalid or unrecognized I2P/SAM message: {0}")]
 	SAMInvalidMessage(String),
+	#[error("Unknown I2P/SAM result {result}: {message}")]
+	SAMUnknownResult { result: String, message: String },
 	#[error("Can't reach peer: {0}")]
 	SAMCantReachPeer(String),
 	#[error("Destination key not found: {0}")]
@@ -24,6 +33,8 @@ pub enum I2PError {
 	SAMPeerNotFound(String),
 	#[error("Duplicate peer destination: {0}")]
 	SAMDuplicatedDest(String),
+	#[error("Duplicate session id: {0}")]
+	SAMDuplicatedId(String),
 	#[error("Invalid destination key: {0}")]
 	SAMInvalidKey(String),
 	#[error("Invalid stream id: {0}")]
@@ -32,15 +43,223 @@ pub enum I2PError {
 	SAMTimeout(String),
 	#[error("Unknown I2P/SAM error: {0}")]
 	SAMI2PError(String),
+	#[error("No SAM version supported by both sides: {0}")]
+	SAMNoVersion(String),
+	#[error("Already accepting on the session: {0}")]
+	SAMAlreadyAccepting(String),
+	#[error("Peer's leaseset not found: {0}")]
+	SAMLeaseSetNotFound(String),
 	#[error("I2P address isn't a valid b32 or b64 encoding: {0}")]
 	BadAddressEncoding(String),
 	#[error("Accept encountered error, and session was recreated. try operation again")]
 	SessionRecreated,
+	#[error("Operation interrupted, the session was shut down")]
+	Shutdown,
+	#[error("Silent stream closed by the SAM bridge before any data")]
+	SilentStreamClosed,
+	#[error("Invalid SAM options: {}", join_options(.0))]
+	InvalidOptions(Vec<InvalidOption>),
+	#[error("No tunnel named {0} in the configuration")]
+	TunnelNotFound(String),
+	#[error("The options of a {0} can't be updated")]
+	OptionsUpdateUnsupported(String),
+	/// The session was closed to update its options, and neither the new
+	/// nor the old options could create it again.
+	#[error("Session closed and couldn't be created again: {0}")]
+	SessionLost(#[source] Box<I2PError>),
+	#[error("Invalid or non-I2P HTTP URL: {0}")]
+	HttpInvalidUrl(String),
+	#[error("Malformed HTTP request: {0}")]
+	HttpMalformedRequest(String),
+	#[error("Malformed HTTP response: {0}")]
+	HttpMalformedResponse(String),
+	#[error("Too many HTTP redirects, gave up after {0}")]
+	HttpTooManyRedirects(usize),
+	#[error("SOCKS protocol error: {0}")]
+	SocksProtocol(String),
+	#[error("SOCKS{version} request not allowed, only .i2p hosts are proxied")]
+	SocksNotAllowed { version: u8 },
+	#[error("SOCKS{version} command {command:#04x} not supported")]
+	SocksCommandNotSupported { version: u8, command: u8 },
+	#[error("SOCKS5 address type {0:#04x} not supported")]
+	SocksAddressTypeNotSupported(u8),
+}
+
+/// An [io::Error] shared between the clones of an [I2PError]. Two of them
+/// are equal when their kinds and messages are.
+#[derive(Clone, Debug, ThisError)]
+#[error(transparent)]
+pub struct IoError(Arc<io::Error>);
+
+impl IoError {
+	/// Takes back the [io::Error], or a copy of its kind and message when
+	/// it is still shared with a clone.
+	pub fn into_inner(self) -> io::Error {
+		Arc::try_unwrap(self.0).unwrap_or_else(|err| io::Error::new(err.kind(), err.to_string()))
+	}
+}
+
+impl Deref for IoError {
+	type Target = io::Error;
+
+	fn deref(&self) -> &io::Error {
+		&self.0
+	}
 }
 
+impl PartialEq for IoError {
+	fn eq(&self, other: &IoError) -> bool {
+		self.kind() == other.kind() && self.to_string() == other.to_string()
+	}
+}
+
+impl Eq for IoError {}
+
+impl From<io::Error> for IoError {
+	fn from(err: io::Error) -> IoError {
+		IoError(Arc::new(err))
+	}
+}
+
+impl I2PError {
+	/// Maps a SAM `RESULT` other than `OK` to its error, with the reply's
+	/// `MESSAGE`.
+	pub(crate) fn from_result(result: &str, message: &str) -> I2PError {
+		let message = message.to_string();
+		match result {
+			"CANT_REACH_PEER" => I2PError::SAMCantReachPeer(message),
+			"KEY_NOT_FOUND" => I2PError::SAMKeyNotFound(message),
+			"PEER_NOT_FOUND" => I2PError::SAMPeerNotFound(message),
+			"DUPLICATED_DEST" => I2PError::SAMDuplicatedDest(message),
+			"DUPLICATED_ID" => I2PError::SAMDuplicatedId(message),
+			"INVALID_KEY" => I2PError::SAMInvalidKey(message),
+			"INVALID_ID" => I2PError::SAMInvalidId(message),
+			"TIMEOUT" => I2PError::SAMTimeout(message),
+			"I2P_ERROR" => I2PError::SAMI2PError(message),
+			"NOVERSION" => I2PError::SAMNoVersion(message),
+			"ALREADY_ACCEPTING" => I2PError::SAMAlreadyAccepting(message),
+			"LEASESET_NOT_FOUND" => I2PError::SAMLeaseSetNotFound(message),
+			_ => I2PError::SAMUnknownResult {
+				result: result.to_string(),
+				message,
+			},
+		}
+	}
+
+	/// Whether the operation may succeed if tried again, possibly on a new
+	/// session: timeouts, peers that can't be reached yet, router errors
+	/// and I/O errors of a connection that was lost or refused.
+	pub fn is_retryable(&self) -> bool {
+		match self {
+			I2PError::Io(err) => matches!(
+				err.kind(),
+				io::ErrorKind::ConnectionRefused
+					| io::ErrorKind::ConnectionReset
+					| io::ErrorKind::ConnectionAborted
+					| io::ErrorKind::NotConnected
+					| io::ErrorKind::BrokenPipe
+					| io::ErrorKind::TimedOut
+					| io::ErrorKind::Interrupted
+					| io::ErrorKind::UnexpectedEof
+			),
+			I2PError::SAMCantReachPeer(_)
+			| I2PError::SAMPeerNotFound(_)
+			| I2PError::SAMLeaseSetNotFound(_)
+			| I2PError::SAMTimeout(_)
+			| I2PError::SAMI2PError(_)
+			| I2PError::SAMAlreadyAccepting(_)
+			| I2PError::SessionRecreated
+			| I2PError::SilentStreamClosed => true,
+			_ => false,
+		}
+	}
+
+	/// The [io::ErrorKind] closest to this error, the one it has once
+	/// converted into an [io::Error].
+	pub fn kind(&self) -> io::ErrorKind {
+		match self {
+			I2PError::Io(err) => err.kind(),
+			I2PError::UnresolvableAddress
+			| I2PError::SAMInvalidKey(_)
+			| I2PError::SAMInvalidId(_)
+			| I2PError::BadAddressEncoding(_)
+			| I2PError::InvalidOptions(_)
+			| I2PError::HttpInvalidUrl(_) => io::ErrorKind::InvalidInput,
+			I2PError::MessageParsing
+			| I2PError::SAMInvalidMessage(_)
+			| I2PError::SAMUnknownResult { .. }
+			| I2PError::HttpMalformedRequest(_)
+			| I2PError::HttpMalformedResponse(_)
+			| I2PError::SocksProtocol(_) => io::ErrorKind::InvalidData,
+			I2PError::SAMCantReachPeer(_)
+			| I2PError::SocksNotAllowed { .. }
+			| I2PError::SocksCommandNotSupported { .. }
+			| I2PError::SocksAddressTypeNotSupported(_) => io::ErrorKind::ConnectionRefused,
+			I2PError::SAMPeerNotFound(_) | I2PError::SAMLeaseSetNotFound(_) => {
+				io::ErrorKind::HostUnreachable
+			}
+			I2PError::SAMKeyNotFound(_) | I2PError::TunnelNotFound(_) => io::ErrorKind::NotFound,
+			I2PError::SAMDuplicatedDest(_)
+			| I2PError::SAMDuplicatedId(_)
+			| I2PError::SAMAlreadyAccepting(_) => io::ErrorKind::AddrInUse,
+			I2PError::SAMTimeout(_) => io::ErrorKind::TimedOut,
+			I2PError::SAMNoVersion(_) | I2PError::OptionsUpdateUnsupported(_) => {
+				io::ErrorKind::Unsupported
+			}
+			// not Interrupted, which std callers retry in a loop
+			I2PError::Shutdown => io::ErrorKind::ConnectionAborted,
+			I2PError::SessionRecreated => io::ErrorKind::ConnectionReset,
+			I2PError::SessionLost(_) => io::ErrorKind::NotConnected,
+			I2PError::SilentStreamClosed => io::ErrorKind::UnexpectedEof,
+			I2PError::SAMI2PError(_) | I2PError::HttpTooManyRedirects(_) => io::ErrorKind::Other,
+		}
+	}
+
+	/// Whether the operation timed out, on the SAM bridge or locally.
+	pub fn is_timeout(&self) -> bool {
+		match self {
+			I2PError::Io(err) => matches!(
+				err.kind(),
+				io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+			),
+			I2PError::SAMTimeout(_) => true,
+			_ => false,
+		}
+	}
+}
+
+fn join_options(options: &[InvalidOption]) -> String {
+	options
+		.iter()
+		.map(ToString::to_string)
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+/// Wraps the error in an [io::Error] of its [kind](I2PError::kind), or
+/// unwraps the [io::Error] of [I2PError::Io].
+impl From<I2PError> for io::Error {
+	fn from(err: I2PError) -> io::Error {
+		match err {
+			I2PError::Io(err) => err.into_inner(),
+			err => io::Error::new(err.kind(), err),
+		}
+	}
+}
+
+/// Wraps the error in [I2PError::Io], unless it wraps an [I2PError], which
+/// is then taken back out.
 impl From<io::Error> for I2PError {
 	fn from(err: io::Error) -> I2PError {
-		Self::Io(err.to_string())
+		err.downcast::<I2PError>()
+			.unwrap_or_else(|err| I2PError::Io(err.into()))
+	}
+}
+
+#[cfg(feature = "tokio")]
+impl From<tokio::task::JoinError> for I2PError {
+	fn from(err: tokio::task::JoinError) -> I2PError {
+		Self::Io(io::Error::from(err).into())
 	}
 }
 
@@ -49,3 +268,63 @@ impl<E> From<nom::Err<E>> for I2PError {
 		Self::MessageParsing
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::error::Error;
+
+	#[test]
+	fn sam_results() {
+		assert!(matches!(
+			I2PError::from_result("DUPLICATED_ID", "id in use"),
+			I2PError::SAMDuplicatedId(m) if m == "id in use"
+		));
+		assert!(matches!(
+			I2PError::from_result("LEASESET_NOT_FOUND", ""),
+			I2PError::SAMLeaseSetNotFound(_)
+		));
+		let err = I2PError::from_result("NEW_RESULT", "what");
+		assert!(
+			matches!(&err, I2PError::SAMUnknownResult { result, .. } if result == "NEW_RESULT")
+		);
+		assert!(!err.is_retryable());
+	}
+
+	#[test]
+	fn io_errors() {
+		let err = I2PError::from(io::Error::from(io::ErrorKind::ConnectionRefused));
+		assert!(err.is_retryable());
+		assert!(!err.is_timeout());
+		let source = err.source().unwrap().downcast_ref::<IoError>().unwrap();
+		assert_eq!(source.kind(), io::ErrorKind::ConnectionRefused);
+		// the cause is only in the source, not repeated in the message
+		assert_eq!(err.to_string(), "IO error occurred (is i2p running?)");
+		assert_eq!(err.clone(), err);
+		assert_ne!(err, I2PError::from(io::Error::other("boom")));
+
+		let err = I2PError::from(io::Error::from(io::ErrorKind::PermissionDenied));
+		assert!(!err.is_retryable());
+		assert!(I2PError::from(io::Error::from(io::ErrorKind::TimedOut)).is_timeout());
+	}
+
+	#[test]
+	fn into_io_error() {
+		let err = io::Error::from(I2PError::SAMCantReachPeer("gone".to_string()));
+		assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+		assert_eq!(err.to_string(), "Can't reach peer: gone");
+		let err = io::Error::from(I2PError::SAMTimeout(String::new()));
+		assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+		let err = io::Error::from(I2PError::SAMInvalidKey(String::new()));
+		assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+		// the round trip gives back the original error
+		assert!(matches!(
+			I2PError::from(io::Error::from(I2PError::SAMDuplicatedId("x".to_string()))),
+			I2PError::SAMDuplicatedId(id) if id == "x"
+		));
+		let err = io::Error::from(I2PError::from(io::Error::other("boom")));
+		assert_eq!(err.kind(), io::ErrorKind::Other);
+		assert_eq!(err.to_string(), "boom");
+	}
+}
@@ -0,0 +1,281 @@
+use crate::error::Result;
+use log::debug;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::client::DEFAULT_MAX_IDLE_PER_HOST;
+use super::{
+	end_line, is_credential, is_idempotent, redirect_method, write_request_head, Next,
+	ReadResponse, Response, ResponseHead, ResponseReader, Url, DEFAULT_MAX_BODY_SIZE,
+	DEFAULT_MAX_REDIRECTS, MAX_LINE_LEN,
+};
+use crate::error::I2PError;
+use crate::net::{I2pSocketAddr, I2pStream};
+use crate::sam::Session;
+
+/// The async counterpart of [HttpClient](super::HttpClient).
+///
+/// Stream establishment still goes through the blocking SAM API, so it is
+/// moved onto tokio's blocking thread pool. Once connected, requests and
+/// responses are handled asynchronously.
+///
+/// # Examples
+///
+/// ```no_run
+/// use i2p::http::AsyncHttpClient;
+/// use i2p::Session;
+///
+/// # async fn run() -> i2p::error::Result<()> {
+/// let session = Session::transient(i2p::sam::DEFAULT_API)?;
+/// let client = AsyncHttpClient::new(session);
+/// let res = client.get("http://example.i2p/").await?;
+/// println!("{} {}", res.status, res.text());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AsyncHttpClient {
+	session: Arc<Session>,
+	idle: Arc<Mutex<HashMap<I2pSocketAddr, Vec<TcpStream>>>>,
+	max_idle_per_host: usize,
+	max_redirects: usize,
+	max_body_size: u64,
+}
+
+impl AsyncHttpClient {
+	/// Creates a client sending all requests through `session`.
+	pub fn new(session: Session) -> AsyncHttpClient {
+		AsyncHttpClient {
+			session: Arc::new(session),
+			idle: Arc::new(Mutex::new(HashMap::new())),
+			max_idle_per_host: DEFAULT_MAX_IDLE_PER_HOST,
+			max_redirects: DEFAULT_MAX_REDIRECTS,
+			max_body_size: DEFAULT_MAX_BODY_SIZE,
+		}
+	}
+
+	/// Creates a client using a duplicate handle of an existing session.
+	pub fn with_session(session: &Session) -> Result<AsyncHttpClient> {
+		Ok(AsyncHttpClient::new(session.duplicate()?))
+	}
+
+	/// Sets the maximum number of redirects followed for a single request.
+	/// Zero disables following redirects.
+	pub fn max_redirects(mut self, max: usize) -> AsyncHttpClient {
+		self.max_redirects = max;
+		self
+	}
+
+	/// Sets the maximum number of idle connections kept open per destination.
+	/// Zero disables connection reuse.
+	pub fn max_idle_per_host(mut self, max: usize) -> AsyncHttpClient {
+		self.max_idle_per_host = max;
+		self
+	}
+
+	/// Sets the maximum size of a response body, [DEFAULT_MAX_BODY_SIZE] by
+	/// default. Larger responses fail with
+	/// [I2PError::HttpMalformedResponse].
+	pub fn max_body_size(mut self, max: u64) -> AsyncHttpClient {
+		self.max_body_size = max;
+		self
+	}
+
+	/// Sends a `GET` request.
+	pub async fn get(&self, url: &str) -> Result<Response> {
+		self.request("GET", url, &[], &[]).await
+	}
+
+	/// Sends a `POST` request with the given body.
+	pub async fn post(&self, url: &str, content_type: &str, body: &[u8]) -> Result<Response> {
+		self.request("POST", url, &[("Content-Type", content_type)], body)
+			.await
+	}
+
+	/// Sends a request and reads the full response, following redirects
+	/// within I2P. Credentials in `headers`, such as `Authorization` or
+	/// `Cookie`, are dropped when a redirect leads to another host.
+	pub async fn request(
+		&self,
+		method: &str,
+		url: &str,
+		headers: &[(&str, &str)],
+		body: &[u8],
+	) -> Result<Response> {
+		let mut url = Url::parse(url)?;
+		let mut method = method.to_string();
+		let mut headers = headers.to_vec();
+		let mut body = body;
+		for _ in 0..=self.max_redirects {
+			let (head, res_body) = self.send(&method, &url, &headers, body).await?;
+			if let (true, Some(location)) = (head.is_redirect(), head.header("Location")) {
+				debug!("{} {} redirected to {}", head.status, url, location);
+				let next_url = url.join(location)?;
+				if next_url.host() != url.host() {
+					headers.retain(|(name, _)| !is_credential(name));
+				}
+				url = next_url;
+				let next = redirect_method(head.status, &method);
+				if next != method {
+					method = next.to_string();
+					body = &[];
+				}
+				continue;
+			}
+			return Ok(Response {
+				status: head.status,
+				reason: head.reason,
+				headers: head.headers,
+				body: res_body,
+				url,
+			});
+		}
+		Err(I2PError::HttpTooManyRedirects(self.max_redirects))
+	}
+
+	async fn send(
+		&self,
+		method: &str,
+		url: &Url,
+		headers: &[(&str, &str)],
+		body: &[u8],
+	) -> Result<(ResponseHead, Vec<u8>)> {
+		let addr = url.socket_addr();
+		let head = write_request_head(method, url, headers, body.len());
+
+		if is_idempotent(method) {
+			while let Some(stream) = self.checkout(&addr) {
+				match self.round_trip(stream, &addr, method, &head, body).await? {
+					Some(res) => return Ok(res),
+					None => debug!("idle connection to {} was closed", addr),
+				}
+			}
+		}
+
+		let stream = self.connect(addr.clone()).await?;
+		self.round_trip(stream, &addr, method, &head, body)
+			.await?
+			.ok_or_else(|| {
+				I2PError::HttpMalformedResponse("connection closed without a response".into())
+			})
+	}
+
+	async fn connect(&self, addr: I2pSocketAddr) -> Result<TcpStream> {
+		let session = self.session.clone();
+		tokio::task::spawn_blocking(move || {
+			let mut stream = I2pStream::connect_with_session(&session, &addr)?;
+			stream.to_tokio_stream()
+		})
+		.await?
+	}
+
+	async fn round_trip(
+		&self,
+		mut stream: TcpStream,
+		addr: &I2pSocketAddr,
+		method: &str,
+		head: &str,
+		body: &[u8],
+	) -> Result<Option<(ResponseHead, Vec<u8>)>> {
+		let written = async {
+			stream.write_all(head.as_bytes()).await?;
+			stream.write_all(body).await?;
+			stream.flush().await
+		};
+		if let Err(err) = written.await {
+			debug!("writing request to {} failed: {}", addr, err);
+			return Ok(None);
+		}
+
+		let mut reader = BufReader::new(stream);
+		let res = match read_response(&mut reader, method, self.max_body_size).await? {
+			Some(res) => res,
+			None => return Ok(None),
+		};
+		if res.keep_alive && reader.buffer().is_empty() {
+			self.checkin(addr, reader.into_inner());
+		}
+		Ok(Some((res.head, res.body)))
+	}
+
+	fn checkout(&self, addr: &I2pSocketAddr) -> Option<TcpStream> {
+		self.idle
+			.lock()
+			.ok()?
+			.get_mut(addr)
+			.and_then(|streams| streams.pop())
+	}
+
+	fn checkin(&self, addr: &I2pSocketAddr, stream: TcpStream) {
+		if let Ok(mut idle) = self.idle.lock() {
+			let streams = idle.entry(addr.clone()).or_default();
+			if streams.len() < self.max_idle_per_host {
+				streams.push(stream);
+			}
+		}
+	}
+}
+
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<String>> {
+	let mut line = String::new();
+	(&mut *reader)
+		.take(MAX_LINE_LEN as u64)
+		.read_line(&mut line)
+		.await?;
+	end_line(line)
+}
+
+/// The async counterpart of [read_response](super::read_response).
+async fn read_response<R: AsyncBufRead + Unpin>(
+	reader: &mut R,
+	method: &str,
+	max_body: u64,
+) -> Result<Option<ReadResponse>> {
+	let mut response = ResponseReader::new(method, max_body);
+	while let Some(next) = response.next() {
+		match next {
+			Next::Line => match read_line(reader).await {
+				Ok(line) => response.line(line)?,
+				Err(err) if response.unanswered(&err) => return Ok(None),
+				Err(err) => return Err(err.into()),
+			},
+			Next::Bytes(len) => {
+				let mut data = Vec::new();
+				(&mut *reader).take(len).read_to_end(&mut data).await?;
+				response.data(&data)?;
+			}
+			Next::ToEnd(max) => {
+				let mut data = Vec::new();
+				(&mut *reader).take(max).read_to_end(&mut data).await?;
+				response.data(&data)?;
+			}
+		}
+	}
+	Ok(response.finish())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn chunked_body() {
+		let mut reader = BufReader::new(
+			&b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n"[..],
+		);
+		let res = read_response(&mut reader, "GET", DEFAULT_MAX_BODY_SIZE)
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(res.head.status, 200);
+		assert_eq!(res.body, b"abc");
+		assert!(res.keep_alive);
+		assert!(read_response(&mut reader, "GET", DEFAULT_MAX_BODY_SIZE)
+			.await
+			.unwrap()
+			.is_none());
+	}
+}
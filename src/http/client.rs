@@ -0,0 +1,348 @@
+use crate::error::Result;
+use log::debug;
+use std::collections::HashMap;
+use std::io::{BufReader, Write};
+use std::sync::Mutex;
+
+use super::{
+	is_credential, is_idempotent, read_response, redirect_method, write_request_head, Response,
+	ResponseHead, Url, DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_REDIRECTS,
+};
+use crate::error::I2PError;
+use crate::net::{I2pSocketAddr, I2pStream};
+use crate::sam::Session;
+
+/// Default number of idle connections kept open per destination.
+pub const DEFAULT_MAX_IDLE_PER_HOST: usize = 2;
+
+/// A blocking HTTP/1.1 client for eepsites.
+///
+/// All connections are opened through the same SAM session, and idle
+/// keep-alive connections are reused for later requests to the same
+/// destination.
+///
+/// # Examples
+///
+/// ```no_run
+/// use i2p::http::HttpClient;
+/// use i2p::Session;
+///
+/// let session = Session::transient(i2p::sam::DEFAULT_API).unwrap();
+/// let client = HttpClient::new(session);
+/// let res = client.get("http://example.i2p/").unwrap();
+/// println!("{} {}", res.status, res.text());
+/// ```
+pub struct HttpClient {
+	session: Session,
+	idle: Mutex<HashMap<I2pSocketAddr, Vec<I2pStream>>>,
+	max_idle_per_host: usize,
+	max_redirects: usize,
+	max_body_size: u64,
+}
+
+impl HttpClient {
+	/// Creates a client sending all requests through `session`.
+	pub fn new(session: Session) -> HttpClient {
+		HttpClient {
+			session,
+			idle: Mutex::new(HashMap::new()),
+			max_idle_per_host: DEFAULT_MAX_IDLE_PER_HOST,
+			max_redirects: DEFAULT_MAX_REDIRECTS,
+			max_body_size: DEFAULT_MAX_BODY_SIZE,
+		}
+	}
+
+	/// Creates a client using a duplicate handle of an existing session.
+	pub fn with_session(session: &Session) -> Result<HttpClient> {
+		Ok(HttpClient::new(session.duplicate()?))
+	}
+
+	/// Sets the maximum number of redirects followed for a single request.
+	/// Zero disables following redirects.
+	pub fn max_redirects(mut self, max: usize) -> HttpClient {
+		self.max_redirects = max;
+		self
+	}
+
+	/// Sets the maximum number of idle connections kept open per destination.
+	/// Zero disables connection reuse.
+	pub fn max_idle_per_host(mut self, max: usize) -> HttpClient {
+		self.max_idle_per_host = max;
+		self
+	}
+
+	/// Sets the maximum size of a response body, [DEFAULT_MAX_BODY_SIZE] by
+	/// default. Larger responses fail with
+	/// [I2PError::HttpMalformedResponse].
+	pub fn max_body_size(mut self, max: u64) -> HttpClient {
+		self.max_body_size = max;
+		self
+	}
+
+	pub fn session(&self) -> &Session {
+		&self.session
+	}
+
+	/// Sends a `GET` request.
+	pub fn get(&self, url: &str) -> Result<Response> {
+		self.request("GET", url, &[], &[])
+	}
+
+	/// Sends a `POST` request with the given body.
+	pub fn post(&self, url: &str, content_type: &str, body: &[u8]) -> Result<Response> {
+		self.request("POST", url, &[("Content-Type", content_type)], body)
+	}
+
+	/// Sends a request and reads the full response, following redirects
+	/// within I2P. Credentials in `headers`, such as `Authorization` or
+	/// `Cookie`, are dropped when a redirect leads to another host.
+	pub fn request(
+		&self,
+		method: &str,
+		url: &str,
+		headers: &[(&str, &str)],
+		body: &[u8],
+	) -> Result<Response> {
+		let mut url = Url::parse(url)?;
+		let mut method = method.to_string();
+		let mut headers = headers.to_vec();
+		let mut body = body;
+		for _ in 0..=self.max_redirects {
+			let (head, res_body) = self.send(&method, &url, &headers, body)?;
+			if let (true, Some(location)) = (head.is_redirect(), head.header("Location")) {
+				debug!("{} {} redirected to {}", head.status, url, location);
+				let next_url = url.join(location)?;
+				if next_url.host() != url.host() {
+					headers.retain(|(name, _)| !is_credential(name));
+				}
+				url = next_url;
+				let next = redirect_method(head.status, &method);
+				if next != method {
+					method = next.to_string();
+					body = &[];
+				}
+				continue;
+			}
+			return Ok(Response {
+				status: head.status,
+				reason: head.reason,
+				headers: head.headers,
+				body: res_body,
+				url,
+			});
+		}
+		Err(I2PError::HttpTooManyRedirects(self.max_redirects))
+	}
+
+	fn send(
+		&self,
+		method: &str,
+		url: &Url,
+		headers: &[(&str, &str)],
+		body: &[u8],
+	) -> Result<(ResponseHead, Vec<u8>)> {
+		let addr = url.socket_addr();
+		let head = write_request_head(method, url, headers, body.len());
+
+		// idle connections may have been closed by the peer in the meantime, in
+		// which case we move on to the next one. Only requests the peer never
+		// saw or answered are retried, and only if repeating them is harmless.
+		if is_idempotent(method) {
+			while let Some(stream) = self.checkout(&addr) {
+				match self.round_trip(stream, &addr, method, &head, body)? {
+					Some(res) => return Ok(res),
+					None => debug!("idle connection to {} was closed", addr),
+				}
+			}
+		}
+
+		let stream = I2pStream::connect_with_session(&self.session, &addr)?;
+		self.round_trip(stream, &addr, method, &head, body)?
+			.ok_or_else(|| {
+				I2PError::HttpMalformedResponse("connection closed without a response".into())
+			})
+	}
+
+	/// Writes the request and reads the response. Returns `None` if the
+	/// request could not be written, or the peer closed the connection
+	/// before answering it.
+	fn round_trip(
+		&self,
+		mut stream: I2pStream,
+		addr: &I2pSocketAddr,
+		method: &str,
+		head: &str,
+		body: &[u8],
+	) -> Result<Option<(ResponseHead, Vec<u8>)>> {
+		let written = stream
+			.write_all(head.as_bytes())
+			.and_then(|_| stream.write_all(body))
+			.and_then(|_| stream.flush());
+		if let Err(err) = written {
+			debug!("writing request to {} failed: {}", addr, err);
+			return Ok(None);
+		}
+
+		let mut reader = BufReader::new(stream);
+		let res = match read_response(&mut reader, method, self.max_body_size)? {
+			Some(res) => res,
+			None => return Ok(None),
+		};
+		if res.keep_alive && reader.buffer().is_empty() {
+			self.checkin(addr, reader.into_inner());
+		}
+		Ok(Some((res.head, res.body)))
+	}
+
+	fn checkout(&self, addr: &I2pSocketAddr) -> Option<I2pStream> {
+		self.idle
+			.lock()
+			.ok()?
+			.get_mut(addr)
+			.and_then(|streams| streams.pop())
+	}
+
+	fn checkin(&self, addr: &I2pSocketAddr, stream: I2pStream) {
+		if let Ok(mut idle) = self.idle.lock() {
+			let streams = idle.entry(addr.clone()).or_default();
+			if streams.len() < self.max_idle_per_host {
+				streams.push(stream);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock_bridge::MockBridge;
+	use crate::net::I2pListener;
+	use std::io::{BufRead, Read};
+	use std::thread;
+
+	/// Answers the first request on each connection and closes the
+	/// connection on the second one without answering.
+	fn one_shot_server(session: &Session) {
+		let listener = I2pListener::bind_with_session(session).unwrap();
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let mut reader = BufReader::new(stream.unwrap());
+				thread::spawn(move || {
+					for answered in [true, false] {
+						let mut len = 0;
+						loop {
+							let mut line = String::new();
+							if reader.read_line(&mut line).unwrap_or(0) == 0 {
+								return;
+							}
+							match line.trim_end().split_once(": ") {
+								Some(("Content-Length", value)) => len = value.parse().unwrap(),
+								None if line.trim_end().is_empty() => break,
+								_ => {}
+							}
+						}
+						let mut body = vec![0; len];
+						reader.read_exact(&mut body).unwrap();
+						if answered {
+							let res = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+							reader.get_mut().write_all(res).unwrap();
+						}
+					}
+				});
+			}
+		});
+	}
+
+	#[test]
+	fn retries_unanswered_idempotent_requests() {
+		let bridge = MockBridge::start();
+		let session = Session::transient(bridge.addr()).unwrap();
+		one_shot_server(&session);
+		let client = HttpClient::with_session(&session).unwrap();
+
+		assert_eq!(client.get("http://site.i2p/").unwrap().text(), "ok");
+		assert_eq!(bridge.commands("STREAM CONNECT").len(), 1);
+
+		// the idle connection is closed without an answer, so the GET is
+		// repeated on a new one
+		assert_eq!(client.get("http://site.i2p/").unwrap().text(), "ok");
+		assert_eq!(bridge.commands("STREAM CONNECT").len(), 2);
+
+		// a POST never goes over an idle connection, as it could not be
+		// repeated if the peer closed it
+		let res = client
+			.post("http://site.i2p/", "text/plain", b"data")
+			.unwrap();
+		assert_eq!(res.text(), "ok");
+		assert_eq!(bridge.commands("STREAM CONNECT").len(), 3);
+	}
+
+	#[test]
+	fn redirects_drop_credentials() {
+		let bridge = MockBridge::start();
+		let session = Session::transient(bridge.addr()).unwrap();
+		let listener = I2pListener::bind_with_session(&session).unwrap();
+		// every host is the same server, which redirects "/" to the path
+		// and tells which credentials it got on the others
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let mut reader = BufReader::new(stream.unwrap());
+				thread::spawn(move || loop {
+					let mut path = String::new();
+					let mut credentials = Vec::new();
+					loop {
+						let mut line = String::new();
+						if reader.read_line(&mut line).unwrap_or(0) == 0 {
+							return;
+						}
+						match line.trim_end().split_once(": ") {
+							Some((name, _)) if is_credential(name) => {
+								credentials.push(name.to_string())
+							}
+							None if line.trim_end().is_empty() => break,
+							None if path.is_empty() => {
+								path = line.split(' ').nth(1).unwrap().to_string()
+							}
+							_ => {}
+						}
+					}
+					let res = match path.split_once("/to/") {
+						Some((_, to)) => format!(
+							"HTTP/1.1 302 Found\r\nLocation: {to}\r\nContent-Length: 0\r\n\r\n"
+						),
+						None => {
+							let body = credentials.join(",");
+							format!(
+								"HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+								body.len()
+							)
+						}
+					};
+					reader.get_mut().write_all(res.as_bytes()).unwrap();
+				});
+			}
+		});
+		let client = HttpClient::with_session(&session).unwrap();
+		let headers = [("Authorization", "Basic eDp5"), ("Cookie", "a=b")];
+
+		let same_host = client
+			.request(
+				"GET",
+				"http://site.i2p/to/http://site.i2p/next",
+				&headers,
+				&[],
+			)
+			.unwrap();
+		assert_eq!(same_host.text(), "Authorization,Cookie");
+		let other_host = client
+			.request(
+				"GET",
+				"http://site.i2p/to/http://other.i2p/next",
+				&headers,
+				&[],
+			)
+			.unwrap();
+		assert_eq!(other_host.url.host(), "other.i2p");
+		assert_eq!(other_host.text(), "");
+	}
+}
@@ -0,0 +1,70 @@
+use crate::error::{I2PError, Result};
+use hyper::Uri;
+use hyper_util::rt::TokioIo;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::net::TcpStream;
+use tower_service::Service;
+
+use super::Url;
+use crate::net::I2pStream;
+use crate::sam::Session;
+
+/// A hyper connector opening [I2pStream]s through a shared SAM session.
+///
+/// Only `http://` URIs with `.i2p` hosts are accepted.
+///
+/// # Examples
+///
+/// ```no_run
+/// use hyper_util::client::legacy::Client;
+/// use hyper_util::rt::TokioExecutor;
+/// use i2p::http::I2pConnector;
+/// use i2p::Session;
+///
+/// let session = Session::transient(i2p::sam::DEFAULT_API).unwrap();
+/// let client: Client<_, String> =
+///     Client::builder(TokioExecutor::new()).build(I2pConnector::new(session));
+/// ```
+#[derive(Clone)]
+pub struct I2pConnector {
+	session: Arc<Session>,
+}
+
+impl I2pConnector {
+	pub fn new(session: Session) -> I2pConnector {
+		I2pConnector {
+			session: Arc::new(session),
+		}
+	}
+
+	/// Creates a connector using a duplicate handle of an existing session.
+	pub fn with_session(session: &Session) -> Result<I2pConnector> {
+		Ok(I2pConnector::new(session.duplicate()?))
+	}
+}
+
+impl Service<Uri> for I2pConnector {
+	type Response = TokioIo<TcpStream>;
+	type Error = I2PError;
+	type Future = Pin<Box<dyn Future<Output = Result<Self::Response>> + Send>>;
+
+	fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, uri: Uri) -> Self::Future {
+		let session = self.session.clone();
+		Box::pin(async move {
+			let addr = Url::parse(&uri.to_string())?.socket_addr();
+			let stream = tokio::task::spawn_blocking(move || {
+				let mut stream = I2pStream::connect_with_session(&session, &addr)?;
+				stream.to_tokio_stream()
+			})
+			.await??;
+			Ok(TokioIo::new(stream))
+		})
+	}
+}
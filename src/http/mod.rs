@@ -0,0 +1,746 @@
+//! A small HTTP/1.1 client for eepsites, built on top of [I2pStream].
+//!
+//! Requests are sent over streams opened from a single, shared SAM [Session].
+//! Connections are kept alive and reused per destination, chunked transfer
+//! encoding is decoded transparently, and redirects are followed as long as
+//! they stay within I2P.
+//!
+//! A blocking client is provided by [HttpClient], an async one by
+//! [AsyncHttpClient] when the `tokio` feature is enabled, and [I2pConnector]
+//! can be plugged into hyper's client when the `hyper` feature is enabled.
+//!
+//! The [server] module goes the other way, serving HTTP over an
+//! [I2pListener](crate::net::I2pListener), and [proxy] provides a local HTTP
+//! proxy for browsers.
+//!
+//! [I2pStream]: crate::net::I2pStream
+//! [Session]: crate::Session
+
+use crate::error::Result;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+use crate::error::I2PError;
+use crate::net::{I2pAddr, I2pSocketAddr};
+
+pub use self::client::HttpClient;
+
+#[cfg(feature = "tokio")]
+pub use self::async_client::AsyncHttpClient;
+
+#[cfg(feature = "hyper")]
+pub use self::connector::I2pConnector;
+
+mod client;
+pub mod proxy;
+pub mod server;
+
+#[cfg(feature = "tokio")]
+mod async_client;
+
+#[cfg(feature = "hyper")]
+mod connector;
+
+/// Default number of redirects followed before giving up.
+pub const DEFAULT_MAX_REDIRECTS: usize = 5;
+
+/// Upper bound on the number of lines in a request or response head.
+pub const MAX_HEAD_LINES: usize = 256;
+
+/// Upper bound on the length of a single head line, or chunk size line.
+pub const MAX_LINE_LEN: usize = 8 * 1024;
+
+/// Default upper bound on the size of a response body.
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 32 * 1024 * 1024;
+
+/// An `http://` URL pointing at an I2P host.
+///
+/// # Examples
+///
+/// ```
+/// use i2p::http::Url;
+///
+/// let url = Url::parse("http://example.i2p:8080/index.html?a=b").unwrap();
+/// assert_eq!(url.host(), "example.i2p");
+/// assert_eq!(url.port(), 8080);
+/// assert_eq!(url.path(), "/index.html?a=b");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Url {
+	host: String,
+	port: u16,
+	path: String,
+}
+
+impl Url {
+	/// Parses an absolute URL. The scheme may be omitted, in which case
+	/// `http` is assumed. Only hosts ending in `.i2p` are accepted.
+	pub fn parse(url: &str) -> Result<Url> {
+		let invalid = || I2PError::HttpInvalidUrl(url.to_string());
+		let rest = match url.split_once("://") {
+			Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+			Some(_) => return Err(invalid()),
+			None => url,
+		};
+		let (authority, path) = match rest.find(['/', '?']) {
+			Some(idx) => (&rest[..idx], &rest[idx..]),
+			None => (rest, "/"),
+		};
+		let (host, port) = match authority.rsplit_once(':') {
+			Some((host, port)) => (host, port.parse::<u16>().map_err(|_| invalid())?),
+			None => (authority, 80),
+		};
+		if !host.to_ascii_lowercase().ends_with(".i2p") || host.len() <= ".i2p".len() {
+			return Err(invalid());
+		}
+		let path = if path.starts_with('?') {
+			format!("/{path}")
+		} else {
+			path.to_string()
+		};
+		Ok(Url {
+			host: host.to_ascii_lowercase(),
+			port,
+			path,
+		})
+	}
+
+	/// Resolves a `Location` header value against this URL.
+	pub fn join(&self, location: &str) -> Result<Url> {
+		if location.contains("://") {
+			return Url::parse(location);
+		}
+		if let Some(rest) = location.strip_prefix("//") {
+			return Url::parse(rest);
+		}
+		let path = if location.starts_with('/') {
+			location.to_string()
+		} else {
+			let base = self.path.split('?').next().unwrap_or("/");
+			let dir = &base[..base.rfind('/').map(|i| i + 1).unwrap_or(0)];
+			format!("{dir}{location}")
+		};
+		Ok(Url {
+			host: self.host.clone(),
+			port: self.port,
+			path,
+		})
+	}
+
+	pub fn host(&self) -> &str {
+		&self.host
+	}
+
+	pub fn port(&self) -> u16 {
+		self.port
+	}
+
+	/// Returns the path and query, always starting with `/`.
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+
+	/// Returns the value to send in the `Host` header.
+	pub fn host_header(&self) -> String {
+		if self.port == 80 {
+			self.host.clone()
+		} else {
+			format!("{}:{}", self.host, self.port)
+		}
+	}
+
+	/// Returns the I2P socket address requests to this URL are sent to.
+	pub fn socket_addr(&self) -> I2pSocketAddr {
+		I2pSocketAddr::new(I2pAddr::new(&self.host), self.port)
+	}
+}
+
+impl fmt::Display for Url {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "http://{}{}", self.host_header(), self.path)
+	}
+}
+
+/// A complete HTTP response, with the body already read and de-chunked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+	pub status: u16,
+	pub reason: String,
+	pub headers: Vec<(String, String)>,
+	pub body: Vec<u8>,
+	/// The URL the response was finally served from, after redirects.
+	pub url: Url,
+}
+
+impl Response {
+	/// Returns the first header matching `name`, compared case-insensitively.
+	pub fn header(&self, name: &str) -> Option<&str> {
+		find_header(&self.headers, name)
+	}
+
+	/// Returns the body decoded as UTF-8, replacing invalid sequences.
+	pub fn text(&self) -> String {
+		String::from_utf8_lossy(&self.body).into_owned()
+	}
+}
+
+/// The status line and headers of a response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ResponseHead {
+	pub minor_version: u8,
+	pub status: u16,
+	pub reason: String,
+	pub headers: Vec<(String, String)>,
+}
+
+/// How the length of a response body is determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BodyKind {
+	Empty,
+	Length(u64),
+	Chunked,
+	/// Delimited by the peer closing the connection.
+	Close,
+}
+
+impl ResponseHead {
+	pub fn header(&self, name: &str) -> Option<&str> {
+		find_header(&self.headers, name)
+	}
+
+	/// Determines the body framing as described in RFC 7230 section 3.3.3.
+	pub fn body_kind(&self, method: &str) -> Result<BodyKind> {
+		if method.eq_ignore_ascii_case("HEAD")
+			|| (100..200).contains(&self.status)
+			|| self.status == 204
+			|| self.status == 304
+		{
+			return Ok(BodyKind::Empty);
+		}
+		if let Some(te) = self.header("Transfer-Encoding") {
+			if te
+				.rsplit(',')
+				.next()
+				.map(|t| t.trim().eq_ignore_ascii_case("chunked"))
+				.unwrap_or(false)
+			{
+				return Ok(BodyKind::Chunked);
+			}
+			return Ok(BodyKind::Close);
+		}
+		match self.header("Content-Length") {
+			Some(len) => len
+				.trim()
+				.parse::<u64>()
+				.map(BodyKind::Length)
+				.map_err(|_| I2PError::HttpMalformedResponse(format!("bad Content-Length {len}"))),
+			None => Ok(BodyKind::Close),
+		}
+	}
+
+	/// Whether the connection may be reused after this response is read.
+	pub fn keep_alive(&self) -> bool {
+		match self.header("Connection") {
+			Some(conn) if has_token(conn, "close") => false,
+			Some(conn) if has_token(conn, "keep-alive") => true,
+			_ => self.minor_version >= 1,
+		}
+	}
+
+	pub fn is_redirect(&self) -> bool {
+		matches!(self.status, 301 | 302 | 303 | 307 | 308)
+	}
+}
+
+fn has_token(value: &str, token: &str) -> bool {
+	value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+	headers
+		.iter()
+		.find(|(k, _)| k.eq_ignore_ascii_case(name))
+		.map(|(_, v)| v.as_str())
+}
+
+/// Serializes a request head. `Host`, `Connection` and `Content-Length` are
+/// always set by the client, so any caller-provided copies are dropped.
+pub(crate) fn write_request_head(
+	method: &str,
+	url: &Url,
+	headers: &[(&str, &str)],
+	body_len: usize,
+) -> String {
+	let mut head = format!(
+		"{method} {path} HTTP/1.1\r\nHost: {host}\r\nConnection: keep-alive\r\n",
+		path = url.path(),
+		host = url.host_header(),
+	);
+	for (name, value) in headers {
+		if ["host", "connection", "content-length"]
+			.iter()
+			.any(|h| name.eq_ignore_ascii_case(h))
+		{
+			continue;
+		}
+		head.push_str(&format!("{name}: {value}\r\n"));
+	}
+	if body_len > 0 || !["GET", "HEAD"].iter().any(|m| method.eq_ignore_ascii_case(m)) {
+		head.push_str(&format!("Content-Length: {body_len}\r\n"));
+	}
+	head.push_str("\r\n");
+	head
+}
+
+/// Parses the status line and headers, given the raw lines of the head
+/// without the terminating empty line.
+pub(crate) fn parse_response_head(lines: &[String]) -> Result<ResponseHead> {
+	let malformed = |what: &str| I2PError::HttpMalformedResponse(what.to_string());
+	let status_line = lines.first().ok_or_else(|| malformed("empty response"))?;
+	let mut parts = status_line.splitn(3, ' ');
+	let version = parts.next().unwrap_or("");
+	let minor_version = match version {
+		"HTTP/1.1" => 1,
+		"HTTP/1.0" => 0,
+		_ => return Err(malformed(status_line)),
+	};
+	let status = parts
+		.next()
+		.and_then(|s| s.parse::<u16>().ok())
+		.ok_or_else(|| malformed(status_line))?;
+	let reason = parts.next().unwrap_or("").to_string();
+
+	let mut headers = Vec::with_capacity(lines.len() - 1);
+	for line in &lines[1..] {
+		let (name, value) = line.split_once(':').ok_or_else(|| malformed(line))?;
+		headers.push((name.trim().to_string(), value.trim().to_string()));
+	}
+	Ok(ResponseHead {
+		minor_version,
+		status,
+		reason,
+		headers,
+	})
+}
+
+/// Reads a single CRLF (or bare LF) terminated line, without the terminator.
+/// Returns `None` if the peer closed the connection before sending anything.
+/// Lines over [MAX_LINE_LEN] are an error.
+pub(crate) fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+	let mut line = String::new();
+	reader.take(MAX_LINE_LEN as u64).read_line(&mut line)?;
+	end_line(line)
+}
+
+/// Strips the terminator from a line read up to and including `\n`, and at
+/// most [MAX_LINE_LEN] bytes long. Returns `None` for an empty read, and an
+/// error if the line was cut off or is too long.
+pub(crate) fn end_line(mut line: String) -> io::Result<Option<String>> {
+	if line.is_empty() {
+		return Ok(None);
+	}
+	if !line.ends_with('\n') {
+		if line.len() >= MAX_LINE_LEN {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+		}
+		return Err(io::ErrorKind::UnexpectedEof.into());
+	}
+	let trimmed = line.trim_end_matches(['\r', '\n']).len();
+	line.truncate(trimmed);
+	Ok(Some(line))
+}
+
+pub(crate) fn parse_chunk_size(line: &str) -> Result<u64> {
+	let size = line.split(';').next().unwrap_or("").trim();
+	u64::from_str_radix(size, 16)
+		.map_err(|_| I2PError::HttpMalformedResponse(format!("bad chunk size {line}")))
+}
+
+/// What a client has to read next to make progress on a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Next {
+	/// A single line, passed to [ResponseReader::line].
+	Line,
+	/// Exactly this many bytes, passed to [ResponseReader::data].
+	Bytes(u64),
+	/// Everything until the peer closes the connection, reading at most this
+	/// many bytes.
+	ToEnd(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReadState {
+	Head,
+	Body(u64),
+	ChunkSize,
+	Chunk(u64),
+	ChunkEnd,
+	Trailers,
+	ToEnd,
+	Done,
+}
+
+/// A fully read response.
+pub(crate) struct ReadResponse {
+	pub head: ResponseHead,
+	pub body: Vec<u8>,
+	/// Whether the connection may be reused for another request.
+	pub keep_alive: bool,
+}
+
+/// Parses a response from the lines and bytes a client reads off its
+/// connection, so that the blocking and async clients only differ in how
+/// they read. Interim `1xx` responses are skipped. Heads over
+/// [MAX_HEAD_LINES] lines and bodies over `max_body` bytes are an error.
+pub(crate) struct ResponseReader<'a> {
+	method: &'a str,
+	max_body: u64,
+	state: ReadState,
+	lines: Vec<String>,
+	head: Option<(ResponseHead, BodyKind)>,
+	body: Vec<u8>,
+	answered: bool,
+}
+
+impl<'a> ResponseReader<'a> {
+	pub fn new(method: &'a str, max_body: u64) -> ResponseReader<'a> {
+		ResponseReader {
+			method,
+			max_body,
+			state: ReadState::Head,
+			lines: Vec::new(),
+			head: None,
+			body: Vec::new(),
+			answered: false,
+		}
+	}
+
+	/// Returns what to read next, or `None` once the response is complete.
+	pub fn next(&self) -> Option<Next> {
+		match self.state {
+			ReadState::Head | ReadState::ChunkSize | ReadState::ChunkEnd | ReadState::Trailers => {
+				Some(Next::Line)
+			}
+			ReadState::Body(len) | ReadState::Chunk(len) => Some(Next::Bytes(len)),
+			// one byte over the limit tells a body that is too large
+			ReadState::ToEnd => Some(Next::ToEnd(self.remaining().saturating_add(1))),
+			ReadState::Done => None,
+		}
+	}
+
+	/// Whether a read error leaves the request unanswered: the peer closed or
+	/// reset the connection before sending a single line.
+	pub fn unanswered(&self, err: &io::Error) -> bool {
+		!self.answered
+			&& matches!(
+				err.kind(),
+				io::ErrorKind::ConnectionReset
+					| io::ErrorKind::ConnectionAborted
+					| io::ErrorKind::BrokenPipe
+					| io::ErrorKind::UnexpectedEof
+			)
+	}
+
+	/// Feeds a line read for [Next::Line], `None` if the connection closed.
+	pub fn line(&mut self, line: Option<String>) -> Result<()> {
+		let eof = || I2PError::from(io::Error::from(io::ErrorKind::UnexpectedEof));
+		match (self.state, line) {
+			(ReadState::Head, None) if !self.answered => self.state = ReadState::Done,
+			(ReadState::Head, Some(line)) if line.is_empty() => {
+				let head = parse_response_head(&self.lines)?;
+				self.lines.clear();
+				if (100..200).contains(&head.status) && head.status != 101 {
+					return Ok(());
+				}
+				let kind = head.body_kind(self.method)?;
+				self.state = match kind {
+					BodyKind::Empty | BodyKind::Length(0) => ReadState::Done,
+					BodyKind::Length(len) if len > self.max_body => return Err(self.too_large()),
+					BodyKind::Length(len) => ReadState::Body(len),
+					BodyKind::Chunked => ReadState::ChunkSize,
+					BodyKind::Close => ReadState::ToEnd,
+				};
+				self.head = Some((head, kind));
+			}
+			(ReadState::Head, Some(_)) if self.lines.len() >= MAX_HEAD_LINES => {
+				return Err(I2PError::HttpMalformedResponse(
+					"too many header lines".into(),
+				))
+			}
+			(ReadState::Head, Some(line)) => {
+				self.answered = true;
+				self.lines.push(line);
+			}
+			(ReadState::ChunkSize, Some(line)) => {
+				self.state = match parse_chunk_size(&line)? {
+					0 => ReadState::Trailers,
+					size if size > self.remaining() => return Err(self.too_large()),
+					size => ReadState::Chunk(size),
+				};
+			}
+			(ReadState::ChunkEnd, Some(line)) if line.is_empty() => {
+				self.state = ReadState::ChunkSize
+			}
+			(ReadState::ChunkEnd, Some(_)) => {
+				return Err(I2PError::HttpMalformedResponse(
+					"missing chunk terminator".into(),
+				))
+			}
+			(ReadState::Trailers, Some(line)) if line.is_empty() => self.state = ReadState::Done,
+			(ReadState::Trailers, Some(_)) => {}
+			(_, _) => return Err(eof()),
+		}
+		Ok(())
+	}
+
+	/// Feeds the bytes read for [Next::Bytes] or [Next::ToEnd].
+	pub fn data(&mut self, data: &[u8]) -> Result<()> {
+		match self.state {
+			ReadState::Body(len) | ReadState::Chunk(len) if (data.len() as u64) < len => {
+				return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+			}
+			_ if data.len() as u64 > self.remaining() => return Err(self.too_large()),
+			ReadState::Chunk(_) => self.state = ReadState::ChunkEnd,
+			_ => self.state = ReadState::Done,
+		}
+		self.body.extend_from_slice(data);
+		Ok(())
+	}
+
+	/// How many more body bytes are allowed.
+	fn remaining(&self) -> u64 {
+		self.max_body.saturating_sub(self.body.len() as u64)
+	}
+
+	fn too_large(&self) -> I2PError {
+		I2PError::HttpMalformedResponse(format!("body over {} bytes", self.max_body))
+	}
+
+	/// Returns the response, or `None` if the peer closed the connection
+	/// without answering.
+	pub fn finish(self) -> Option<ReadResponse> {
+		let (head, kind) = self.head?;
+		Some(ReadResponse {
+			keep_alive: kind != BodyKind::Close && head.keep_alive(),
+			head,
+			body: self.body,
+		})
+	}
+}
+
+/// Reads a response to a `method` request, with a body of at most
+/// `max_body` bytes. Returns `None` if the peer closed or reset the
+/// connection before answering.
+pub(crate) fn read_response<R: BufRead>(
+	reader: &mut R,
+	method: &str,
+	max_body: u64,
+) -> Result<Option<ReadResponse>> {
+	let mut response = ResponseReader::new(method, max_body);
+	while let Some(next) = response.next() {
+		match next {
+			Next::Line => match read_line(reader) {
+				Ok(line) => response.line(line)?,
+				Err(err) if response.unanswered(&err) => return Ok(None),
+				Err(err) => return Err(err.into()),
+			},
+			Next::Bytes(len) => {
+				let mut data = Vec::new();
+				reader.take(len).read_to_end(&mut data)?;
+				response.data(&data)?;
+			}
+			Next::ToEnd(max) => {
+				let mut data = Vec::new();
+				reader.take(max).read_to_end(&mut data)?;
+				response.data(&data)?;
+			}
+		}
+	}
+	Ok(response.finish())
+}
+
+/// Whether repeating a `method` request has the same effect as sending it
+/// once, which makes it safe to retry on another connection.
+pub(crate) fn is_idempotent(method: &str) -> bool {
+	["GET", "HEAD", "PUT", "DELETE", "OPTIONS", "TRACE"]
+		.iter()
+		.any(|m| method.eq_ignore_ascii_case(m))
+}
+
+/// Whether the request header `name` carries credentials, which aren't sent
+/// along when a redirect leads to another host.
+pub(crate) fn is_credential(name: &str) -> bool {
+	["Authorization", "Proxy-Authorization", "Cookie"]
+		.iter()
+		.any(|h| name.eq_ignore_ascii_case(h))
+}
+
+/// Returns the method to use when following a redirect with `status`.
+pub(crate) fn redirect_method(status: u16, method: &str) -> &str {
+	match status {
+		303 if !method.eq_ignore_ascii_case("HEAD") => "GET",
+		301 | 302 if method.eq_ignore_ascii_case("POST") => "GET",
+		_ => method,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+
+	#[test]
+	fn parse_url() {
+		let url = Url::parse("http://Example.i2p/foo/bar?x=1").unwrap();
+		assert_eq!(url.host(), "example.i2p");
+		assert_eq!(url.port(), 80);
+		assert_eq!(url.path(), "/foo/bar?x=1");
+		assert_eq!(url.to_string(), "http://example.i2p/foo/bar?x=1");
+
+		let url = Url::parse("example.i2p:8080").unwrap();
+		assert_eq!(url.port(), 8080);
+		assert_eq!(url.path(), "/");
+		assert_eq!(url.host_header(), "example.i2p:8080");
+
+		assert_eq!(Url::parse("example.i2p?a").unwrap().path(), "/?a");
+		assert!(Url::parse("https://example.i2p/").is_err());
+		assert!(Url::parse("http://example.com/").is_err());
+		assert!(Url::parse("http://.i2p/").is_err());
+		assert!(Url::parse("http://example.i2p:port/").is_err());
+	}
+
+	#[test]
+	fn join_url() {
+		let base = Url::parse("http://example.i2p/a/b?q").unwrap();
+		assert_eq!(base.join("/c").unwrap().path(), "/c");
+		assert_eq!(base.join("c?d").unwrap().path(), "/a/c?d");
+		assert_eq!(
+			base.join("http://other.i2p/x").unwrap(),
+			Url::parse("other.i2p/x").unwrap()
+		);
+		assert_eq!(base.join("//other.i2p").unwrap().host(), "other.i2p");
+		assert!(base.join("http://example.com/").is_err());
+	}
+
+	#[test]
+	fn request_head() {
+		let url = Url::parse("example.i2p/index.html").unwrap();
+		assert_eq!(
+			write_request_head("GET", &url, &[("Accept", "*/*"), ("Host", "evil")], 0),
+			"GET /index.html HTTP/1.1\r\nHost: example.i2p\r\nConnection: keep-alive\r\nAccept: */*\r\n\r\n"
+		);
+		assert!(write_request_head("POST", &url, &[], 0).contains("Content-Length: 0\r\n"));
+	}
+
+	#[test]
+	fn response_head() {
+		let mut reader = Cursor::new(
+			"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length:  5 \r\n\r\nhello"
+				.as_bytes(),
+		);
+		let res = read_response(&mut reader, "GET", DEFAULT_MAX_BODY_SIZE)
+			.unwrap()
+			.unwrap();
+		assert_eq!(res.head.status, 200);
+		assert_eq!(res.head.reason, "OK");
+		assert_eq!(res.head.header("content-type"), Some("text/plain"));
+		assert_eq!(res.head.body_kind("GET").unwrap(), BodyKind::Length(5));
+		assert_eq!(res.head.body_kind("HEAD").unwrap(), BodyKind::Empty);
+		assert!(res.keep_alive);
+		assert_eq!(res.body, b"hello");
+
+		let head = parse_response_head(&["HTTP/1.0 302 Found".to_string()]).unwrap();
+		assert!(head.is_redirect());
+		assert!(!head.keep_alive());
+		assert_eq!(head.body_kind("GET").unwrap(), BodyKind::Close);
+
+		assert!(parse_response_head(&["SSH-2.0".to_string()]).is_err());
+		assert!(parse_response_head(&["HTTP/1.1 200 OK".to_string(), "bogus".to_string()]).is_err());
+	}
+
+	#[test]
+	fn unanswered_response() {
+		assert!(
+			read_response(&mut Cursor::new(&b""[..]), "GET", DEFAULT_MAX_BODY_SIZE)
+				.unwrap()
+				.is_none()
+		);
+
+		let reader = ResponseReader::new("GET", DEFAULT_MAX_BODY_SIZE);
+		assert!(reader.unanswered(&io::ErrorKind::ConnectionReset.into()));
+		assert!(!reader.unanswered(&io::ErrorKind::TimedOut.into()));
+
+		// once the peer started answering, closing is an error
+		let mut cut = Cursor::new(&b"HTTP/1.1 200 OK\r\n"[..]);
+		assert!(read_response(&mut cut, "GET", DEFAULT_MAX_BODY_SIZE).is_err());
+		let mut cut = Cursor::new(&b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhel"[..]);
+		assert!(read_response(&mut cut, "GET", DEFAULT_MAX_BODY_SIZE).is_err());
+	}
+
+	#[test]
+	fn chunked_body() {
+		let mut reader = Cursor::new(
+			"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+			 4\r\nWiki\r\n5;ext=1\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\nX-Trailer: 1\r\n\r\nNEXT"
+				.as_bytes(),
+		);
+		let res = read_response(&mut reader, "GET", DEFAULT_MAX_BODY_SIZE)
+			.unwrap()
+			.unwrap();
+		assert_eq!(res.body, b"Wikipedia in\r\n\r\nchunks.");
+		assert!(res.keep_alive);
+		let mut rest = String::new();
+		reader.read_to_string(&mut rest).unwrap();
+		assert_eq!(rest, "NEXT");
+
+		let mut truncated = Cursor::new(
+			"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\na\r\nshort".as_bytes(),
+		);
+		assert!(read_response(&mut truncated, "GET", DEFAULT_MAX_BODY_SIZE).is_err());
+		assert!(parse_chunk_size("zz").is_err());
+	}
+
+	#[test]
+	fn response_limits() {
+		let read =
+			|res: &str, max_body| read_response(&mut Cursor::new(res.as_bytes()), "GET", max_body);
+		let long = format!(
+			"HTTP/1.1 200 OK\r\nX-Long: {}\r\n\r\n",
+			"a".repeat(MAX_LINE_LEN)
+		);
+		assert!(read(&long, 10).is_err());
+		let many = format!(
+			"HTTP/1.1 200 OK\r\n{}\r\n",
+			"X-A: 1\r\n".repeat(MAX_HEAD_LINES)
+		);
+		assert!(read(&many, 10).is_err());
+
+		let sized = "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world";
+		assert!(read(sized, 10).is_err());
+		assert_eq!(read(sized, 11).unwrap().unwrap().body, b"hello world");
+		let chunked = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n";
+		assert!(read(chunked, 10).is_err());
+		assert!(read(chunked, 11).is_ok());
+		let to_end = "HTTP/1.0 200 OK\r\n\r\nhello world";
+		assert!(read(to_end, 10).is_err());
+		assert_eq!(read(to_end, 11).unwrap().unwrap().body, b"hello world");
+	}
+
+	#[test]
+	fn idempotent_methods() {
+		assert!(is_idempotent("GET"));
+		assert!(is_idempotent("put"));
+		assert!(!is_idempotent("POST"));
+		assert!(!is_idempotent("PATCH"));
+	}
+
+	#[test]
+	fn redirects() {
+		assert!(is_credential("cookie"));
+		assert!(!is_credential("Accept"));
+		assert_eq!(redirect_method(303, "POST"), "GET");
+		assert_eq!(redirect_method(302, "POST"), "GET");
+		assert_eq!(redirect_method(307, "POST"), "POST");
+		assert_eq!(redirect_method(303, "HEAD"), "HEAD");
+	}
+}
@@ -0,0 +1,464 @@
+//! A local HTTP proxy for `.i2p` hosts.
+//!
+//! [HttpProxy] accepts both plain proxy requests (`GET http://foo.i2p/ HTTP/1.1`)
+//! and `CONNECT foo.i2p:443` tunnels. Plain requests have their absolute URI
+//! rewritten to origin form and identifying headers removed before being
+//! relayed over an [I2pStream]. Each client connection carries a single
+//! request.
+//!
+//! Links produced by jump services carry the destination of the host in an
+//! `i2paddresshelper` query parameter. The proxy stores it in its
+//! [AddressBook] and strips it from the forwarded request. A helper never
+//! replaces a host already in the address book, or in the router's: if it
+//! points elsewhere, the request is refused.
+//!
+//! [I2pStream]: crate::net::I2pStream
+
+use crate::error::Result;
+use log::{debug, error, info};
+use std::io::{BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+
+use super::{read_line, Url, MAX_HEAD_LINES};
+use crate::error::I2PError;
+use crate::net::{AddressBook, I2pStream};
+use crate::sam::{SamConnection, Session};
+use crate::session_watcher::Backoff;
+use crate::utils::relay;
+
+/// Query parameter used by jump services to hand out destinations.
+pub const ADDRESS_HELPER_PARAM: &str = "i2paddresshelper";
+
+/// Request headers never forwarded into I2P.
+const STRIPPED_HEADERS: &[&str] = &[
+	"user-agent",
+	"referer",
+	"x-forwarded-for",
+	"x-forwarded-host",
+	"x-forwarded-proto",
+	"forwarded",
+	"via",
+	"from",
+	"proxy-connection",
+	"proxy-authorization",
+	"connection",
+	"keep-alive",
+	"host",
+];
+
+/// An HTTP proxy server bridging into I2P.
+///
+/// # Examples
+///
+/// ```no_run
+/// use i2p::http::proxy::HttpProxy;
+/// use i2p::Session;
+///
+/// let session = Session::transient(i2p::sam::DEFAULT_API).unwrap();
+/// let proxy = HttpProxy::bind("127.0.0.1:4444", session).unwrap();
+/// proxy.run().unwrap();
+/// ```
+pub struct HttpProxy {
+	listener: TcpListener,
+	session: Arc<Session>,
+	address_book: Arc<AddressBook>,
+}
+
+impl HttpProxy {
+	/// Binds the proxy to a local address. Streams are opened through
+	/// `session`.
+	pub fn bind<A: ToSocketAddrs>(addr: A, session: Session) -> Result<HttpProxy> {
+		Ok(HttpProxy {
+			listener: TcpListener::bind(addr)?,
+			session: Arc::new(session),
+			address_book: Arc::new(AddressBook::new()),
+		})
+	}
+
+	/// Uses `address_book` for lookups and address helpers, e.g. to share it
+	/// with a [SocksProxy](crate::socks::SocksProxy).
+	pub fn address_book(mut self, address_book: Arc<AddressBook>) -> HttpProxy {
+		self.address_book = address_book;
+		self
+	}
+
+	pub fn local_addr(&self) -> Result<SocketAddr> {
+		self.listener.local_addr().map_err(|e| e.into())
+	}
+
+	/// Accepts clients forever, serving each one on its own thread. Failed
+	/// accepts are logged and tried again after a growing delay.
+	pub fn run(&self) -> Result<()> {
+		let backoff = Backoff::accept();
+		let mut failures = 0;
+		for client in self.listener.incoming() {
+			let client = match client {
+				Ok(client) => client,
+				Err(err) => {
+					failures += 1;
+					error!("http proxy accept failed: {}", err);
+					thread::sleep(backoff.delay(failures));
+					continue;
+				}
+			};
+			failures = 0;
+			let session = self.session.clone();
+			let address_book = self.address_book.clone();
+			thread::spawn(move || {
+				let peer = client.peer_addr();
+				if let Err(err) = handle_client(&session, &address_book, client) {
+					error!("http proxy client {:?} failed: {:#}", peer, err);
+				}
+			});
+		}
+		Ok(())
+	}
+}
+
+/// A parsed proxy request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyRequest {
+	/// A `CONNECT` tunnel to the given URL's host and port.
+	Connect(Url),
+	/// A plain request, with the head to send to the eepsite.
+	Forward {
+		url: Url,
+		head: String,
+		/// A destination handed out through `i2paddresshelper`.
+		address_helper: Option<String>,
+	},
+}
+
+/// Serves a single proxy client until either side closes.
+pub fn handle_client(
+	session: &Session,
+	address_book: &AddressBook,
+	client: TcpStream,
+) -> Result<()> {
+	let mut reader = BufReader::new(client.try_clone()?);
+	let mut lines = Vec::new();
+	loop {
+		match read_line(&mut reader)? {
+			None => return Ok(()),
+			Some(line) if line.is_empty() => break,
+			Some(_) if lines.len() >= MAX_HEAD_LINES => {
+				return respond(client, 431, "Request Header Fields Too Large")
+			}
+			Some(line) => lines.push(line),
+		}
+	}
+
+	let request = match parse_request(&lines) {
+		Ok(request) => request,
+		Err(err) => {
+			debug!("refusing proxy request: {:#}", err);
+			return match err {
+				I2PError::HttpInvalidUrl(_) => {
+					respond(client, 403, "Only .i2p hosts can be reached through this proxy")
+				}
+				_ => respond(client, 400, "Bad Request"),
+			};
+		}
+	};
+
+	let (url, head) = match request {
+		ProxyRequest::Connect(url) => (url, None),
+		ProxyRequest::Forward {
+			url,
+			head,
+			address_helper,
+		} => {
+			if let Some(dest) = address_helper {
+				// local entries take precedence over the router's, so a helper
+				// must not shadow a host only the router knows either
+				match router_lookup(session, url.host()) {
+					Ok(Some(known)) if known != dest => {
+						info!("refusing i2paddresshelper for {} known to the router", url.host());
+						return respond(
+							client,
+							409,
+							"i2paddresshelper conflicts with the router's address book entry for this host",
+						);
+					}
+					Ok(_) => {}
+					Err(err) => {
+						respond(client.try_clone()?, 502, "Bad Gateway")?;
+						return Err(err);
+					}
+				}
+				match address_book.insert_if_absent(url.host(), &dest) {
+					Ok(None) => info!("added {} to the address book", url.host()),
+					Ok(Some(known)) if known == dest => {}
+					// a link must not be able to take over a host we already know
+					Ok(Some(_)) => {
+						info!("refusing conflicting i2paddresshelper for {}", url.host());
+						return respond(
+							client,
+							409,
+							"i2paddresshelper conflicts with the address book entry for this host",
+						);
+					}
+					Err(_) => return respond(client, 400, "Invalid i2paddresshelper destination"),
+				}
+			}
+			(url, Some(head))
+		}
+	};
+	info!("proxying {}", url);
+
+	let addr = address_book.resolve(&url.socket_addr());
+	let mut stream = match I2pStream::connect_with_session(session, &addr) {
+		Ok(stream) => stream,
+		Err(err) => {
+			let (status, reason) = match err {
+				I2PError::SAMTimeout(_) => (504, "Gateway Timeout"),
+				I2PError::SAMKeyNotFound(_) => (404, "Host not found in address book"),
+				_ => (502, "Bad Gateway"),
+			};
+			respond(client.try_clone()?, status, reason)?;
+			return Err(err);
+		}
+	};
+
+	match head {
+		Some(head) => stream.write_all(head.as_bytes())?,
+		None => (&client).write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?,
+	}
+	// anything the client sent past the head, e.g. the start of a body
+	stream.write_all(reader.buffer())?;
+	relay(client, stream)?;
+	Ok(())
+}
+
+/// Looks `host` up in the router's address book, returning None if it is
+/// not there.
+fn router_lookup(session: &Session, host: &str) -> Result<Option<String>> {
+	let mut sam = SamConnection::connect(session.sam_api()?)?;
+	match sam.naming_lookup(host) {
+		Ok(dest) => Ok(Some(dest)),
+		Err(I2PError::SAMKeyNotFound(_)) => Ok(None),
+		Err(err) => Err(err),
+	}
+}
+
+/// Parses a proxy request head, given its lines without the terminating
+/// empty line.
+pub fn parse_request(lines: &[String]) -> Result<ProxyRequest> {
+	let bad_request = |msg: &str| I2PError::HttpMalformedRequest(msg.to_string());
+	let request_line = lines.first().ok_or_else(|| bad_request("empty request"))?;
+	let mut parts = request_line.split(' ');
+	let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+		(Some(m), Some(t), Some(v), None) if v.starts_with("HTTP/1.") => (m, t, v),
+		_ => return Err(bad_request(request_line)),
+	};
+
+	if method.eq_ignore_ascii_case("CONNECT") {
+		if target.contains('/') || !target.contains(':') {
+			return Err(bad_request(target));
+		}
+		return Ok(ProxyRequest::Connect(Url::parse(target)?));
+	}
+
+	if !target.contains("://") {
+		return Err(bad_request("proxy requests need an absolute URI"));
+	}
+	let (target, address_helper) = strip_address_helper(target);
+	let url = Url::parse(&target)?;
+
+	let mut head = format!(
+		"{method} {path} {version}\r\nHost: {host}\r\nConnection: close\r\n",
+		path = url.path(),
+		host = url.host_header(),
+	);
+	for line in &lines[1..] {
+		// a continuation line would survive the header it belongs to being
+		// stripped, so refuse it as RFC 9112 allows
+		if line.starts_with([' ', '\t']) {
+			return Err(bad_request("obsolete line folding"));
+		}
+		let name = line.split(':').next().unwrap_or("").trim();
+		if STRIPPED_HEADERS.iter().any(|h| name.eq_ignore_ascii_case(h)) {
+			continue;
+		}
+		head.push_str(line);
+		head.push_str("\r\n");
+	}
+	head.push_str("\r\n");
+
+	Ok(ProxyRequest::Forward {
+		url,
+		head,
+		address_helper,
+	})
+}
+
+/// Removes the `i2paddresshelper` parameter from a URL, returning the
+/// remaining URL and the parameter's value.
+pub fn strip_address_helper(url: &str) -> (String, Option<String>) {
+	let (base, query) = match url.split_once('?') {
+		Some(parts) => parts,
+		None => return (url.to_string(), None),
+	};
+	let mut helper = None;
+	let params: Vec<&str> = query
+		.split('&')
+		.filter(|param| match param.split_once('=') {
+			Some((key, value)) if key == ADDRESS_HELPER_PARAM => {
+				helper = Some(value.to_string());
+				false
+			}
+			_ => true,
+		})
+		.collect();
+	if params.is_empty() {
+		(base.to_string(), helper)
+	} else {
+		(format!("{base}?{}", params.join("&")), helper)
+	}
+}
+
+fn respond(mut client: TcpStream, status: u16, reason: &str) -> Result<()> {
+	let body = format!("{status} {reason}\n");
+	client.write_all(
+		format!(
+			"HTTP/1.1 {status} {reason}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+			body.len()
+		)
+		.as_bytes(),
+	)?;
+	client.flush()?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn lines(head: &str) -> Vec<String> {
+		head.split("\r\n").map(|l| l.to_string()).collect()
+	}
+
+	#[test]
+	fn forward_request() {
+		let req = parse_request(&lines(
+			"GET http://Example.i2p/index.html?a=1 HTTP/1.1\r\nHost: example.i2p\r\nUser-Agent: Firefox\r\nReferer: http://other.i2p/\r\nX-Forwarded-For: 10.0.0.1\r\nProxy-Connection: keep-alive\r\nAccept: */*",
+		))
+		.unwrap();
+		assert_eq!(
+			req,
+			ProxyRequest::Forward {
+				url: Url::parse("example.i2p/index.html?a=1").unwrap(),
+				head: "GET /index.html?a=1 HTTP/1.1\r\nHost: example.i2p\r\nConnection: close\r\nAccept: */*\r\n\r\n".to_string(),
+				address_helper: None,
+			}
+		);
+	}
+
+	#[test]
+	fn connect_request() {
+		assert_eq!(
+			parse_request(&lines("CONNECT example.i2p:443 HTTP/1.1\r\nHost: example.i2p:443")).unwrap(),
+			ProxyRequest::Connect(Url::parse("example.i2p:443").unwrap())
+		);
+		assert!(parse_request(&lines("CONNECT example.i2p HTTP/1.1")).is_err());
+	}
+
+	#[test]
+	fn rejected_requests() {
+		let err = parse_request(&lines("GET http://example.com/ HTTP/1.1")).unwrap_err();
+		assert!(matches!(err, I2PError::HttpInvalidUrl(_)));
+		let err = parse_request(&lines("GET / HTTP/1.1")).unwrap_err();
+		assert!(matches!(err, I2PError::HttpMalformedRequest(_)));
+		assert!(parse_request(&lines("GET http://example.i2p/")).is_err());
+		assert!(parse_request(&[]).is_err());
+		assert!(parse_request(&lines(
+			"GET http://example.i2p/ HTTP/1.1\r\nReferer: http://other.i2p/\r\n secret"
+		))
+		.is_err());
+	}
+
+	#[test]
+	fn address_helper() {
+		assert_eq!(
+			strip_address_helper("http://example.i2p/?i2paddresshelper=abc~"),
+			("http://example.i2p/".to_string(), Some("abc~".to_string()))
+		);
+		assert_eq!(
+			strip_address_helper("http://example.i2p/p?x=1&i2paddresshelper=abc&y=2"),
+			("http://example.i2p/p?x=1&y=2".to_string(), Some("abc".to_string()))
+		);
+		assert_eq!(
+			strip_address_helper("http://example.i2p/?x=1"),
+			("http://example.i2p/?x=1".to_string(), None)
+		);
+
+		match parse_request(&lines("GET http://example.i2p/?i2paddresshelper=dest HTTP/1.1")).unwrap() {
+			ProxyRequest::Forward {
+				url, address_helper, ..
+			} => {
+				assert_eq!(url.path(), "/");
+				assert_eq!(address_helper, Some("dest".to_string()));
+			}
+			req => panic!("unexpected request {req:?}"),
+		}
+	}
+
+	#[test]
+	fn address_helper_conflict() {
+		use crate::mock_bridge::MockBridge;
+		use crate::net::BASE64_I2P;
+		use std::io::Read;
+
+		let bridge = MockBridge::start();
+		let session = Session::transient(bridge.addr()).unwrap();
+		let book = AddressBook::new();
+		let known = BASE64_I2P.encode(&[0u8; 387]);
+		book.insert("example.i2p", &known).unwrap();
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (proxied, _) = listener.accept().unwrap();
+		let request = format!(
+			"GET http://example.i2p/?{ADDRESS_HELPER_PARAM}={} HTTP/1.1\r\n\r\n",
+			BASE64_I2P.encode(&[1u8; 387])
+		);
+		client.write_all(request.as_bytes()).unwrap();
+		handle_client(&session, &book, proxied).unwrap();
+
+		let mut res = String::new();
+		client.read_to_string(&mut res).unwrap();
+		assert!(res.starts_with("HTTP/1.1 409 "), "{res}");
+		assert_eq!(book.get("example.i2p"), Some(known));
+		assert!(bridge.commands("STREAM CONNECT").is_empty());
+	}
+
+	#[test]
+	fn address_helper_router_conflict() {
+		use crate::mock_bridge::MockBridge;
+		use crate::net::BASE64_I2P;
+		use std::io::Read;
+
+		// the bridge resolves example.i2p, the local book doesn't know it
+		let bridge = MockBridge::start();
+		let session = Session::transient(bridge.addr()).unwrap();
+		let book = AddressBook::new();
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (proxied, _) = listener.accept().unwrap();
+		let request = format!(
+			"GET http://example.i2p/?{ADDRESS_HELPER_PARAM}={} HTTP/1.1\r\n\r\n",
+			BASE64_I2P.encode(&[1u8; 387])
+		);
+		client.write_all(request.as_bytes()).unwrap();
+		handle_client(&session, &book, proxied).unwrap();
+
+		let mut res = String::new();
+		client.read_to_string(&mut res).unwrap();
+		assert!(res.starts_with("HTTP/1.1 409 "), "{res}");
+		assert_eq!(book.get("example.i2p"), None);
+		assert!(bridge.commands("STREAM CONNECT").is_empty());
+	}
+}
@@ -0,0 +1,630 @@
+//! Serving HTTP over an [I2pListener], in the style of Java I2P's HTTP
+//! server tunnels.
+//!
+//! Every accepted connection is wrapped in a [HeaderInjector], which rewrites
+//! the head of every request to carry the identity of the connecting peer:
+//!
+//! * `X-I2P-DestHash`: base64 of the SHA-256 hash of the peer destination
+//! * `X-I2P-DestB32`: the peer's `.b32.i2p` address
+//! * `X-I2P-DestB64`: the peer's full base64 destination
+//!
+//! Copies of these headers sent by the client are removed, so they can be
+//! trusted by the application. Request bodies are followed through their
+//! `Content-Length` or chunked encoding, so that pipelined requests are
+//! rewritten too, and requests the server could frame differently are
+//! refused. A head that cannot be rewritten, because it is malformed or the
+//! peer closes the stream before finishing it, ends the connection with an
+//! error. Requests also get `Connection: close`, so that clients open a new
+//! stream for their next request.
+//!
+//! [I2pListener]: crate::net::I2pListener
+
+use crate::error::Result;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+
+use super::{parse_chunk_size, MAX_LINE_LEN};
+use crate::error::I2PError;
+use crate::net::{B32_EXT, BASE32_I2P, BASE64_I2P};
+#[cfg(feature = "tokio")]
+use crate::sam::SessionGuard;
+
+#[cfg(feature = "tokio")]
+pub use self::listener::I2pHttpListener;
+
+/// Upper bound on the size of a request head we are willing to buffer.
+pub const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+pub const DEST_HASH_HEADER: &str = "X-I2P-DestHash";
+pub const DEST_B32_HEADER: &str = "X-I2P-DestB32";
+pub const DEST_B64_HEADER: &str = "X-I2P-DestB64";
+
+/// The identity headers describing a connecting peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DestHeaders {
+	pub dest_hash: String,
+	pub dest_b32: String,
+	pub dest_b64: String,
+}
+
+impl DestHeaders {
+	/// Derives the headers from a peer's full base64 destination.
+	pub fn from_b64(dest: &str) -> Result<DestHeaders> {
+		let bin_data = BASE64_I2P
+			.decode(dest.as_bytes())
+			.map_err(|_| I2PError::BadAddressEncoding(dest.to_string()))?;
+		let hash = Sha256::digest(bin_data);
+		Ok(DestHeaders {
+			dest_hash: BASE64_I2P.encode(&hash[..]),
+			dest_b32: format!("{}{}", BASE32_I2P.encode(&hash[..]), B32_EXT),
+			dest_b64: dest.to_string(),
+		})
+	}
+}
+
+/// Whether a header is one of ours, and has to be dropped when sent by the
+/// client. Underscores are matched as dashes, as some servers (and CGI-style
+/// environments) treat them alike.
+fn is_reserved(name: &str) -> bool {
+	let name = name.trim().replace('_', "-");
+	[
+		DEST_HASH_HEADER,
+		DEST_B32_HEADER,
+		DEST_B64_HEADER,
+		"Connection",
+	]
+	.iter()
+	.any(|h| name.eq_ignore_ascii_case(h))
+}
+
+/// Rewrites a complete request head, including its terminating empty line,
+/// and tells how the request's body follows it. Lines may end in CRLF or a
+/// bare LF.
+fn rewrite_head(head: &[u8], dest: &DestHeaders) -> io::Result<(Vec<u8>, State)> {
+	let head = std::str::from_utf8(head).map_err(|_| invalid("request head is not UTF-8"))?;
+	let mut lines = head.split('\n').map(|l| l.strip_suffix('\r').unwrap_or(l));
+	let request_line = lines.next().unwrap_or("");
+
+	let mut headers: Vec<String> = Vec::new();
+	for line in lines.filter(|l| !l.is_empty()) {
+		if line.contains('\r') {
+			return Err(invalid("bare CR in request head"));
+		}
+		// obs-fold continuation lines are unfolded, so that they are dropped
+		// along with the header they belong to
+		if line.starts_with([' ', '\t']) {
+			let header = headers
+				.last_mut()
+				.ok_or_else(|| invalid("continuation line without a header"))?;
+			header.push(' ');
+			header.push_str(line.trim_start());
+			continue;
+		}
+		headers.push(line.to_string());
+	}
+	headers.retain(|header| !is_reserved(header.split(':').next().unwrap_or("")));
+	let body = body_state(&headers)?;
+
+	let mut rewritten = format!("{request_line}\r\n");
+	for header in headers {
+		rewritten.push_str(&header);
+		rewritten.push_str("\r\n");
+	}
+	rewritten.push_str(&format!(
+		"{DEST_HASH_HEADER}: {}\r\n{DEST_B32_HEADER}: {}\r\n{DEST_B64_HEADER}: {}\r\nConnection: close\r\n\r\n",
+		dest.dest_hash, dest.dest_b32, dest.dest_b64
+	));
+	Ok((rewritten.into_bytes(), body))
+}
+
+/// Where the body of a request with `headers` ends. Requests the server
+/// could frame differently, and so read a request we did not rewrite, are
+/// refused.
+fn body_state(headers: &[String]) -> io::Result<State> {
+	let values = |name: &str| {
+		headers
+			.iter()
+			.filter_map(|header| header.split_once(':'))
+			.filter(|(n, _)| n.trim().eq_ignore_ascii_case(name))
+			.map(|(_, value)| value.trim())
+			.collect::<Vec<_>>()
+	};
+	let encodings = values("Transfer-Encoding");
+	let lengths = values("Content-Length");
+	if !encodings.is_empty() {
+		if !lengths.is_empty() {
+			return Err(invalid("both Transfer-Encoding and Content-Length"));
+		}
+		let last = encodings.last().and_then(|v| v.rsplit(',').next());
+		if !last.is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked")) {
+			return Err(invalid("request body not chunked"));
+		}
+		return Ok(State::ChunkSize);
+	}
+	let mut len = None;
+	for value in lengths {
+		let value = value
+			.parse::<u64>()
+			.map_err(|_| invalid("bad Content-Length"))?;
+		if len.is_some_and(|len| len != value) {
+			return Err(invalid("conflicting Content-Length"));
+		}
+		len = Some(value);
+	}
+	Ok(match len {
+		Some(len) if len > 0 => State::Body(len),
+		_ => State::Head,
+	})
+}
+
+fn invalid(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Finds the end of the request head, which lenient parsers also accept
+/// with bare LF line endings.
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+	(0..buf.len())
+		.find(|&i| buf[i] == b'\n' && (buf[..i].ends_with(b"\n") || buf[..i].ends_with(b"\n\r")))
+		.map(|i| i + 1)
+}
+
+/// Where the injector is in the request it reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+	/// Buffering a request head.
+	Head,
+	/// Within a body of this many more bytes.
+	Body(u64),
+	/// Expecting the size line of a chunk.
+	ChunkSize,
+	/// Within a chunk of this many more bytes.
+	Chunk(u64),
+	/// Expecting the line break ending a chunk.
+	ChunkEnd,
+	/// Expecting trailer lines, up to an empty one.
+	Trailers,
+	/// The peer closed the connection.
+	Closed,
+}
+
+/// Wraps a server-side stream, injecting the peer's identity headers into
+/// every request read from it. Writes go straight through.
+pub struct HeaderInjector<S> {
+	inner: S,
+	dest: DestHeaders,
+	state: State,
+	/// Bytes read from the peer and not processed yet.
+	input: Vec<u8>,
+	/// Processed bytes, handed out from `pos` on.
+	output: Vec<u8>,
+	pos: usize,
+	/// Keeps the session of an accepted stream up.
+	#[cfg(feature = "tokio")]
+	session: Option<SessionGuard>,
+}
+
+impl<S> HeaderInjector<S> {
+	pub fn new(inner: S, dest: DestHeaders) -> HeaderInjector<S> {
+		HeaderInjector {
+			inner,
+			dest,
+			state: State::Head,
+			input: Vec::new(),
+			output: Vec::new(),
+			pos: 0,
+			#[cfg(feature = "tokio")]
+			session: None,
+		}
+	}
+
+	pub fn dest(&self) -> &DestHeaders {
+		&self.dest
+	}
+
+	pub fn get_ref(&self) -> &S {
+		&self.inner
+	}
+
+	pub fn into_inner(self) -> S {
+		self.inner
+	}
+
+	/// Whether more has to be read from the peer before anything can be
+	/// handed out.
+	fn needs_input(&self) -> bool {
+		self.pos == self.output.len() && self.state != State::Closed
+	}
+
+	/// Feeds bytes read from the peer. An empty `data` means the peer closed
+	/// the connection.
+	fn feed(&mut self, data: &[u8]) -> io::Result<()> {
+		self.input.extend_from_slice(data);
+		while self.step()? {}
+		if data.is_empty() {
+			// never hand over a head we could not rewrite
+			if self.state == State::Head && !self.input.is_empty() {
+				return Err(io::Error::new(
+					io::ErrorKind::UnexpectedEof,
+					"connection closed within the request head",
+				));
+			}
+			// a cut off body is left to the server
+			self.output.append(&mut self.input);
+			self.state = State::Closed;
+		}
+		Ok(())
+	}
+
+	/// Processes as much of the input as possible, returning whether it
+	/// made progress.
+	fn step(&mut self) -> io::Result<bool> {
+		match self.state {
+			State::Head => {
+				// servers skip empty lines before the request line, so must we
+				let start = self
+					.input
+					.iter()
+					.position(|b| !matches!(b, b'\r' | b'\n'))
+					.unwrap_or(self.input.len());
+				self.input.drain(..start);
+				let Some(end) = find_head_end(&self.input) else {
+					if self.input.len() > MAX_HEAD_SIZE {
+						return Err(invalid("request head too large"));
+					}
+					return Ok(false);
+				};
+				let (head, body) = rewrite_head(&self.input[..end], &self.dest)?;
+				self.output.extend_from_slice(&head);
+				self.input.drain(..end);
+				self.state = body;
+			}
+			State::Body(len) | State::Chunk(len) => {
+				if self.input.is_empty() {
+					return Ok(false);
+				}
+				let n = len.min(self.input.len() as u64);
+				self.output.extend(self.input.drain(..n as usize));
+				self.state = match self.state {
+					State::Body(_) if n == len => State::Head,
+					State::Body(_) => State::Body(len - n),
+					_ if n == len => State::ChunkEnd,
+					_ => State::Chunk(len - n),
+				};
+			}
+			State::ChunkSize | State::ChunkEnd | State::Trailers => {
+				let Some(end) = self.input.iter().position(|b| *b == b'\n') else {
+					if self.input.len() > MAX_LINE_LEN {
+						return Err(invalid("chunk line too long"));
+					}
+					return Ok(false);
+				};
+				let line: Vec<u8> = self.input.drain(..=end).collect();
+				let text = std::str::from_utf8(&line)
+					.map_err(|_| invalid("chunk line is not UTF-8"))?
+					.trim_end_matches(['\r', '\n']);
+				let (next, keep) = match self.state {
+					State::ChunkSize => match parse_chunk_size(text) {
+						Ok(0) => (State::Trailers, true),
+						Ok(size) => (State::Chunk(size), true),
+						Err(_) => return Err(invalid("bad chunk size")),
+					},
+					State::ChunkEnd if text.is_empty() => (State::ChunkSize, true),
+					State::ChunkEnd => return Err(invalid("missing chunk terminator")),
+					_ if text.is_empty() => (State::Head, true),
+					// our headers can't be sent as trailers either
+					_ => (
+						State::Trailers,
+						!is_reserved(text.split(':').next().unwrap_or("")),
+					),
+				};
+				if keep {
+					self.output.extend_from_slice(&line);
+				}
+				self.state = next;
+			}
+			State::Closed => return Ok(false),
+		}
+		Ok(true)
+	}
+
+	/// Copies processed bytes into `out`.
+	fn take_output(&mut self, out: &mut [u8]) -> usize {
+		let n = out.len().min(self.output.len() - self.pos);
+		out[..n].copy_from_slice(&self.output[self.pos..self.pos + n]);
+		self.pos += n;
+		if self.pos == self.output.len() {
+			self.output.clear();
+			self.pos = 0;
+		}
+		n
+	}
+}
+
+impl<S: Read> Read for HeaderInjector<S> {
+	fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+		let mut chunk = [0u8; 4096];
+		while !out.is_empty() && self.needs_input() {
+			let n = self.inner.read(&mut chunk)?;
+			self.feed(&chunk[..n])?;
+		}
+		Ok(self.take_output(out))
+	}
+}
+
+impl<S: Write> Write for HeaderInjector<S> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.inner.write(buf)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+#[cfg(feature = "tokio")]
+mod listener {
+	use crate::error::{I2PError, Result};
+	use std::io;
+	use std::pin::Pin;
+	use std::sync::Arc;
+	use std::task::{ready, Context, Poll};
+	use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+	use tokio::net::TcpStream;
+
+	use super::{DestHeaders, HeaderInjector};
+	use crate::net::{I2pListener, I2pSocketAddr};
+
+	/// An async HTTP listener over an [I2pListener].
+	///
+	/// The yielded streams can be driven by hyper's `serve_connection`, and
+	/// with the `axum` feature the listener can be passed straight to
+	/// `axum::serve`. Each stream carries the `X-I2P-Dest*` headers of its
+	/// peer, see the [module documentation](super).
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::http::server::I2pHttpListener;
+	/// use i2p::net::I2pListener;
+	///
+	/// # async fn run() -> i2p::error::Result<()> {
+	/// let listener = I2pHttpListener::new(I2pListener::bind()?);
+	/// loop {
+	///     let (stream, peer) = listener.accept().await?;
+	///     println!("request from {}", peer);
+	///     // hand `stream` to hyper, e.g. wrapped in hyper_util::rt::TokioIo
+	/// }
+	/// # }
+	/// ```
+	pub struct I2pHttpListener {
+		listener: Arc<I2pListener>,
+	}
+
+	impl I2pHttpListener {
+		pub fn new(listener: I2pListener) -> I2pHttpListener {
+			I2pHttpListener {
+				listener: Arc::new(listener),
+			}
+		}
+
+		pub fn local_addr(&self) -> Result<I2pSocketAddr> {
+			self.listener.local_addr()
+		}
+
+		/// Accepts the next connection, returning it wrapped in a
+		/// [HeaderInjector] together with the peer's address.
+		pub async fn accept(&self) -> Result<(HeaderInjector<TcpStream>, I2pSocketAddr)> {
+			let listener = self.listener.clone();
+			let (stream, addr, dest, session) = tokio::task::spawn_blocking(move || {
+				let (mut stream, addr) = listener.accept()?;
+				let dest = DestHeaders::from_b64(&stream.inner.peer_dest)?;
+				let session = stream.session_guard();
+				Ok::<_, I2PError>((stream.to_tokio_stream()?, addr, dest, session))
+			})
+			.await??;
+			let mut stream = HeaderInjector::new(stream, dest);
+			// the tokio stream alone doesn't keep the session up
+			stream.session = Some(session);
+			Ok((stream, addr))
+		}
+	}
+
+	impl<S: AsyncRead + Unpin> AsyncRead for HeaderInjector<S> {
+		fn poll_read(
+			self: Pin<&mut Self>,
+			cx: &mut Context<'_>,
+			out: &mut ReadBuf<'_>,
+		) -> Poll<io::Result<()>> {
+			let this = self.get_mut();
+			while out.remaining() > 0 && this.needs_input() {
+				let mut chunk = [0u8; 4096];
+				let mut chunk_buf = ReadBuf::new(&mut chunk);
+				ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+				this.feed(chunk_buf.filled())?;
+			}
+			let n = this.take_output(out.initialize_unfilled());
+			out.advance(n);
+			Poll::Ready(Ok(()))
+		}
+	}
+
+	impl<S: AsyncWrite + Unpin> AsyncWrite for HeaderInjector<S> {
+		fn poll_write(
+			self: Pin<&mut Self>,
+			cx: &mut Context<'_>,
+			buf: &[u8],
+		) -> Poll<io::Result<usize>> {
+			Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+		}
+		fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+			Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+		}
+		fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+			Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+		}
+	}
+
+	#[cfg(feature = "axum")]
+	impl axum::serve::Listener for I2pHttpListener {
+		type Io = HeaderInjector<TcpStream>;
+		type Addr = I2pSocketAddr;
+
+		async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+			use log::error;
+			use std::time::Duration;
+
+			loop {
+				match I2pHttpListener::accept(self).await {
+					Ok(res) => return res,
+					Err(err) => {
+						error!("accept failed, retrying: {:#}", err);
+						tokio::time::sleep(Duration::from_secs(1)).await;
+					}
+				}
+			}
+		}
+
+		fn local_addr(&self) -> io::Result<Self::Addr> {
+			I2pHttpListener::local_addr(self).map_err(io::Error::from)
+		}
+	}
+
+	#[cfg(feature = "axum")]
+	impl axum::extract::connect_info::Connected<axum::serve::IncomingStream<'_, I2pHttpListener>>
+		for I2pSocketAddr
+	{
+		fn connect_info(stream: axum::serve::IncomingStream<'_, I2pHttpListener>) -> Self {
+			stream.remote_addr().clone()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+
+	fn dest() -> DestHeaders {
+		DestHeaders {
+			dest_hash: "hash".to_string(),
+			dest_b32: "peer.b32.i2p".to_string(),
+			dest_b64: "b64".to_string(),
+		}
+	}
+
+	#[test]
+	fn dest_headers() {
+		let b64 = BASE64_I2P.encode(&[7u8; 391]);
+		let headers = DestHeaders::from_b64(&b64).unwrap();
+		assert_eq!(headers.dest_b64, b64);
+		assert_eq!(headers.dest_hash.len(), 44);
+		assert!(headers.dest_b32.ends_with(".b32.i2p"));
+		assert_eq!(
+			crate::net::I2pAddr::from_b64(&b64).unwrap().string(),
+			headers.dest_b32
+		);
+		assert!(DestHeaders::from_b64("not base64!").is_err());
+	}
+
+	#[test]
+	fn injects_headers() {
+		let request = "POST / HTTP/1.1\r\nHost: foo.i2p\r\nX-I2P-DestB32: spoofed\r\nContent-Length: 4\r\nConnection: keep-alive\r\n\r\nbody";
+		let mut stream = HeaderInjector::new(Cursor::new(request.as_bytes()), dest());
+		let mut out = String::new();
+		stream.read_to_string(&mut out).unwrap();
+		assert_eq!(
+			out,
+			"POST / HTTP/1.1\r\nHost: foo.i2p\r\nContent-Length: 4\r\nX-I2P-DestHash: hash\r\nX-I2P-DestB32: peer.b32.i2p\r\nX-I2P-DestB64: b64\r\nConnection: close\r\n\r\nbody"
+		);
+	}
+
+	#[test]
+	fn truncated_head() {
+		let mut stream = HeaderInjector::new(Cursor::new(&b"GET / HT"[..]), dest());
+		assert!(stream.read_to_end(&mut Vec::new()).is_err());
+
+		// a half-closed LF-only head must not slip through either
+		let spoof = "GET / HTTP/1.1\nX-I2P-DestB32: admin.b32.i2p\n";
+		let mut stream = HeaderInjector::new(Cursor::new(spoof.as_bytes()), dest());
+		assert!(stream.read_to_end(&mut Vec::new()).is_err());
+
+		let mut stream = HeaderInjector::new(Cursor::new(&b""[..]), dest());
+		assert_eq!(stream.read_to_end(&mut Vec::new()).unwrap(), 0);
+
+		let huge = vec![b'a'; MAX_HEAD_SIZE + 1];
+		let mut stream = HeaderInjector::new(Cursor::new(huge), dest());
+		assert!(stream.read_to_end(&mut Vec::new()).is_err());
+	}
+
+	fn rewrite(request: &str) -> io::Result<String> {
+		let mut stream = HeaderInjector::new(Cursor::new(request.as_bytes()), dest());
+		let mut out = String::new();
+		stream.read_to_string(&mut out)?;
+		Ok(out)
+	}
+
+	#[test]
+	fn spoofing_attempts() {
+		let expected = "GET / HTTP/1.1\r\nHost: foo.i2p\r\nX-I2P-DestHash: hash\r\nX-I2P-DestB32: peer.b32.i2p\r\nX-I2P-DestB64: b64\r\nConnection: close\r\n\r\n";
+
+		// bare LF line endings
+		let out =
+			rewrite("GET / HTTP/1.1\nHost: foo.i2p\nX-I2P-DestB32: admin.b32.i2p\n\n").unwrap();
+		assert_eq!(out, expected);
+
+		// underscore variants
+		let out = rewrite(
+			"GET / HTTP/1.1\r\nX_I2P_DestB32: admin\r\nHost: foo.i2p\r\nx-i2p_desthash: x\r\n\r\n",
+		)
+		.unwrap();
+		assert_eq!(out, expected);
+
+		// continuation lines of a dropped header
+		let out =
+			rewrite("GET / HTTP/1.1\r\nX-I2P-DestB32:\r\n admin.b32.i2p\r\nHost: foo.i2p\r\n\r\n")
+				.unwrap();
+		assert_eq!(out, expected);
+		assert!(rewrite("GET / HTTP/1.1\r\n X-I2P-DestB32: admin\r\n\r\n").is_err());
+
+		// empty lines before the request line
+		let out = rewrite("\r\n\nGET / HTTP/1.1\r\nHost: foo.i2p\r\nX-I2P-DestB32: admin\r\n\r\n")
+			.unwrap();
+		assert_eq!(out, expected);
+
+		// bare CR separating headers
+		assert!(rewrite("GET / HTTP/1.1\r\nHost: foo.i2p\rX-I2P-DestB32: admin\r\n\r\n").is_err());
+	}
+
+	#[test]
+	fn pipelined_requests() {
+		let injected = "X-I2P-DestHash: hash\r\nX-I2P-DestB32: peer.b32.i2p\r\nX-I2P-DestB64: b64\r\nConnection: close\r\n\r\n";
+		let out = rewrite(
+			"POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET /b HTTP/1.1\r\nX-I2P-DestB32: admin\r\n\r\n",
+		)
+		.unwrap();
+		assert_eq!(
+			out,
+			format!("POST /a HTTP/1.1\r\nContent-Length: 5\r\n{injected}helloGET /b HTTP/1.1\r\n{injected}")
+		);
+
+		// chunks, and trailers, are followed too
+		let out = rewrite(
+			"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nGET\r\n0\r\nX-I2P-DestB32: admin\r\nX-T: 1\r\n\r\nGET /b HTTP/1.1\r\n\r\n",
+		)
+		.unwrap();
+		assert_eq!(
+			out,
+			format!("POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n{injected}3\r\nGET\r\n0\r\nX-T: 1\r\n\r\nGET /b HTTP/1.1\r\n{injected}")
+		);
+
+		// ambiguous bodies
+		assert!(rewrite(
+			"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n"
+		)
+		.is_err());
+		assert!(
+			rewrite("POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\n").is_err()
+		);
+		assert!(rewrite("POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n").is_err());
+	}
+}
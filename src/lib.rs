@@ -1,12 +1,20 @@
 pub mod error;
+#[cfg(feature = "http")]
+pub mod http;
 pub mod net;
 pub mod sam;
 pub mod sam_options;
 pub mod session_watcher;
+pub mod shutdown;
+pub mod socks;
+pub mod tunnel;
 
+#[cfg(test)]
+mod mock_bridge;
 mod parsers;
 
 pub mod utils;
 
 pub use crate::error::I2PError;
 pub use crate::sam::{SamConnection, Session};
+pub use crate::shutdown::ShutdownHandle;
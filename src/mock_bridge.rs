@@ -0,0 +1,289 @@
+//! A minimal in-process SAM bridge for tests.
+//!
+//! Every session shares a single destination, [MockBridge::dest], and every
+//! name resolves to it except those starting with `missing`. Sessions with
+//! an id, and names, starting with `stall` never get a reply. Sessions are
+//! destroyed, dropping their pending accepts and forwards, when their
+//! control socket is closed. Like the Java bridge, a destination starting
+//! with `exclusive` can only be used by one session at a time. Sessions
+//! with an `inbound.nickname` starting with `refused` fail with `I2P_ERROR`.
+//! Stream commands on an unknown session fail with `INVALID_ID`, and a
+//! `STREAM CONNECT` is paired with a pending `STREAM ACCEPT`, or with a
+//! registered `STREAM FORWARD` target, and the two sockets are then piped
+//! together. `SILENT=true` is honoured the way the Java bridge does.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::net::BASE64_I2P;
+use crate::utils::relay_tcp;
+
+/// How long a `STREAM CONNECT` waits for a peer before failing.
+const CONNECT_WAIT: Duration = Duration::from_secs(2);
+
+#[derive(Default)]
+struct State {
+	/// Pending `STREAM ACCEPT` sockets by session id, and whether they're
+	/// silent.
+	accepts: HashMap<String, VecDeque<(TcpStream, bool)>>,
+	/// `STREAM FORWARD` targets by session id, and whether they're silent.
+	forwards: HashMap<String, (SocketAddr, bool)>,
+	/// Every command received, in order.
+	commands: Vec<String>,
+	/// The destination of each live session by id.
+	sessions: HashMap<String, String>,
+}
+
+pub(crate) struct MockBridge {
+	addr: SocketAddr,
+	state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl MockBridge {
+	pub fn start() -> MockBridge {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let state = Arc::new((Mutex::new(State::default()), Condvar::new()));
+		let bridge_state = state.clone();
+		thread::spawn(move || {
+			for conn in listener.incoming() {
+				let state = bridge_state.clone();
+				thread::spawn(move || {
+					if let Ok(conn) = conn {
+						let _ = serve(conn, &state);
+					}
+				});
+			}
+		});
+		MockBridge { addr, state }
+	}
+
+	pub fn addr(&self) -> SocketAddr {
+		self.addr
+	}
+
+	/// The public destination shared by all sessions.
+	pub fn dest() -> String {
+		BASE64_I2P.encode(&[0u8; 387])
+	}
+
+	/// Returns the commands received so far starting with `prefix`.
+	pub fn commands(&self, prefix: &str) -> Vec<String> {
+		let state = self.state.0.lock().unwrap();
+		state
+			.commands
+			.iter()
+			.filter(|c| c.starts_with(prefix))
+			.cloned()
+			.collect()
+	}
+
+	/// Whether session `id` was created and not destroyed yet.
+	pub fn is_live(&self, id: &str) -> bool {
+		self.state.0.lock().unwrap().sessions.contains_key(id)
+	}
+
+	/// Waits until session `id` is destroyed.
+	pub fn wait_destroyed(&self, id: &str) {
+		let (lock, cvar) = &*self.state;
+		let mut state = lock.lock().unwrap();
+		while state.sessions.contains_key(id) {
+			state = cvar.wait(state).unwrap();
+		}
+	}
+
+	/// Waits until `n` `STREAM ACCEPT`s are pending on session `id`.
+	pub fn wait_accepts(&self, id: &str, n: usize) {
+		let (lock, cvar) = &*self.state;
+		let mut state = lock.lock().unwrap();
+		while state.accepts.get(id).map_or(0, |a| a.len()) < n {
+			state = cvar.wait(state).unwrap();
+		}
+	}
+}
+
+fn read_line(conn: &mut TcpStream) -> io::Result<Option<String>> {
+	let mut line = Vec::new();
+	let mut byte = [0u8];
+	loop {
+		if conn.read(&mut byte)? == 0 {
+			return Ok(None);
+		}
+		if byte[0] == b'\n' {
+			return Ok(Some(String::from_utf8_lossy(&line).into_owned()));
+		}
+		line.push(byte[0]);
+	}
+}
+
+fn args(line: &str) -> HashMap<&str, &str> {
+	line.split(' ')
+		.filter_map(|part| part.split_once('='))
+		.collect()
+}
+
+fn serve(mut conn: TcpStream, state: &(Mutex<State>, Condvar)) -> io::Result<()> {
+	let (lock, cvar) = state;
+	// sessions created on this connection
+	let mut created = Vec::new();
+	while let Some(line) = read_line(&mut conn).unwrap_or(None) {
+		lock.lock().unwrap().commands.push(line.clone());
+		let args = args(&line);
+		let id = args.get("ID").copied().unwrap_or("").to_string();
+		let silent = args.get("SILENT") == Some(&"true");
+		let words: Vec<&str> = line.split(' ').take(2).collect();
+		match words.as_slice() {
+			["HELLO", "VERSION"] => conn.write_all(b"HELLO REPLY RESULT=OK VERSION=3.3\n")?,
+			["SESSION", "CREATE"] | ["SESSION", "ADD"] if id.starts_with("stall") => {
+				return stall(conn)
+			}
+			["SESSION", "CREATE"] | ["SESSION", "ADD"] => {
+				let dest = match args.get("DESTINATION") {
+					Some(&"TRANSIENT") | None => MockBridge::dest(),
+					Some(dest) => dest.to_string(),
+				};
+				let mut state = lock.lock().unwrap();
+				if dest.starts_with("exclusive") && state.sessions.values().any(|d| *d == dest) {
+					drop(state);
+					conn.write_all(b"SESSION STATUS RESULT=DUPLICATED_DEST\n")?;
+					continue;
+				}
+				if args
+					.get("inbound.nickname")
+					.is_some_and(|n| n.starts_with("refused"))
+				{
+					drop(state);
+					conn.write_all(b"SESSION STATUS RESULT=I2P_ERROR\n")?;
+					continue;
+				}
+				state.sessions.insert(id.clone(), dest.clone());
+				created.push(id);
+				drop(state);
+				conn.write_all(
+					format!("SESSION STATUS RESULT=OK DESTINATION={dest}\n").as_bytes(),
+				)?;
+			}
+			["NAMING", "LOOKUP"] => {
+				let name = args.get("NAME").copied().unwrap_or("");
+				if name.starts_with("stall") {
+					return stall(conn);
+				} else if name.starts_with("missing") {
+					conn.write_all(b"NAMING REPLY RESULT=KEY_NOT_FOUND\n")?;
+				} else {
+					conn.write_all(
+						format!(
+							"NAMING REPLY RESULT=OK NAME={} VALUE={}\n",
+							name,
+							MockBridge::dest()
+						)
+						.as_bytes(),
+					)?;
+				}
+			}
+			["STREAM", _] if !lock.lock().unwrap().sessions.contains_key(&id) => {
+				conn.write_all(b"STREAM STATUS RESULT=INVALID_ID\n")?;
+			}
+			["STREAM", "ACCEPT"] => {
+				if !silent {
+					conn.write_all(b"STREAM STATUS RESULT=OK\n")?;
+				}
+				let mut state = lock.lock().unwrap();
+				state
+					.accepts
+					.entry(id)
+					.or_default()
+					.push_back((conn, silent));
+				cvar.notify_all();
+				return Ok(());
+			}
+			["STREAM", "FORWARD"] => {
+				let target = format!("{}:{}", args["HOST"], args["PORT"]);
+				let mut state = lock.lock().unwrap();
+				state.forwards.insert(id, (target.parse().unwrap(), silent));
+				drop(state);
+				conn.write_all(b"STREAM STATUS RESULT=OK\n")?;
+			}
+			["STREAM", "CONNECT"] => {
+				let ports = format!(
+					"FROM_PORT={} TO_PORT={}",
+					args.get("FROM_PORT").unwrap_or(&"0"),
+					args.get("TO_PORT").unwrap_or(&"0")
+				);
+				return match find_peer(&conn, lock, cvar) {
+					Some((mut peer, peer_silent)) => {
+						if !silent {
+							conn.write_all(b"STREAM STATUS RESULT=OK\n")?;
+						}
+						if !peer_silent {
+							peer.write_all(
+								format!("{} {}\n", MockBridge::dest(), ports).as_bytes(),
+							)?;
+						}
+						relay_tcp(conn, peer).map(|_| ())
+					}
+					// silent connects are just closed
+					None if silent => Ok(()),
+					None => conn.write_all(b"STREAM STATUS RESULT=CANT_REACH_PEER\n"),
+				};
+			}
+			_ => conn.write_all(b"STREAM STATUS RESULT=I2P_ERROR MESSAGE=\"unsupported\"\n")?,
+		}
+	}
+	destroy(created, lock, cvar);
+	Ok(())
+}
+
+/// Destroys sessions `ids`, closing their pending accepts.
+fn destroy(ids: Vec<String>, lock: &Mutex<State>, cvar: &Condvar) {
+	let mut state = lock.lock().unwrap();
+	for id in ids {
+		state.sessions.remove(&id);
+		state.forwards.remove(&id);
+		for (conn, _) in state.accepts.remove(&id).into_iter().flatten() {
+			let _ = conn.shutdown(Shutdown::Both);
+		}
+	}
+	cvar.notify_all();
+}
+
+/// Whether the client closed `conn`, giving up on its command.
+fn is_closed(conn: &TcpStream) -> bool {
+	conn.set_nonblocking(true).unwrap();
+	let closed = matches!(conn.peek(&mut [0u8]), Ok(0));
+	conn.set_nonblocking(false).unwrap();
+	closed
+}
+
+/// Leaves the client waiting until it closes the connection.
+fn stall(mut conn: TcpStream) -> io::Result<()> {
+	io::copy(&mut conn, &mut io::sink()).map(|_| ())
+}
+
+/// Takes a pending accept or connects to a forward target, waiting a little
+/// for one to show up while the client waits for it.
+fn find_peer(client: &TcpStream, lock: &Mutex<State>, cvar: &Condvar) -> Option<(TcpStream, bool)> {
+	let mut state = lock.lock().unwrap();
+	let mut waited = Duration::ZERO;
+	loop {
+		for accepts in state.accepts.values_mut() {
+			while let Some((conn, silent)) = accepts.pop_front() {
+				if !is_closed(&conn) {
+					return Some((conn, silent));
+				}
+			}
+		}
+		if let Some((target, silent)) = state.forwards.values().next() {
+			return TcpStream::connect(target).ok().map(|conn| (conn, *silent));
+		}
+		if waited >= CONNECT_WAIT || is_closed(client) {
+			return None;
+		}
+		let step = Duration::from_millis(50);
+		state = cvar.wait_timeout(state, step).unwrap().0;
+		waited += step;
+	}
+}
@@ -0,0 +1,203 @@
+use crate::error::Result;
+use log::error;
+use std::io;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::I2PError;
+use crate::net::{I2pListener, I2pSocketAddr, I2pStream};
+use crate::shutdown::ShutdownHandle;
+
+/// Delay before a worker issues a new `STREAM ACCEPT` after a failed one.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+type Accepted = Result<(I2pStream, I2pSocketAddr)>;
+
+/// A pool of background threads keeping several `STREAM ACCEPT`s pending on
+/// a listener's session.
+///
+/// A plain [I2pListener] only has one accept pending at a time, so bursts of
+/// incoming streams queue up at the router. Each worker of the pool waits on
+/// its own SAM connection and issues a new accept as soon as it has handed
+/// over a stream. Accepted streams are buffered up to the `backlog`; when it
+/// is full, workers stop accepting until streams are taken out of the pool.
+/// Workers exit when the listener's session is shut down, or when the pool
+/// is dropped, which cancels their pending accepts.
+///
+/// Several pending accepts on a session require SAM 3.2 or later.
+///
+/// # Examples
+///
+/// ```no_run
+/// use i2p::net::I2pListener;
+///
+/// let listener = I2pListener::bind().unwrap();
+/// let pool = listener.accept_pool(4, 16).unwrap();
+/// for stream in pool.incoming() {
+///     let stream = stream.unwrap();
+///     // ...
+/// }
+/// ```
+pub struct AcceptPool {
+	receiver: Mutex<Receiver<Accepted>>,
+	/// Stops the workers, but not the session.
+	stop: ShutdownHandle,
+	shutdown: ShutdownHandle,
+}
+
+impl AcceptPool {
+	/// Starts `pending` workers accepting on duplicates of `listener`, with
+	/// room for `backlog` accepted streams.
+	pub fn new(listener: &I2pListener, pending: usize, backlog: usize) -> Result<AcceptPool> {
+		let (sender, receiver) = mpsc::sync_channel(backlog);
+		let stop = ShutdownHandle::new();
+		for _ in 0..pending.max(1) {
+			let listener = listener.try_clone()?;
+			let sender = sender.clone();
+			let stop = stop.clone();
+			thread::spawn(move || accept_worker(listener, sender, stop));
+		}
+		Ok(AcceptPool {
+			receiver: Mutex::new(receiver),
+			stop,
+			shutdown: listener.shutdown_handle(),
+		})
+	}
+
+	/// Takes the next accepted stream, blocking until there is one. Fails
+	/// with [I2PError::Shutdown] once the session is shut down and the
+	/// streams accepted before have been taken.
+	pub fn accept(&self) -> Result<(I2pStream, I2pSocketAddr)> {
+		let receiver = self
+			.receiver
+			.lock()
+			.map_err(|_| io::Error::other("accept pool lock poisoned"))?;
+		match receiver.recv() {
+			Ok(accepted) => accepted,
+			Err(_) if self.shutdown.is_shutdown() => Err(I2PError::Shutdown),
+			Err(_) => Err(io::Error::other("accept pool workers stopped").into()),
+		}
+	}
+
+	/// Takes an accepted stream if one is ready, without blocking.
+	pub fn try_accept(&self) -> Option<Result<(I2pStream, I2pSocketAddr)>> {
+		self.receiver.lock().ok()?.try_recv().ok()
+	}
+
+	/// Returns an iterator over the accepted streams, ending once the
+	/// session is shut down, as [I2pListener::incoming] does.
+	pub fn incoming(&self) -> impl Iterator<Item = Result<I2pStream>> + '_ {
+		std::iter::from_fn(move || match self.accept() {
+			Ok((stream, _)) => Some(Ok(stream)),
+			Err(I2PError::Shutdown) => None,
+			Err(e) => Some(Err(e)),
+		})
+	}
+}
+
+impl Drop for AcceptPool {
+	/// Cancels the pending accepts, so that later streams go to the
+	/// session's other listeners. Streams accepted but not taken are closed.
+	fn drop(&mut self) {
+		self.stop.shutdown();
+	}
+}
+
+fn accept_worker(listener: I2pListener, sender: SyncSender<Accepted>, stop: ShutdownHandle) {
+	let shutdown = listener.shutdown_handle();
+	while !stop.is_shutdown() {
+		let accepted = listener.accept_stoppable(&stop);
+		if stop.is_shutdown() {
+			return;
+		}
+		if shutdown.is_shutdown() {
+			// the session is gone, accepting again would only fail
+			if accepted.is_ok() {
+				let _ = sender.try_send(accepted);
+			}
+			return;
+		}
+		let failed = accepted.is_err();
+		if let Err(err) = &accepted {
+			error!("pooled accept failed: {:#}", err);
+		}
+		// blocks while the backlog is full
+		if sender.send(accepted).is_err() {
+			return;
+		}
+		if failed {
+			thread::sleep(ACCEPT_RETRY_DELAY);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock_bridge::MockBridge;
+	use crate::net::I2pAddr;
+	use crate::sam::Session;
+	use std::io::{Read, Write};
+	use std::thread;
+
+	#[test]
+	fn pending_accepts() {
+		let bridge = MockBridge::start();
+		let session = Session::transient(bridge.addr()).unwrap();
+		let listener = I2pListener::bind_with_session(&session).unwrap();
+		let pool = listener.accept_pool(3, 1).unwrap();
+		bridge.wait_accepts(&session.nickname, 3);
+
+		let mut clients: Vec<I2pStream> = (0..3)
+			.map(|port| {
+				let addr = I2pSocketAddr::new(I2pAddr::new("pool.i2p"), port + 1);
+				I2pStream::connect_with_session(&session, addr).unwrap()
+			})
+			.collect();
+		for (i, client) in clients.iter_mut().enumerate() {
+			client.write_all(&[i as u8]).unwrap();
+		}
+
+		let mut ports = Vec::new();
+		for stream in pool.incoming().take(3) {
+			let mut stream = stream.unwrap();
+			let mut byte = [0u8];
+			stream.read_exact(&mut byte).unwrap();
+			let port = stream.local_addr().unwrap().port();
+			assert_eq!(byte[0] as u16 + 1, port);
+			ports.push(port);
+		}
+		ports.sort();
+		assert_eq!(ports, vec![1, 2, 3]);
+		// accepts are replenished once the streams have been handed over
+		bridge.wait_accepts(&session.nickname, 2);
+		assert!(bridge.commands("STREAM ACCEPT").len() >= 5);
+		assert!(pool.try_accept().is_none());
+	}
+
+	#[test]
+	fn drop_cancels_accepts() {
+		let bridge = MockBridge::start();
+		let session = Session::transient(bridge.addr()).unwrap();
+		let listener = I2pListener::bind_with_session(&session).unwrap();
+		let pool = listener.accept_pool(2, 1).unwrap();
+		bridge.wait_accepts(&session.nickname, 2);
+		drop(pool);
+
+		// pending before this one, the pool's accepts must not get the stream
+		let accepted = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let mut byte = [0u8];
+			stream.read_exact(&mut byte).unwrap();
+			byte[0]
+		});
+		while bridge.commands("STREAM ACCEPT").len() < 3 {
+			thread::sleep(Duration::from_millis(10));
+		}
+		let mut client = I2pStream::connect_with_session(&session, "pool.i2p:80").unwrap();
+		client.write_all(&[7]).unwrap();
+		assert_eq!(accepted.join().unwrap(), 7);
+	}
+}
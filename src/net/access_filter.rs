@@ -0,0 +1,325 @@
+//! Client-side enforcement of the access list and connection limits of a
+//! session, for routers that ignore these streaming options.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::net::{I2pAddr, I2pSocketAddr};
+use crate::sam_options::{AccessList, StreamingOptions};
+
+const MINUTE: Duration = Duration::from_secs(60);
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The options the filter follows.
+pub(crate) const FILTER_OPTIONS: [&str; 9] = [
+	"i2cp.enableAccessList",
+	"i2cp.enableBlackList",
+	"i2cp.accessList",
+	"i2p.streaming.maxConnsPerMinute",
+	"i2p.streaming.maxConnsPerHour",
+	"i2p.streaming.maxConnsPerDay",
+	"i2p.streaming.maxTotalConnsPerMinute",
+	"i2p.streaming.maxTotalConnsPerHour",
+	"i2p.streaming.maxTotalConnsPerDay",
+];
+
+/// Decides which incoming connections listeners hand out, following the
+/// `i2cp.enableAccessList`, `i2cp.enableBlackList`, `i2cp.accessList` and
+/// `i2p.streaming.max*Conns*` options of their session. Duplicates of a
+/// session share the same filter, and so the same connection counts.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AccessFilter {
+	/// None if every connection is allowed.
+	inner: Option<Arc<Filter>>,
+}
+
+#[derive(Debug)]
+struct Filter {
+	policy: Policy,
+	limits: Vec<Limit>,
+	history: Mutex<History>,
+}
+
+#[derive(Debug)]
+enum Policy {
+	AllowAll,
+	AllowOnly(HashSet<I2pAddr>),
+	Deny(HashSet<I2pAddr>),
+}
+
+/// Connections allowed within a window of time, 0 for no limit.
+#[derive(Debug)]
+struct Limit {
+	window: Duration,
+	per_peer: u32,
+	total: u32,
+}
+
+/// Times connections were allowed, oldest first.
+#[derive(Debug, Default)]
+struct History {
+	total: VecDeque<Instant>,
+	peers: HashMap<I2pAddr, VecDeque<Instant>>,
+}
+
+impl AccessFilter {
+	pub(crate) fn new(options: Option<&StreamingOptions>) -> AccessFilter {
+		let Some(options) = options else {
+			return AccessFilter::default();
+		};
+		let addrs = || {
+			options
+				.access_list
+				.iter()
+				.flat_map(AccessList::addrs)
+				.map(normalize)
+				.collect()
+		};
+		// the access list takes precedence, as in the Java streaming library
+		let policy = if options.enable_access_list == Some(true) {
+			Policy::AllowOnly(addrs())
+		} else if options.enable_black_list == Some(true) {
+			Policy::Deny(addrs())
+		} else {
+			Policy::AllowAll
+		};
+		let limits: Vec<_> = [
+			(
+				MINUTE,
+				options.max_conns_per_minute,
+				options.max_total_conns_per_minute,
+			),
+			(
+				HOUR,
+				options.max_conns_per_hour,
+				options.max_total_conns_per_hour,
+			),
+			(
+				DAY,
+				options.max_conns_per_day,
+				options.max_total_conns_per_day,
+			),
+		]
+		.into_iter()
+		.map(|(window, per_peer, total)| Limit {
+			window,
+			per_peer: per_peer.unwrap_or(0),
+			total: total.unwrap_or(0),
+		})
+		.filter(|limit| limit.per_peer > 0 || limit.total > 0)
+		.collect();
+		if matches!(policy, Policy::AllowAll) && limits.is_empty() {
+			return AccessFilter::default();
+		}
+		AccessFilter {
+			inner: Some(Arc::new(Filter {
+				policy,
+				limits,
+				history: Mutex::default(),
+			})),
+		}
+	}
+
+	/// Whether to hand out a connection from `peer`, counting it against the
+	/// limits if so. Connections from unknown peers, accepted with
+	/// `SILENT=true`, are refused by an access list, and only count against
+	/// the total limits.
+	pub(crate) fn allows(&self, peer: &I2pSocketAddr) -> bool {
+		let Some(filter) = &self.inner else {
+			return true;
+		};
+		let peer = peer.dest();
+		let peer = (!peer.string().is_empty()).then(|| normalize(&peer));
+		let allowed = match (&filter.policy, &peer) {
+			(Policy::AllowAll, _) => true,
+			(Policy::AllowOnly(addrs), Some(peer)) => addrs.contains(peer),
+			(Policy::AllowOnly(_), None) => false,
+			(Policy::Deny(addrs), Some(peer)) => !addrs.contains(peer),
+			(Policy::Deny(_), None) => true,
+		};
+		allowed && filter.admit(peer)
+	}
+}
+
+impl Filter {
+	fn admit(&self, peer: Option<I2pAddr>) -> bool {
+		let Some(longest) = self.limits.iter().map(|limit| limit.window).max() else {
+			return true;
+		};
+		let mut history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+		let now = Instant::now();
+		history.prune(now, longest);
+		let History { total, peers } = &mut *history;
+		let mut times = peer.map(|peer| peers.entry(peer).or_default());
+		let within = |times: &VecDeque<Instant>, window: Duration| {
+			times
+				.iter()
+				.rev()
+				.take_while(|time| now.duration_since(**time) < window)
+				.count()
+		};
+		for limit in &self.limits {
+			let peer_full =
+				|times: &VecDeque<Instant>| within(times, limit.window) >= limit.per_peer as usize;
+			if limit.per_peer > 0 && times.as_deref().is_some_and(peer_full)
+				|| limit.total > 0 && within(total, limit.window) >= limit.total as usize
+			{
+				return false;
+			}
+		}
+		if let Some(times) = &mut times {
+			times.push_back(now);
+		}
+		total.push_back(now);
+		true
+	}
+}
+
+impl History {
+	/// Forgets the connections older than `keep`.
+	fn prune(&mut self, now: Instant, keep: Duration) {
+		let expired = |time: &Instant| now.duration_since(*time) >= keep;
+		while self.total.front().is_some_and(expired) {
+			self.total.pop_front();
+		}
+		self.peers.retain(|_, times| {
+			while times.front().is_some_and(expired) {
+				times.pop_front();
+			}
+			!times.is_empty()
+		});
+	}
+}
+
+/// B32 addresses are case insensitive.
+fn normalize(addr: &I2pAddr) -> I2pAddr {
+	I2pAddr::new(&addr.string().to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::error::I2PError;
+	use crate::mock_bridge::MockBridge;
+	use crate::net::{I2pListener, I2pStream, BASE32_I2P};
+	use crate::sam::{Session, SessionStyle};
+	use crate::sam_options::SAMOptions;
+	use std::io::Read;
+	use std::thread;
+
+	fn peer(n: u8) -> I2pSocketAddr {
+		let b32 = format!("{}.b32.i2p", BASE32_I2P.encode(&[n; 32]));
+		I2pSocketAddr::new(I2pAddr::new(&b32), 0)
+	}
+
+	#[test]
+	fn access_lists() {
+		assert!(AccessFilter::new(None).inner.is_none());
+		let list = Some(AccessList::new(vec![peer(1).dest()]));
+		let allow = AccessFilter::new(Some(&StreamingOptions {
+			enable_access_list: Some(true),
+			access_list: list.clone(),
+			..Default::default()
+		}));
+		assert!(allow.allows(&peer(1)));
+		assert!(!allow.allows(&peer(2)));
+		let upper = I2pAddr::new(&peer(1).dest().string().to_ascii_uppercase());
+		assert!(allow.allows(&I2pSocketAddr::new(upper, 0)));
+		// silent accepts don't know the peer, an access list can't admit them
+		let unknown = I2pSocketAddr::new(I2pAddr::new(""), 0);
+		assert!(!allow.allows(&unknown));
+
+		let deny = AccessFilter::new(Some(&StreamingOptions {
+			enable_black_list: Some(true),
+			access_list: list,
+			..Default::default()
+		}));
+		assert!(!deny.allows(&peer(1)));
+		assert!(deny.allows(&peer(2)));
+		assert!(deny.allows(&unknown));
+	}
+
+	#[test]
+	fn connection_limits() {
+		let filter = AccessFilter::new(Some(&StreamingOptions {
+			max_conns_per_minute: Some(2),
+			max_total_conns_per_hour: Some(3),
+			..Default::default()
+		}));
+		assert!(filter.allows(&peer(1)));
+		assert!(filter.allows(&peer(1)));
+		assert!(!filter.allows(&peer(1)));
+		assert!(filter.allows(&peer(2)));
+		assert!(!filter.allows(&peer(3)));
+		let unknown = I2pSocketAddr::new(I2pAddr::new(""), 0);
+		assert!(!filter.allows(&unknown));
+
+		let history = &filter.inner.as_ref().unwrap().history;
+		let mut history = history.lock().unwrap();
+		let later = Instant::now() + HOUR;
+		history.prune(later, HOUR);
+		assert!(history.total.is_empty() && history.peers.is_empty());
+	}
+
+	#[test]
+	fn listener_drops_rejected_streams() {
+		let bridge = MockBridge::start();
+		let accepted = |options: SAMOptions| {
+			let nickname = format!("filter-{}", rand::random::<u32>());
+			let session = Session::create(
+				bridge.addr(),
+				"TRANSIENT",
+				&nickname,
+				SessionStyle::Stream,
+				options,
+			)
+			.unwrap();
+			let listener = I2pListener::bind_with_session(&session).unwrap();
+			let accept = thread::spawn(move || {
+				let timeout = Duration::from_millis(300);
+				(0..2)
+					.map(|_| match listener.accept_timeout(timeout) {
+						Ok(_) => true,
+						Err(I2PError::SAMTimeout(_)) => false,
+						Err(e) => panic!("{:?}", e),
+					})
+					.collect::<Vec<_>>()
+			});
+			let mut clients = Vec::new();
+			for _ in 0..2 {
+				bridge.wait_accepts(&nickname, 1);
+				clients.push(I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap());
+			}
+			(accept.join().unwrap(), clients)
+		};
+		// every session of the bridge has the same destination
+		let bridge_addr = I2pAddr::from_b64(&MockBridge::dest()).unwrap();
+
+		let (accepts, mut clients) = accepted(
+			SAMOptions::builder()
+				.deny(vec![bridge_addr.clone()])
+				.build()
+				.unwrap(),
+		);
+		assert_eq!(accepts, vec![false, false]);
+		// dropped streams are closed
+		assert_eq!(clients[0].read(&mut [0u8]).unwrap(), 0);
+
+		let options = SAMOptions::builder()
+			.allow_only(vec![bridge_addr])
+			.build()
+			.unwrap();
+		assert_eq!(accepted(options).0, vec![true, true]);
+
+		let options = SAMOptions::builder()
+			.streaming_options(StreamingOptions {
+				max_conns_per_minute: Some(1),
+				..Default::default()
+			})
+			.build()
+			.unwrap();
+		assert_eq!(accepted(options).0, vec![true, false]);
+	}
+}
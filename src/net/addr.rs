@@ -178,7 +178,7 @@ impl ToI2pSocketAddrs for (I2pAddr, u16) {
 	}
 }
 
-impl<'a> ToI2pSocketAddrs for (&'a str, u16) {
+impl ToI2pSocketAddrs for (&str, u16) {
 	type Iter = vec::IntoIter<I2pSocketAddr>;
 	fn to_socket_addrs(&self) -> io::Result<vec::IntoIter<I2pSocketAddr>> {
 		let (host, port) = *self;
@@ -217,7 +217,7 @@ impl<'a> ToI2pSocketAddrs for &'a [I2pSocketAddr] {
 	}
 }
 
-impl<'a, T: ToI2pSocketAddrs + ?Sized> ToI2pSocketAddrs for &'a T {
+impl<T: ToI2pSocketAddrs + ?Sized> ToI2pSocketAddrs for &T {
 	type Iter = T::Iter;
 	fn to_socket_addrs(&self) -> io::Result<T::Iter> {
 		(**self).to_socket_addrs()
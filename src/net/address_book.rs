@@ -0,0 +1,112 @@
+use crate::error::Result;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::net::{I2pAddr, I2pSocketAddr};
+
+/// A local address book mapping hostnames to base64 destinations.
+///
+/// Entries take precedence over the router's address book, which the SAM
+/// bridge only exposes read-only through `NAMING LOOKUP`. This is where
+/// hosts learned from jump services and `i2paddresshelper` links end up.
+///
+/// # Examples
+///
+/// ```
+/// use i2p::net::{AddressBook, I2pAddr, I2pSocketAddr};
+///
+/// let book = AddressBook::new();
+/// let dest = i2p::net::BASE64_I2P.encode(&[0u8; 387]);
+/// book.insert("Example.i2p", &dest).unwrap();
+///
+/// let addr = I2pSocketAddr::new(I2pAddr::new("example.i2p"), 80);
+/// assert_eq!(book.resolve(&addr).dest(), I2pAddr::new(&dest));
+/// ```
+#[derive(Debug, Default)]
+pub struct AddressBook {
+	entries: RwLock<HashMap<String, String>>,
+}
+
+impl AddressBook {
+	pub fn new() -> AddressBook {
+		AddressBook::default()
+	}
+
+	/// Adds or replaces the destination for `host`. Hostnames are compared
+	/// case-insensitively, and the destination must be valid base64.
+	pub fn insert(&self, host: &str, dest: &str) -> Result<()> {
+		I2pAddr::from_b64(dest)?;
+		if let Ok(mut entries) = self.entries.write() {
+			entries.insert(host.to_ascii_lowercase(), dest.to_string());
+		}
+		Ok(())
+	}
+
+	/// Adds the destination for `host` unless it already has one, which is
+	/// left untouched and returned. Unlike [insert](AddressBook::insert),
+	/// this is safe to use with destinations from untrusted sources, such as
+	/// `i2paddresshelper` links.
+	pub fn insert_if_absent(&self, host: &str, dest: &str) -> Result<Option<String>> {
+		I2pAddr::from_b64(dest)?;
+		if let Ok(mut entries) = self.entries.write() {
+			match entries.entry(host.to_ascii_lowercase()) {
+				Entry::Occupied(entry) => return Ok(Some(entry.get().clone())),
+				Entry::Vacant(entry) => {
+					entry.insert(dest.to_string());
+				}
+			}
+		}
+		Ok(None)
+	}
+
+	/// Returns the destination stored for `host`, if any.
+	pub fn get(&self, host: &str) -> Option<String> {
+		self.entries
+			.read()
+			.ok()?
+			.get(&host.to_ascii_lowercase())
+			.cloned()
+	}
+
+	pub fn remove(&self, host: &str) -> Option<String> {
+		self.entries
+			.write()
+			.ok()?
+			.remove(&host.to_ascii_lowercase())
+	}
+
+	/// Replaces the host of `addr` by its destination when it is known
+	/// locally, leaving it for the SAM bridge to resolve otherwise.
+	pub fn resolve(&self, addr: &I2pSocketAddr) -> I2pSocketAddr {
+		match self.get(&addr.dest().string()) {
+			Some(dest) => I2pSocketAddr::new(I2pAddr::new(&dest), addr.port()),
+			None => addr.clone(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::net::BASE64_I2P;
+
+	#[test]
+	fn insert_if_absent() {
+		let book = AddressBook::new();
+		let known = BASE64_I2P.encode(&[0u8; 387]);
+		let other = BASE64_I2P.encode(&[1u8; 387]);
+
+		assert_eq!(book.insert_if_absent("Example.i2p", &known).unwrap(), None);
+		assert_eq!(
+			book.insert_if_absent("example.i2p", &other).unwrap(),
+			Some(known.clone())
+		);
+		assert_eq!(book.get("example.i2p"), Some(known));
+		assert!(book.insert_if_absent("bad.i2p", "not base64!").is_err());
+		assert_eq!(book.get("bad.i2p"), None);
+
+		book.insert("example.i2p", &other).unwrap();
+		assert_eq!(book.get("example.i2p"), Some(other));
+	}
+}
@@ -1,7 +1,6 @@
-use crate::error::I2PError;
+use crate::error::{I2PError, Result};
 use crate::net::{I2pSocketAddr, ToI2pSocketAddrs};
 use crate::sam::DEFAULT_API;
-use anyhow::Result;
 use std::net::{SocketAddr, ToSocketAddrs};
 
 /// Unimplemented
@@ -15,8 +14,7 @@ use std::net::{SocketAddr, ToSocketAddrs};
 ///
 /// ```no_run
 /// use i2p::net::I2pDatagramSocket;
-/// use i2p::error::I2PError;
-/// use anyhow::Result;
+/// use i2p::error::Result;
 /// fn foo() -> Result<()> {
 ///    {
 ///        let mut socket = I2pDatagramSocket::bind("127.0.0.1:34254")?;
@@ -121,7 +119,7 @@ impl I2pDatagramSocket {
 	pub fn send_to<A: ToI2pSocketAddrs>(&self, _buf: &[u8], addr: A) -> Result<usize> {
 		match addr.to_socket_addrs()?.next() {
 			Some(_addr) => unimplemented!(),
-			None => Err(I2PError::UnresolvableAddress.into()),
+			None => Err(I2PError::UnresolvableAddress),
 		}
 	}
 
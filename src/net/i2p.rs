@@ -1,4 +1,4 @@
-use anyhow::Result;
+use crate::error::Result;
 use data_encoding::{Encoding, Specification};
 use lazy_static::lazy_static;
 use log::error;
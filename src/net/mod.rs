@@ -1,18 +1,28 @@
+pub use self::accept_pool::AcceptPool;
+pub(crate) use self::access_filter::{AccessFilter, FILTER_OPTIONS};
 pub use self::addr::{I2pSocketAddr, ToI2pSocketAddrs};
+pub use self::address_book::AddressBook;
 pub use self::datagram::I2pDatagramSocket;
 pub use self::i2p::I2pAddr;
+pub use self::port_mux::PortMux;
+pub use self::stream_pool::{PooledStream, StreamPool};
 pub use self::streaming::{I2pListener, I2pStream};
 use crate::error::I2PError;
-use anyhow::Result;
+use crate::error::Result;
 use std::net::{SocketAddr, ToSocketAddrs};
 
 pub use self::i2p::B32_EXT;
 pub use self::i2p::BASE32_I2P;
 pub use self::i2p::BASE64_I2P;
 
+mod accept_pool;
+mod access_filter;
 mod addr;
+mod address_book;
 mod datagram;
 mod i2p;
+mod port_mux;
+mod stream_pool;
 mod streaming;
 #[cfg(test)]
 mod test;
@@ -34,7 +44,7 @@ where
 			}
 		}
 	}
-	Err(last_err.unwrap_or(I2PError::UnresolvableAddress.into()))
+	Err(last_err.unwrap_or(I2PError::UnresolvableAddress))
 }
 
 fn each_addr<A: ToSocketAddrs, F, T>(sam_addr: A, mut f: F) -> Result<T>
@@ -48,5 +58,5 @@ where
 			Err(e) => last_err = Some(e),
 		}
 	}
-	Err(last_err.unwrap_or(I2PError::UnresolvableAddress.into()))
+	Err(last_err.unwrap_or(I2PError::UnresolvableAddress))
 }
@@ -0,0 +1,191 @@
+use crate::error::{I2PError, Result};
+use log::{debug, error};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::net::{I2pListener, I2pSocketAddr, I2pStream};
+
+/// Pause between accepts failing back to back, e.g. while the bridge is down.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+type Handler = Arc<dyn Fn(I2pStream, I2pSocketAddr) + Send + Sync>;
+
+/// Dispatches the streams accepted by a single [I2pListener] to per-port
+/// handlers, according to the `TO_PORT` they were opened with.
+///
+/// This lets a destination host several services, the same way a TCP host
+/// does. Streams opened without a port, or to a port with no handler, go to
+/// the fallback handler if there is one and are closed otherwise. Each
+/// stream is handled on its own thread.
+///
+/// # Examples
+///
+/// ```no_run
+/// use i2p::net::{I2pListener, PortMux};
+///
+/// let listener = I2pListener::bind().unwrap();
+/// PortMux::new(listener)
+///     .route(80, |_stream, peer| println!("http client {}", peer))
+///     .route(6667, |_stream, peer| println!("irc client {}", peer))
+///     .run()
+///     .unwrap();
+/// ```
+pub struct PortMux {
+	listener: I2pListener,
+	routes: Routes,
+}
+
+impl PortMux {
+	pub fn new(listener: I2pListener) -> PortMux {
+		PortMux {
+			listener,
+			routes: Routes::default(),
+		}
+	}
+
+	/// Handles the streams opened to `port`, replacing any previous handler.
+	pub fn route<F>(mut self, port: u16, handler: F) -> PortMux
+	where
+		F: Fn(I2pStream, I2pSocketAddr) + Send + Sync + 'static,
+	{
+		self.routes.ports.insert(port, Arc::new(handler));
+		self
+	}
+
+	/// Handles the streams no route matches.
+	pub fn fallback<F>(mut self, handler: F) -> PortMux
+	where
+		F: Fn(I2pStream, I2pSocketAddr) + Send + Sync + 'static,
+	{
+		self.routes.fallback = Some(Arc::new(handler));
+		self
+	}
+
+	pub fn listener(&self) -> &I2pListener {
+		&self.listener
+	}
+
+	/// Accepts and dispatches streams until the listener is shut down or its
+	/// session is lost. Other failures, such as a stream with a bad header,
+	/// are logged and accepting goes on, pausing when they happen back to
+	/// back.
+	pub fn run(&self) -> Result<()> {
+		let mut failures = 0;
+		loop {
+			let (stream, peer) = match self.listener.accept() {
+				Ok(accepted) => accepted,
+				Err(err) if ends_session(&err) => return Err(err),
+				Err(err) => {
+					error!("accept failed, continuing: {}", err);
+					failures += 1;
+					if failures > 1 {
+						thread::sleep(RETRY_DELAY);
+					}
+					continue;
+				}
+			};
+			failures = 0;
+			let port = stream.local_addr().map(|addr| addr.port()).unwrap_or(0);
+			match self.routes.get(port) {
+				Some(handler) => {
+					let handler = handler.clone();
+					thread::spawn(move || handler(stream, peer));
+				}
+				None => debug!("no handler for port {}, closing stream from {}", port, peer),
+			}
+		}
+	}
+}
+
+/// Whether `err` means the listener can't accept anymore, rather than a
+/// single stream failed. A bridge that can't be reached isn't, as it may be
+/// restarting: the session will then be reported unknown once it is back.
+fn ends_session(err: &I2PError) -> bool {
+	matches!(
+		err,
+		I2PError::Shutdown | I2PError::SAMInvalidId(_) | I2PError::SessionLost(_)
+	)
+}
+
+#[derive(Default)]
+struct Routes {
+	ports: HashMap<u16, Handler>,
+	fallback: Option<Handler>,
+}
+
+impl Routes {
+	fn get(&self, port: u16) -> Option<&Handler> {
+		self.ports.get(&port).or(self.fallback.as_ref())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock_bridge::MockBridge;
+	use crate::sam::Session;
+	use std::io::{Read, Write};
+	use std::sync::mpsc;
+
+	#[test]
+	fn routes() {
+		let http: Handler = Arc::new(|_, _| {});
+		let irc: Handler = Arc::new(|_, _| {});
+		let mut routes = Routes::default();
+		routes.ports.insert(80, http.clone());
+		routes.ports.insert(6667, irc.clone());
+
+		assert!(Arc::ptr_eq(routes.get(80).unwrap(), &http));
+		assert!(Arc::ptr_eq(routes.get(6667).unwrap(), &irc));
+		assert!(routes.get(0).is_none());
+		assert!(routes.get(443).is_none());
+
+		let fallback: Handler = Arc::new(|_, _| {});
+		routes.fallback = Some(fallback.clone());
+		assert!(Arc::ptr_eq(routes.get(443).unwrap(), &fallback));
+		assert!(Arc::ptr_eq(routes.get(80).unwrap(), &http));
+	}
+
+	#[test]
+	fn session_errors() {
+		assert!(ends_session(&I2PError::Shutdown));
+		assert!(ends_session(&I2PError::SAMInvalidId("gone".into())));
+		assert!(!ends_session(&I2PError::SAMInvalidKey("bad header".into())));
+		assert!(!ends_session(&I2PError::SilentStreamClosed));
+		assert!(!ends_session(
+			&std::io::Error::from(std::io::ErrorKind::ConnectionRefused).into()
+		));
+	}
+
+	#[test]
+	fn run_survives_stream_errors() {
+		let bridge = MockBridge::start();
+		let mut session = Session::transient(bridge.addr()).unwrap();
+		session.set_silent(true);
+		let listener = I2pListener::bind_with_session(&session).unwrap();
+		let (tx, rx) = mpsc::channel();
+		let mux = PortMux::new(listener).fallback(move |mut stream, _| {
+			let mut buf = [0u8; 2];
+			stream.read_exact(&mut buf).unwrap();
+			tx.send(buf).unwrap();
+		});
+		let run = thread::spawn(move || mux.run());
+
+		// a silent stream closed before sending anything fails its accept
+		while bridge.commands("STREAM ACCEPT").is_empty() {
+			thread::sleep(Duration::from_millis(10));
+		}
+		drop(I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap());
+		while bridge.commands("STREAM ACCEPT").len() < 2 {
+			thread::sleep(Duration::from_millis(10));
+		}
+		let mut client = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		client.write_all(b"hi").unwrap();
+		assert_eq!(&rx.recv_timeout(Duration::from_secs(5)).unwrap(), b"hi");
+
+		session.shutdown_handle().shutdown();
+		assert!(matches!(run.join().unwrap(), Err(I2PError::Shutdown)));
+	}
+}
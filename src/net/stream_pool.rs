@@ -0,0 +1,600 @@
+use crate::error::Result;
+use log::{debug, warn};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::I2PError;
+use crate::net::{I2pSocketAddr, I2pStream, ToI2pSocketAddrs};
+use crate::sam::Session;
+
+/// Default number of idle streams kept per destination.
+pub const DEFAULT_MAX_IDLE_PER_DEST: usize = 4;
+
+/// Default time a stream may stay idle in the pool.
+pub const DEFAULT_MAX_IDLE_TIME: Duration = Duration::from_secs(60);
+
+/// A pool of open streams, keyed by destination and port.
+///
+/// Opening an [I2pStream] costs a new SAM connection, a lookup and a
+/// `STREAM CONNECT`, which adds up for RPC-style traffic. The pool hands out
+/// streams that were opened earlier, either by [prewarm](StreamPool::prewarm)
+/// or by a previous user, and only opens new ones when none is available.
+///
+/// Streams are returned to the pool when their [PooledStream] is dropped,
+/// unless an I/O error occurred on them or they were
+/// [discarded](PooledStream::discard). Their timeouts and other socket
+/// settings are reset on the way back. Idle streams are checked before being
+/// handed out again and closed once they were idle for too long or reached
+/// their maximum lifetime. The pool is meant for protocols where the stream
+/// is left clean after each exchange.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::io::Write;
+/// use i2p::net::StreamPool;
+/// use i2p::Session;
+///
+/// let session = Session::transient(i2p::sam::DEFAULT_API).unwrap();
+/// let pool = StreamPool::new(session).max_idle_per_dest(2);
+/// pool.prewarm("example.i2p:8080", 2).unwrap();
+///
+/// let mut stream = pool.get("example.i2p:8080").unwrap();
+/// stream.write_all(b"ping\n").unwrap();
+/// // the stream goes back to the pool here
+/// ```
+#[derive(Clone)]
+pub struct StreamPool {
+	inner: Arc<Inner>,
+}
+
+struct Inner {
+	session: Session,
+	idle: Mutex<HashMap<I2pSocketAddr, VecDeque<Idle>>>,
+	/// Streams being opened for the pool, counted towards the idle ones.
+	opening: Mutex<HashMap<I2pSocketAddr, usize>>,
+	warm: Mutex<HashMap<I2pSocketAddr, usize>>,
+	wakeup: Arc<Wakeup>,
+	max_idle_per_dest: usize,
+	max_idle_time: Duration,
+	max_lifetime: Option<Duration>,
+}
+
+struct Idle {
+	stream: I2pStream,
+	opened: Instant,
+	since: Instant,
+}
+
+/// Wakes the maintenance thread up ahead of time.
+#[derive(Default)]
+struct Wakeup {
+	pending: Mutex<bool>,
+	cvar: Condvar,
+}
+
+/// A stream being opened for the pool, counted until dropped.
+struct Opening<'a> {
+	inner: &'a Inner,
+	addr: &'a I2pSocketAddr,
+}
+
+impl StreamPool {
+	/// Creates a pool opening its streams through `session`.
+	pub fn new(session: Session) -> StreamPool {
+		StreamPool {
+			inner: Arc::new(Inner {
+				session,
+				idle: Mutex::new(HashMap::new()),
+				opening: Mutex::new(HashMap::new()),
+				warm: Mutex::new(HashMap::new()),
+				wakeup: Arc::default(),
+				max_idle_per_dest: DEFAULT_MAX_IDLE_PER_DEST,
+				max_idle_time: DEFAULT_MAX_IDLE_TIME,
+				max_lifetime: None,
+			}),
+		}
+	}
+
+	/// Creates a pool using a duplicate handle of an existing session.
+	pub fn with_session(session: &Session) -> Result<StreamPool> {
+		Ok(StreamPool::new(session.duplicate()?))
+	}
+
+	/// Sets the maximum number of idle streams kept per destination.
+	pub fn max_idle_per_dest(self, max: usize) -> StreamPool {
+		self.configure(|inner| inner.max_idle_per_dest = max)
+	}
+
+	/// Sets how long a stream may stay idle before it is closed.
+	pub fn max_idle_time(self, max: Duration) -> StreamPool {
+		self.configure(|inner| inner.max_idle_time = max)
+	}
+
+	/// Sets how long after being opened a stream is closed instead of being
+	/// returned to the pool. Streams live as long as they are used by
+	/// default.
+	pub fn max_lifetime(self, max: Duration) -> StreamPool {
+		self.configure(|inner| inner.max_lifetime = Some(max))
+	}
+
+	fn configure<F: FnOnce(&mut Inner)>(mut self, f: F) -> StreamPool {
+		match Arc::get_mut(&mut self.inner) {
+			Some(inner) => f(inner),
+			None => warn!("stream pool settings changed after it was shared, ignoring"),
+		}
+		self
+	}
+
+	pub fn session(&self) -> &Session {
+		&self.inner.session
+	}
+
+	/// Hands out a stream to `addr`, opening a new one if no healthy idle
+	/// stream is available. If `addr` is kept warm, the maintenance thread
+	/// is woken up to replace the stream.
+	pub fn get<A: ToI2pSocketAddrs>(&self, addr: A) -> Result<PooledStream> {
+		let addr = resolve(addr)?;
+		let idle = self.take_idle(&addr);
+		if self.is_warm(&addr) {
+			self.inner.wakeup.notify();
+		}
+		let (stream, opened) = match idle {
+			Some(idle) => {
+				debug!("reusing pooled stream to {}", addr);
+				(idle.stream, idle.opened)
+			}
+			None => (self.connect(&addr)?, Instant::now()),
+		};
+		Ok(PooledStream {
+			stream: Some(stream),
+			addr,
+			opened,
+			reusable: true,
+			pool: Arc::downgrade(&self.inner),
+		})
+	}
+
+	/// Opens streams to `addr` until `count` of them are idle in the pool.
+	/// Streams other threads are opening for the pool count too.
+	pub fn prewarm<A: ToI2pSocketAddrs>(&self, addr: A, count: usize) -> Result<()> {
+		let addr = resolve(addr)?;
+		let count = count.min(self.inner.max_idle_per_dest);
+		while let Some(_opening) = self.inner.reserve(&addr, count) {
+			let stream = self.connect(&addr)?;
+			self.inner.put(&addr, stream, Instant::now());
+		}
+		Ok(())
+	}
+
+	/// Keeps `count` idle streams to `addr` open. Streams are opened by
+	/// [maintain](StreamPool::maintain), so this is mostly useful along with
+	/// [spawn_maintenance](StreamPool::spawn_maintenance). A count of zero
+	/// stops keeping streams warm.
+	pub fn keep_warm<A: ToI2pSocketAddrs>(&self, addr: A, count: usize) -> Result<()> {
+		let addr = resolve(addr)?;
+		if let Ok(mut warm) = self.inner.warm.lock() {
+			if count == 0 {
+				warm.remove(&addr);
+			} else {
+				warm.insert(addr, count);
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns the number of idle streams to `addr`.
+	pub fn idle_count(&self, addr: &I2pSocketAddr) -> usize {
+		self.inner
+			.idle
+			.lock()
+			.map(|idle| idle.get(addr).map_or(0, |streams| streams.len()))
+			.unwrap_or(0)
+	}
+
+	/// Closes expired and broken idle streams, then refills the destinations
+	/// registered with [keep_warm](StreamPool::keep_warm).
+	pub fn maintain(&self) {
+		// the checks don't block, so the streams stay in the pool meanwhile
+		// rather than look gone to other users
+		if let Ok(mut idle) = self.inner.idle.lock() {
+			for streams in idle.values_mut() {
+				streams.retain(|idle| self.inner.is_usable(idle));
+			}
+			idle.retain(|_, streams| !streams.is_empty());
+		}
+		let warm: Vec<(I2pSocketAddr, usize)> = match self.inner.warm.lock() {
+			Ok(warm) => warm.iter().map(|(a, c)| (a.clone(), *c)).collect(),
+			Err(_) => return,
+		};
+		for (addr, count) in warm {
+			if let Err(err) = self.prewarm(&addr, count) {
+				warn!("could not pre-open stream to {}: {:#}", addr, err);
+			}
+		}
+	}
+
+	/// Runs [maintain](StreamPool::maintain) on a background thread right
+	/// away, then every `interval` and whenever a stream to a destination
+	/// kept warm is handed out, until every handle to the pool is dropped.
+	/// A pool needs a single such thread.
+	pub fn spawn_maintenance(&self, interval: Duration) -> JoinHandle<()> {
+		let pool = Arc::downgrade(&self.inner);
+		let wakeup = self.inner.wakeup.clone();
+		thread::spawn(move || loop {
+			match pool.upgrade() {
+				Some(inner) => StreamPool { inner }.maintain(),
+				None => return,
+			}
+			wakeup.wait(interval);
+		})
+	}
+
+	fn is_warm(&self, addr: &I2pSocketAddr) -> bool {
+		self.inner
+			.warm
+			.lock()
+			.is_ok_and(|warm| warm.contains_key(addr))
+	}
+
+	fn connect(&self, addr: &I2pSocketAddr) -> Result<I2pStream> {
+		I2pStream::connect_with_session(&self.inner.session, addr)
+	}
+
+	fn take_idle(&self, addr: &I2pSocketAddr) -> Option<Idle> {
+		// candidates are checked once out of the pool, so that other users
+		// don't wait on the lock meanwhile
+		while let Some(candidate) = self.pop_idle(addr) {
+			if self.inner.is_usable(&candidate) {
+				return Some(candidate);
+			}
+			debug!("dropping stale pooled stream to {}", addr);
+		}
+		None
+	}
+
+	/// Takes the most recently used idle stream to `addr`, the least likely
+	/// to have been dropped.
+	fn pop_idle(&self, addr: &I2pSocketAddr) -> Option<Idle> {
+		self.inner.idle.lock().ok()?.get_mut(addr)?.pop_back()
+	}
+}
+
+impl Inner {
+	fn put(&self, addr: &I2pSocketAddr, stream: I2pStream, opened: Instant) {
+		self.put_idle(
+			addr,
+			Idle {
+				stream,
+				opened,
+				since: Instant::now(),
+			},
+		);
+	}
+
+	/// Counts a stream about to be opened to `addr`, unless `count` streams
+	/// are idle or being opened already.
+	fn reserve<'a>(&'a self, addr: &'a I2pSocketAddr, count: usize) -> Option<Opening<'a>> {
+		let mut opening = self.opening.lock().ok()?;
+		let idle = self
+			.idle
+			.lock()
+			.ok()?
+			.get(addr)
+			.map_or(0, |streams| streams.len());
+		let pending = opening.entry(addr.clone()).or_default();
+		if idle + *pending >= count {
+			return None;
+		}
+		*pending += 1;
+		Some(Opening { inner: self, addr })
+	}
+
+	fn put_idle(&self, addr: &I2pSocketAddr, stream: Idle) {
+		if let Ok(mut idle) = self.idle.lock() {
+			let streams = idle.entry(addr.clone()).or_default();
+			if streams.len() < self.max_idle_per_dest {
+				streams.push_back(stream);
+			}
+		}
+	}
+
+	fn is_expired(&self, opened: Instant) -> bool {
+		self.max_lifetime
+			.is_some_and(|max_lifetime| opened.elapsed() >= max_lifetime)
+	}
+
+	fn is_usable(&self, idle: &Idle) -> bool {
+		idle.since.elapsed() < self.max_idle_time
+			&& !self.is_expired(idle.opened)
+			&& is_healthy(&idle.stream)
+	}
+}
+
+impl Drop for Inner {
+	fn drop(&mut self) {
+		// lets the maintenance thread exit right away
+		self.wakeup.notify();
+	}
+}
+
+impl Drop for Opening<'_> {
+	fn drop(&mut self) {
+		// the stream is in the pool by now, if it could be opened
+		if let Ok(mut opening) = self.inner.opening.lock() {
+			if let Some(pending) = opening.get_mut(self.addr) {
+				*pending -= 1;
+				if *pending == 0 {
+					opening.remove(self.addr);
+				}
+			}
+		}
+	}
+}
+
+impl Wakeup {
+	fn notify(&self) {
+		if let Ok(mut pending) = self.pending.lock() {
+			*pending = true;
+			self.cvar.notify_one();
+		}
+	}
+
+	/// Waits until notified, or for `timeout`.
+	fn wait(&self, timeout: Duration) {
+		if let Ok(pending) = self.pending.lock() {
+			if let Ok((mut pending, _)) =
+				self.cvar
+					.wait_timeout_while(pending, timeout, |pending| !*pending)
+			{
+				*pending = false;
+			}
+		}
+	}
+}
+
+/// Checks that an idle stream was neither closed nor sent unexpected data.
+fn is_healthy(stream: &I2pStream) -> bool {
+	if stream.set_nonblocking(true).is_err() {
+		return false;
+	}
+	let healthy = match stream.peek(&mut [0u8]) {
+		Err(err) => err.kind() == io::ErrorKind::WouldBlock,
+		Ok(_) => false,
+	};
+	healthy && stream.set_nonblocking(false).is_ok()
+}
+
+fn resolve<A: ToI2pSocketAddrs>(addr: A) -> Result<I2pSocketAddr> {
+	addr.to_socket_addrs()?
+		.next()
+		.ok_or(I2PError::UnresolvableAddress)
+}
+
+/// A stream handed out by a [StreamPool], returned to it when dropped.
+pub struct PooledStream {
+	stream: Option<I2pStream>,
+	addr: I2pSocketAddr,
+	opened: Instant,
+	reusable: bool,
+	pool: Weak<Inner>,
+}
+
+impl PooledStream {
+	/// Closes the stream instead of returning it to the pool, e.g. after an
+	/// exchange was cut short.
+	pub fn discard(mut self) {
+		self.reusable = false;
+	}
+
+	/// Takes the stream out of the pool's management.
+	pub fn into_inner(mut self) -> I2pStream {
+		self.reusable = false;
+		self.stream.take().expect("pooled stream already taken")
+	}
+
+	/// Returns the destination the stream is connected to.
+	pub fn addr(&self) -> &I2pSocketAddr {
+		&self.addr
+	}
+
+	fn track<T>(&mut self, res: io::Result<T>) -> io::Result<T> {
+		if res.is_err() {
+			self.reusable = false;
+		}
+		res
+	}
+}
+
+impl Deref for PooledStream {
+	type Target = I2pStream;
+
+	fn deref(&self) -> &I2pStream {
+		self.stream.as_ref().expect("pooled stream already taken")
+	}
+}
+
+impl DerefMut for PooledStream {
+	fn deref_mut(&mut self) -> &mut I2pStream {
+		self.stream.as_mut().expect("pooled stream already taken")
+	}
+}
+
+impl Read for PooledStream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let res = self.deref_mut().read(buf);
+		if let Ok(0) = res {
+			// closed by the peer
+			self.reusable = false;
+		}
+		self.track(res)
+	}
+}
+
+impl Write for PooledStream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let res = self.deref_mut().write(buf);
+		self.track(res)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		let res = self.deref_mut().flush();
+		self.track(res)
+	}
+}
+
+impl Drop for PooledStream {
+	fn drop(&mut self) {
+		let (Some(stream), Some(pool)) = (self.stream.take(), self.pool.upgrade()) else {
+			return;
+		};
+		// settings made by this user must not carry over to the next one
+		if self.reusable && !pool.is_expired(self.opened) && stream.reset_settings().is_ok() {
+			pool.put(&self.addr, stream, self.opened);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock_bridge::MockBridge;
+	use crate::net::{I2pAddr, I2pListener};
+
+	fn echo_server(bridge: &MockBridge) -> Session {
+		let session = Session::transient(bridge.addr()).unwrap();
+		let listener = I2pListener::bind_with_session(&session).unwrap();
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let mut stream = stream.unwrap();
+				thread::spawn(move || {
+					let mut buf = [0u8; 64];
+					while let Ok(n @ 1..) = stream.read(&mut buf) {
+						if buf[..n].starts_with(b"bye") || stream.write_all(&buf[..n]).is_err() {
+							return;
+						}
+					}
+				});
+			}
+		});
+		session
+	}
+
+	fn ping(stream: &mut PooledStream) {
+		stream.write_all(b"ping").unwrap();
+		let mut buf = [0u8; 4];
+		stream.read_exact(&mut buf).unwrap();
+		assert_eq!(&buf, b"ping");
+	}
+
+	#[test]
+	fn reuse_and_health_check() {
+		let bridge = MockBridge::start();
+		let session = echo_server(&bridge);
+		let pool = StreamPool::with_session(&session).unwrap();
+		let addr = I2pSocketAddr::new(I2pAddr::new("echo.i2p"), 7);
+
+		pool.prewarm(&addr, 2).unwrap();
+		assert_eq!(pool.idle_count(&addr), 2);
+		assert_eq!(bridge.commands("STREAM CONNECT").len(), 2);
+
+		for _ in 0..3 {
+			let mut stream = pool.get(&addr).unwrap();
+			ping(&mut stream);
+		}
+		assert_eq!(bridge.commands("STREAM CONNECT").len(), 2);
+		assert_eq!(pool.idle_count(&addr), 2);
+
+		// the server closes this one while it is idle
+		let mut stream = pool.get(&addr).unwrap();
+		stream.write_all(b"bye").unwrap();
+		drop(stream);
+		thread::sleep(Duration::from_millis(100));
+		pool.maintain();
+		assert_eq!(pool.idle_count(&addr), 1);
+
+		pool.get(&addr).unwrap().discard();
+		assert_eq!(pool.idle_count(&addr), 0);
+		ping(&mut pool.get(&addr).unwrap());
+		assert_eq!(bridge.commands("STREAM CONNECT").len(), 3);
+	}
+
+	#[test]
+	fn concurrent_prewarm() {
+		let bridge = MockBridge::start();
+		let session = echo_server(&bridge);
+		let pool = StreamPool::with_session(&session).unwrap();
+		let addr = I2pSocketAddr::new(I2pAddr::new("echo.i2p"), 7);
+
+		let prewarms: Vec<_> = (0..4)
+			.map(|_| {
+				let (pool, addr) = (pool.clone(), addr.clone());
+				thread::spawn(move || pool.prewarm(&addr, 2).unwrap())
+			})
+			.collect();
+		for prewarm in prewarms {
+			prewarm.join().unwrap();
+		}
+		// streams opened by the other threads count as well
+		assert_eq!(pool.idle_count(&addr), 2);
+		assert_eq!(bridge.commands("STREAM CONNECT").len(), 2);
+	}
+
+	#[test]
+	fn lifetime_limits() {
+		let bridge = MockBridge::start();
+		let session = echo_server(&bridge);
+		let addr = I2pSocketAddr::new(I2pAddr::new("echo.i2p"), 7);
+
+		let pool = StreamPool::with_session(&session)
+			.unwrap()
+			.max_lifetime(Duration::from_millis(100));
+		ping(&mut pool.get(&addr).unwrap());
+		assert_eq!(pool.idle_count(&addr), 1);
+		thread::sleep(Duration::from_millis(150));
+		ping(&mut pool.get(&addr).unwrap());
+		assert_eq!(bridge.commands("STREAM CONNECT").len(), 2);
+
+		let pool = StreamPool::with_session(&session)
+			.unwrap()
+			.max_idle_time(Duration::from_millis(100));
+		pool.keep_warm(&addr, 1).unwrap();
+		pool.maintain();
+		assert_eq!(pool.idle_count(&addr), 1);
+		thread::sleep(Duration::from_millis(150));
+		pool.maintain();
+		assert_eq!(pool.idle_count(&addr), 1);
+		assert_eq!(bridge.commands("STREAM CONNECT").len(), 4);
+	}
+
+	#[cfg(feature = "public-conn")]
+	#[test]
+	fn settings_reset_on_checkin() {
+		let bridge = MockBridge::start();
+		let session = echo_server(&bridge);
+		let pool = StreamPool::with_session(&session).unwrap();
+		let addr = I2pSocketAddr::new(I2pAddr::new("echo.i2p"), 7);
+
+		let stream = pool.get(&addr).unwrap();
+		stream
+			.set_read_timeout(Some(Duration::from_secs(1)))
+			.unwrap();
+		stream
+			.set_write_timeout(Some(Duration::from_secs(1)))
+			.unwrap();
+		stream.inner.sam.conn.set_nodelay(true).unwrap();
+		drop(stream);
+
+		let mut stream = pool.get(&addr).unwrap();
+		assert_eq!(bridge.commands("STREAM CONNECT").len(), 1);
+		let conn = &stream.inner.sam.conn;
+		assert_eq!(conn.read_timeout().unwrap(), None);
+		assert_eq!(conn.write_timeout().unwrap(), None);
+		assert!(!conn.nodelay().unwrap());
+		ping(&mut stream);
+	}
+}
@@ -1,13 +1,16 @@
 use std::io::prelude::*;
 
 use crate::error::I2PError;
-use crate::net::{I2pAddr, I2pSocketAddr, ToI2pSocketAddrs};
-use crate::sam::{Session, StreamConnect, StreamForward, DEFAULT_API};
-use anyhow::Result;
+use crate::error::Result;
+use crate::net::{AcceptPool, I2pAddr, I2pSocketAddr, ToI2pSocketAddrs};
+use crate::sam::{Forwarding, Session, SessionStyle, StreamConnect, StreamForward, DEFAULT_API};
+use crate::shutdown::ShutdownHandle;
 use std::fmt;
 use std::io;
-use std::net::{Shutdown, SocketAddr, ToSocketAddrs};
-use std::time::Duration;
+use std::net::{Shutdown, SocketAddr, TcpListener, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use log::debug;
 
 /// A structure which represents an I2P stream between a local socket and a
 /// remote socket.
@@ -37,8 +40,9 @@ pub struct I2pStream {
 
 /// An infinite iterator over the connections from an `I2pListener`.
 ///
-/// This iterator will infinitely yield [`Some`] of the accepted connections. It
-/// is equivalent to calling `accept` in a loop.
+/// This iterator will yield [`Some`] of the accepted connections until the
+/// listener's session is shut down. It is equivalent to calling `accept` in a
+/// loop.
 ///
 /// This `struct` is created by the [`incoming`] method on [`I2pListener`].
 ///
@@ -77,6 +81,26 @@ impl I2pStream {
 		I2pStream::connect_via(DEFAULT_API, addr)
 	}
 
+	/// Same as `connect`, failing with [I2PError::SAMTimeout] if the
+	/// connection isn't established within `timeout`, which covers reaching
+	/// the SAM bridge, creating the session and opening the stream.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pStream;
+	/// use std::time::Duration;
+	///
+	/// let stream = I2pStream::connect_timeout("example.i2p:8080", Duration::from_secs(30));
+	/// ```
+	pub fn connect_timeout<A: ToI2pSocketAddrs>(addr: A, timeout: Duration) -> Result<I2pStream> {
+		super::each_i2p_addr(DEFAULT_API, addr, |sam_addr, addr| {
+			let stream =
+				StreamConnect::new_timeout(sam_addr, &addr.dest().string(), addr.port(), timeout)?;
+			Ok(I2pStream { inner: stream })
+		})
+	}
+
 	/// Same as `connect` but reuses an existing SAM session.
 	pub fn connect_with_session<A: ToI2pSocketAddrs>(
 		session: &Session,
@@ -85,10 +109,51 @@ impl I2pStream {
 		let addr: Result<_> = addr
 			.to_socket_addrs()?
 			.next()
-			.ok_or(I2PError::UnresolvableAddress.into());
+			.ok_or(I2PError::UnresolvableAddress);
 		I2pStream::connect_addr_with_session(session, &addr?)
 	}
 
+	/// Same as `connect_with_session`, failing with [I2PError::SAMTimeout] if
+	/// the stream isn't opened within `timeout`, regardless of the session's
+	/// own timeout.
+	pub fn connect_with_session_timeout<A: ToI2pSocketAddrs>(
+		session: &Session,
+		addr: A,
+		timeout: Duration,
+	) -> Result<I2pStream> {
+		let addr = addr
+			.to_socket_addrs()?
+			.next()
+			.ok_or(I2PError::UnresolvableAddress)?;
+		let stream = StreamConnect::with_ports_timeout(
+			session,
+			&addr.dest().string(),
+			0,
+			addr.port(),
+			timeout,
+		)?;
+
+		Ok(I2pStream { inner: stream })
+	}
+
+	/// Same as `connect_with_session`, opening the stream from `from_port`
+	/// so the remote end can tell apart streams from several local
+	/// services.
+	pub fn connect_with_session_from_port<A: ToI2pSocketAddrs>(
+		session: &Session,
+		addr: A,
+		from_port: u16,
+	) -> Result<I2pStream> {
+		let addr = addr
+			.to_socket_addrs()?
+			.next()
+			.ok_or(I2PError::UnresolvableAddress)?;
+		let stream =
+			StreamConnect::with_ports(session, &addr.dest().string(), from_port, addr.port())?;
+
+		Ok(I2pStream { inner: stream })
+	}
+
 	pub fn connect_via<A: ToSocketAddrs, B: ToI2pSocketAddrs>(
 		sam_addr: A,
 		addr: B,
@@ -166,6 +231,16 @@ impl I2pStream {
 	/// portions to return immediately with an appropriate value (see the
 	/// documentation of [`Shutdown`]).
 	///
+	/// Shutting down the write half is a half-close: the SAM bridge sees the
+	/// end of its socket and closes the I2P stream's outbound direction,
+	/// sending a streaming `CLOSE`, while the peer's data keeps coming in
+	/// until it closes its side too. Shutting down the read half only stops
+	/// local reads, the peer isn't told.
+	///
+	/// Once the peer closed the stream in an orderly way, reads return 0
+	/// bytes. If the stream is reset instead, reads and writes fail with
+	/// [`io::ErrorKind::ConnectionReset`], and keep failing so.
+	///
 	/// [`Shutdown`]: ../../std/net/enum.Shutdown.html
 	///
 	/// # Examples
@@ -201,6 +276,21 @@ impl I2pStream {
 	pub fn try_clone(&self) -> Result<I2pStream> {
 		self.inner.duplicate().map(|s| I2pStream { inner: s })
 	}
+
+	/// Receives data on the stream from the remote peer, without removing
+	/// that data from the queue. On success, returns the number of bytes
+	/// peeked.
+	pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+		self.inner.peek(buf)
+	}
+
+	pub(crate) fn reset_settings(&self) -> io::Result<()> {
+		self.inner.reset_settings()
+	}
+	#[cfg(all(feature = "http", feature = "tokio"))]
+	pub(crate) fn session_guard(&self) -> crate::sam::SessionGuard {
+		self.inner.session_guard()
+	}
 	pub fn to_tokio_stream(&mut self) -> Result<tokio::net::TcpStream> {
 		self.inner.to_tokio_stream()
 	}
@@ -217,7 +307,7 @@ impl Write for I2pStream {
 		self.inner.write(buf)
 	}
 	fn flush(&mut self) -> io::Result<()> {
-		Ok(())
+		self.inner.flush()
 	}
 }
 
@@ -265,6 +355,8 @@ pub struct I2pListener {
 	pub forward: StreamForward,
 	#[cfg(not(feature = "public-conn"))]
 	forward: StreamForward,
+	/// The local listener and registration of a forwarding listener.
+	forwarded: Option<(TcpListener, Forwarding)>,
 }
 
 impl I2pListener {
@@ -291,7 +383,10 @@ impl I2pListener {
 
 	pub fn bind_with_session(session: &Session) -> Result<I2pListener> {
 		let forward = StreamForward::with_session(session)?;
-		Ok(I2pListener { forward })
+		Ok(I2pListener {
+			forward,
+			forwarded: None,
+		})
 	}
 
 	pub fn bind_via<A: ToSocketAddrs>(sam_addr: A) -> Result<I2pListener> {
@@ -300,7 +395,78 @@ impl I2pListener {
 
 	fn bind_addr(sam_addr: &SocketAddr) -> Result<I2pListener> {
 		let forward = StreamForward::new(sam_addr)?;
-		Ok(I2pListener { forward })
+		Ok(I2pListener {
+			forward,
+			forwarded: None,
+		})
+	}
+
+	/// Creates a listener accepting only the streams opened to `port` of
+	/// `session`'s destination.
+	///
+	/// `session` must be a PRIMARY session (SAM 3.3); a STREAM subsession
+	/// listening on `port` is added to it, so several listeners can serve
+	/// different ports of the same destination. Streams opened through the
+	/// subsession use `port` as their `FROM_PORT`.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pListener;
+	/// use i2p::Session;
+	///
+	/// let session = Session::primary(i2p::sam::DEFAULT_API, "TRANSIENT").unwrap();
+	/// let http = I2pListener::bind_port(&session, 80).unwrap();
+	/// let irc = I2pListener::bind_port(&session, 6667).unwrap();
+	/// ```
+	pub fn bind_port(session: &Session, port: u16) -> Result<I2pListener> {
+		let subsession = session.add_subsession(
+			SessionStyle::Stream,
+			&format!("{}-{}", session.nickname, port),
+			&format!("FROM_PORT={port} LISTEN_PORT={port}"),
+		)?;
+		I2pListener::bind_with_session(&subsession)
+	}
+
+	/// Creates a listener whose connections are forwarded by the SAM bridge
+	/// to a local TCP listener bound to `local_addr`, using a new transient
+	/// session.
+	///
+	/// Unlike a listener created with `bind`, which waits on a new SAM
+	/// connection with a pending `STREAM ACCEPT` for every connection, a
+	/// single `STREAM FORWARD` registration is kept for the lifetime of the
+	/// listener. `local_addr` must be reachable from the SAM bridge.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pListener;
+	///
+	/// let listener = I2pListener::bind_forwarding("127.0.0.1:0").unwrap();
+	/// let (_stream, peer) = listener.accept().unwrap();
+	/// println!("new client: {}", peer);
+	/// ```
+	pub fn bind_forwarding<A: ToSocketAddrs>(local_addr: A) -> Result<I2pListener> {
+		let forward = StreamForward::new(DEFAULT_API)?;
+		I2pListener::forwarding(forward, local_addr)
+	}
+
+	/// Same as `bind_forwarding` but reuses an existing SAM session.
+	pub fn bind_forwarding_with_session<A: ToSocketAddrs>(
+		session: &Session,
+		local_addr: A,
+	) -> Result<I2pListener> {
+		let forward = StreamForward::with_session(session)?;
+		I2pListener::forwarding(forward, local_addr)
+	}
+
+	fn forwarding<A: ToSocketAddrs>(forward: StreamForward, local_addr: A) -> Result<I2pListener> {
+		let listener = TcpListener::bind(local_addr)?;
+		let forwarding = forward.forward_to(&listener)?;
+		Ok(I2pListener {
+			forward,
+			forwarded: Some((listener, forwarding)),
+		})
 	}
 
 	/// Returns the local socket address of this listener.
@@ -336,7 +502,11 @@ impl I2pListener {
 	/// ```
 	pub fn try_clone(&self) -> Result<I2pListener> {
 		let forward = self.forward.duplicate()?;
-		Ok(I2pListener { forward })
+		let forwarded = match &self.forwarded {
+			Some((listener, forwarding)) => Some((listener.try_clone()?, forwarding.duplicate()?)),
+			None => None,
+		};
+		Ok(I2pListener { forward, forwarded })
 	}
 
 	/// Accept a new incoming connection from this listener.
@@ -345,6 +515,10 @@ impl I2pListener {
 	/// is established. When established, the corresponding `TcpStream` and the
 	/// remote peer's address will be returned.
 	///
+	/// Connections rejected by the session's access list or connection limits
+	/// (see [`StreamingOptions`](crate::sam_options::StreamingOptions)) are
+	/// closed and not returned, in case the router doesn't enforce them.
+	///
 	/// # Examples
 	///
 	/// ```no_run
@@ -357,18 +531,102 @@ impl I2pListener {
 	/// }
 	/// ```
 	pub fn accept(&self) -> Result<(I2pStream, I2pSocketAddr)> {
-		let (i2p_stream, addr) = self.forward.accept()?;
-		Ok((I2pStream { inner: i2p_stream }, addr))
+		loop {
+			let (i2p_stream, addr) = match &self.forwarded {
+				Some((listener, _)) => self.forward.accept_forwarded(listener)?,
+				None => self.forward.accept()?,
+			};
+			if let Some(accepted) = self.filter(i2p_stream, addr) {
+				return Ok(accepted);
+			}
+		}
+	}
+
+	/// Same as `accept`, also failing with [I2PError::Shutdown] once `stop`
+	/// is shut down, without shutting down the session.
+	pub(crate) fn accept_stoppable(
+		&self,
+		stop: &ShutdownHandle,
+	) -> Result<(I2pStream, I2pSocketAddr)> {
+		loop {
+			let (i2p_stream, addr) = match &self.forwarded {
+				Some((listener, _)) => self.forward.accept_forwarded_stoppable(listener, stop)?,
+				None => self.forward.accept_stoppable(stop)?,
+			};
+			if let Some(accepted) = self.filter(i2p_stream, addr) {
+				return Ok(accepted);
+			}
+		}
+	}
+
+	/// Same as `accept`, failing with [I2PError::SAMTimeout] if no connection
+	/// comes in within `timeout`.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::net::I2pListener;
+	/// use std::time::Duration;
+	///
+	/// let listener = I2pListener::bind().unwrap();
+	/// match listener.accept_timeout(Duration::from_secs(60)) {
+	///     Ok((_socket, addr)) => println!("new client: {:?}", addr),
+	///     Err(e) => println!("no client in time: {:?}", e),
+	/// }
+	/// ```
+	pub fn accept_timeout(&self, timeout: Duration) -> Result<(I2pStream, I2pSocketAddr)> {
+		let deadline = Instant::now() + timeout;
+		loop {
+			let timeout = deadline.saturating_duration_since(Instant::now());
+			let (i2p_stream, addr) = match &self.forwarded {
+				Some((listener, _)) => self.forward.accept_forwarded_timeout(listener, timeout)?,
+				None => self.forward.accept_timeout(timeout)?,
+			};
+			if let Some(accepted) = self.filter(i2p_stream, addr) {
+				return Ok(accepted);
+			}
+		}
+	}
+
+	/// Drops the streams the session's access list or connection limits
+	/// reject.
+	fn filter(
+		&self,
+		i2p_stream: StreamConnect,
+		addr: I2pSocketAddr,
+	) -> Option<(I2pStream, I2pSocketAddr)> {
+		if self.forward.access_filter().allows(&addr) {
+			return Some((I2pStream { inner: i2p_stream }, addr));
+		}
+		debug!("dropping incoming stream from {}", addr.dest());
+		None
+	}
+
+	/// Asks the SAM bridge to forward incoming connections to a local TCP
+	/// listener at `host:port` instead of accepting them here. See
+	/// [StreamForward::forward]; forwarded streams bypass the session's
+	/// access list and connection limits unless the router enforces them.
+	pub fn forward(&self, host: &str, port: u16) -> Result<Forwarding> {
+		self.forward.forward(host, port)
 	}
-	pub fn forward(&mut self, host: &str, port: &str) -> Result<(I2pStream, I2pSocketAddr)> {
-		let (i2p_stream, addr) = self.forward.forward(host, port)?;
-		Ok((I2pStream { inner: i2p_stream }, addr))
+	/// Starts an [AcceptPool] keeping `pending` accepts waiting on this
+	/// listener's session, buffering up to `backlog` accepted streams.
+	pub fn accept_pool(&self, pending: usize, backlog: usize) -> Result<AcceptPool> {
+		AcceptPool::new(self, pending, backlog)
 	}
+
+	/// Returns the handle shutting down this listener's session, which
+	/// interrupts pending accepts and ends [`incoming`](Self::incoming).
+	pub fn shutdown_handle(&self) -> ShutdownHandle {
+		self.forward.shutdown_handle()
+	}
+
 	/// Returns an iterator over the connections being received on this
 	/// listener.
 	///
-	/// The returned iterator will never return [`None`] and will also not yield
-	/// the peer's [`I2pSocketAddr`] structure.
+	/// The returned iterator returns [`None`] once the listener's session is
+	/// shut down, see [`shutdown_handle`](Self::shutdown_handle). It does not
+	/// yield the peer's [`I2pSocketAddr`] structure.
 	///
 	/// [`None`]: ../../std/option/enum.Option.html#variant.None
 	/// [`I2pSocketAddr`]: ../../std/net/struct.I2pSocketAddr.html
@@ -389,7 +647,7 @@ impl I2pListener {
 	///     }
 	/// }
 	/// ```
-	pub fn incoming(&self) -> Incoming {
+	pub fn incoming(&self) -> Incoming<'_> {
 		Incoming { listener: self }
 	}
 }
@@ -397,6 +655,13 @@ impl I2pListener {
 impl<'a> Iterator for Incoming<'a> {
 	type Item = Result<I2pStream>;
 	fn next(&mut self) -> Option<Result<I2pStream>> {
-		Some(self.listener.accept().map(|p| p.0))
+		if self.listener.shutdown_handle().is_shutdown() {
+			return None;
+		}
+		match self.listener.accept() {
+			Ok((stream, _)) => Some(Ok(stream)),
+			Err(I2PError::Shutdown) => None,
+			Err(e) => Some(Err(e)),
+		}
 	}
 }
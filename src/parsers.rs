@@ -1,7 +1,7 @@
 use nom::{
 	alt,
-	character::complete::{alphanumeric1 as alphanumeric, space1 as space},
-	do_parse, named, separated_list0, tag, take_till,
+	character::complete::space1 as space,
+	do_parse, named, opt, preceded, separated_list0, tag, take_till, take_while1,
 };
 fn is_space(chr: char) -> bool {
 	chr == ' ' || chr == '\t'
@@ -15,6 +15,10 @@ fn is_space_or_next_line(chr: char) -> bool {
 	is_space(chr) || is_next_line(chr)
 }
 
+fn is_key_char(chr: char) -> bool {
+	chr.is_ascii_alphanumeric() || chr == '_'
+}
+
 fn is_double_quote(chr: char) -> bool {
 	chr == '\"'
 }
@@ -32,7 +36,7 @@ named!(value <&str, &str>, take_till!(is_space_or_next_line));
 
 named!(key_value <&str, (&str, &str)>,
 	do_parse!(
-		key: alphanumeric               >>
+		key: take_while1!(is_key_char)  >>
 			 tag!("=")                >>
 		val: alt!(quoted_value | value) >>
 		(key, val)
@@ -86,6 +90,16 @@ named!(pub sam_dest_reply <&str, Vec<(&str, &str)> >,
 	)
 );
 
+named!(pub sam_stream_header <&str, (&str, Option<Vec<(&str, &str)> >)>,
+	do_parse!(
+		dest: take_till!(is_space_or_next_line)        >>
+		opts: opt!(preceded!(space, keys_and_values)) >>
+			  opt!(space)                              >>
+			  tag!("\n")                              >>
+		(dest, opts)
+	)
+);
+
 #[cfg(test)]
 mod tests {
 	use nom::error::ErrorKind;
@@ -203,4 +217,22 @@ mod tests {
 			Ok(("", vec![("PUB", "foo"), ("PRIV", "foobar")]))
 		);
 	}
+
+	#[test]
+	fn stream_header() {
+		use crate::parsers::sam_stream_header;
+
+		assert_eq!(
+			sam_stream_header("dest~ FROM_PORT=1234 TO_PORT=80\n"),
+			Ok((
+				"",
+				("dest~", Some(vec![("FROM_PORT", "1234"), ("TO_PORT", "80")]))
+			))
+		);
+		assert_eq!(sam_stream_header("dest~\n"), Ok(("", ("dest~", None))));
+		assert_eq!(
+			sam_stream_header("dest~ \n"),
+			Ok(("", ("dest~", Some(vec![]))))
+		);
+	}
 }
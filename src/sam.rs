@@ -1,20 +1,25 @@
-use anyhow::Result;
+use crate::error::Result;
 use std::clone::Clone;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::io::prelude::*;
-use std::io::{self, BufReader};
-use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
-use std::time::Duration;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, Weak};
+use std::time::{Duration, Instant};
 
 use log::debug;
 use nom::IResult;
 
 use crate::error::I2PError;
-use crate::net::{I2pAddr, I2pSocketAddr};
+use crate::net::{AccessFilter, I2pAddr, I2pSocketAddr, FILTER_OPTIONS};
 use crate::parsers::{
-	sam_dest_reply, sam_hello, sam_naming_reply, sam_session_status, sam_stream_status,
+	sam_dest_reply, sam_hello, sam_naming_reply, sam_session_status, sam_stream_header,
+	sam_stream_status,
 };
 use crate::sam_options::{SAMOptions, SignatureType};
+use crate::shutdown::{Registration, ShutdownHandle, WakeTimer, Waiter};
 
 pub static DEFAULT_API: &str = "127.0.0.1:7656";
 
@@ -45,7 +50,58 @@ pub struct Session {
 	sam: SamConnection,
 	pub local_dest: String,
 	pub nickname: String,
+	shutdown: ShutdownHandle,
+	timeout: Option<Duration>,
+	silent: bool,
+	/// The SAM session backing this one, shared with duplicates.
+	current: Arc<Current>,
 }
+
+/// What [Session::update_options] replaces, seen by every duplicate of a
+/// session.
+#[derive(Debug)]
+struct Current {
+	generation: RwLock<Arc<Generation>>,
+	/// Forwards to register again on the sessions replacing this one.
+	forwards: Mutex<Vec<Weak<ForwardTarget>>>,
+}
+
+/// One SAM session. Streams hold on to the generation they were opened on,
+/// so that a replaced session stays up until its last stream is closed.
+#[derive(Debug)]
+struct Generation {
+	nickname: String,
+	sam_api: SocketAddr,
+	/// The private keys and style the session was created with, None for
+	/// subsessions.
+	recipe: Option<(String, SessionStyle)>,
+	options: RwLock<SAMOptions>,
+	access: RwLock<AccessFilter>,
+	/// A handle to the control socket, shut down once a replaced
+	/// generation is dropped.
+	control: SamConnection,
+	_registration: Option<Registration>,
+	/// Shut down when the session is replaced, interrupting the accepts
+	/// pending on it.
+	replaced: ShutdownHandle,
+}
+
+impl Drop for Generation {
+	fn drop(&mut self) {
+		if self.replaced.is_shutdown() {
+			let _ = self.control.conn.shutdown(Shutdown::Both);
+		}
+	}
+}
+
+/// Keeps a session up after [Session::update_options] replaced it, as a
+/// stream opened on it does.
+#[cfg(all(feature = "http", feature = "tokio"))]
+#[derive(Debug, Clone)]
+pub(crate) struct SessionGuard {
+	_generation: Arc<Generation>,
+}
+
 #[derive(Debug)]
 pub struct StreamConnect {
 	#[cfg(feature = "public-conn")]
@@ -59,17 +115,20 @@ pub struct StreamConnect {
 	pub peer_dest: String,
 	pub peer_port: u16,
 	pub local_port: u16,
+	/// Set once the stream was reset, shared with duplicates.
+	reset: Arc<AtomicBool>,
+	/// Keeps the session the stream was opened on up.
+	generation: Arc<Generation>,
 }
 
-
-impl ToString for SessionStyle {
-	fn to_string(&self) -> String {
-		match self {
-			SessionStyle::Datagram => "DATAGRAM".to_string(),
-			SessionStyle::Raw => "RAW".to_string(),
-			SessionStyle::Stream => "STREAM".to_string(),
-			SessionStyle::Primary => "PRIMARY".to_string()
-		}
+impl fmt::Display for SessionStyle {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			SessionStyle::Datagram => "DATAGRAM",
+			SessionStyle::Raw => "RAW",
+			SessionStyle::Stream => "STREAM",
+			SessionStyle::Primary => "PRIMARY",
+		})
 	}
 }
 
@@ -79,19 +138,18 @@ fn verify_response<'a>(vec: &'a [(&str, &str)]) -> Result<HashMap<&'a str, &'a s
 	let msg = <&str>::clone(map.get("MESSAGE").unwrap_or(&""));
 	match res {
 		"OK" => Ok(map),
-		"CANT_REACH_PEER" => Err(I2PError::SAMCantReachPeer(msg.to_string()).into()),
-		"KEY_NOT_FOUND" => Err(I2PError::SAMKeyNotFound(msg.to_string()).into()),
-		"PEER_NOT_FOUND" => Err(I2PError::SAMPeerNotFound(msg.to_string()).into()),
-		"DUPLICATED_DEST" => Err(I2PError::SAMDuplicatedDest(msg.to_string()).into()),
-		"INVALID_KEY" => Err(I2PError::SAMInvalidKey(msg.to_string()).into()),
-		"INVALID_ID" => Err(I2PError::SAMInvalidId(msg.to_string()).into()),
-		"TIMEOUT" => Err(I2PError::SAMTimeout(msg.to_string()).into()),
-		"I2P_ERROR" => Err(I2PError::SAMI2PError(msg.to_string()).into()),
-		_ => Err(I2PError::SAMInvalidMessage(msg.to_string()).into()),
+		_ => Err(I2PError::from_result(res, msg)),
 	}
 }
 
 impl SamConnection {
+	/// Sends a command the bridge doesn't reply to.
+	fn send_silent(&mut self, msg: String) -> Result<()> {
+		debug!("-> {}", &msg);
+		self.conn.write_all(msg.as_bytes())?;
+		Ok(())
+	}
+
 	pub fn send<F>(&mut self, msg: String, mut reply_parser: F) -> Result<HashMap<String, String>>
 	where
 		F: FnMut(&str) -> IResult<&str, Vec<(&str, &str)>>,
@@ -99,15 +157,11 @@ impl SamConnection {
 		debug!("-> {}", &msg);
 		self.conn.write_all(&msg.into_bytes())?;
 
-		let mut reader = BufReader::new(&self.conn);
-		let mut buffer = String::new();
-		reader.read_line(&mut buffer)?;
+		// read unbuffered, whatever follows the reply belongs to the stream
+		let buffer = read_line(&mut self.conn, MAX_REPLY_LEN)?;
 		debug!("<- {}", &buffer);
 
-		// TODO: get rid of this hack
-		let b = string_to_static_str(buffer);
-
-		let vec_opts = reply_parser(b)?.1;
+		let vec_opts = reply_parser(&buffer).map_err(I2PError::from)?.1;
 		verify_response(&vec_opts).map(|m| {
 			m.iter()
 				.map(|(k, v)| (k.to_string(), v.to_string()))
@@ -121,14 +175,46 @@ impl SamConnection {
 	}
 
 	pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<SamConnection> {
-		let tcp_stream = TcpStream::connect(addr)?;
+		Self::open(addr, None)
+	}
+
+	/// Same as `connect`, failing with [I2PError::SAMTimeout] if the bridge
+	/// can't be reached or doesn't complete the handshake within `timeout`.
+	pub fn connect_timeout<A: ToSocketAddrs>(addr: A, timeout: Duration) -> Result<SamConnection> {
+		Self::open(addr, Some(Instant::now() + timeout))
+	}
+
+	fn open<A: ToSocketAddrs>(addr: A, deadline: Option<Instant>) -> Result<SamConnection> {
+		let tcp_stream = match deadline {
+			Some(deadline) => connect_until(addr, deadline)?,
+			None => TcpStream::connect(addr)?,
+		};
 
 		let mut socket = SamConnection { conn: tcp_stream };
-		socket.handshake()?;
+		socket.until(deadline, |sam| sam.handshake())?;
 
 		Ok(socket)
 	}
 
+	/// Runs `f`, failing with [I2PError::SAMTimeout] if it is still waiting
+	/// on the bridge by `deadline`.
+	fn until<T, F>(&mut self, deadline: Option<Instant>, f: F) -> Result<T>
+	where
+		F: FnOnce(&mut SamConnection) -> Result<T>,
+	{
+		let deadline = match deadline {
+			Some(deadline) => deadline,
+			None => return f(self),
+		};
+		let timeout = remaining(deadline)?;
+		self.conn.set_read_timeout(Some(timeout))?;
+		self.conn.set_write_timeout(Some(timeout))?;
+		let res = f(self).map_err(|e| map_timeout(e, "waiting for the SAM bridge"));
+		self.conn.set_read_timeout(None)?;
+		self.conn.set_write_timeout(None)?;
+		res
+	}
+
 	// TODO: Implement a lookup table
 	pub fn naming_lookup(&mut self, name: &str) -> Result<String> {
 		let naming_lookup_msg = format!("NAMING LOOKUP NAME={name} \n");
@@ -142,7 +228,7 @@ impl SamConnection {
 	) -> Result<(String, String)> {
 		let dest_gen_msg = format!(
 			"DEST GENERATE SIGNATURE_TYPE={signature_type} \n",
-			signature_type = signature_type.to_string(),
+			signature_type = signature_type,
 		);
 		let ret = self.send(dest_gen_msg, sam_dest_reply)?;
 		Ok((ret["PUB"].clone(), ret["PRIV"].clone()))
@@ -178,19 +264,56 @@ impl Session {
 		style: SessionStyle,
 		options: SAMOptions,
 	) -> Result<Session> {
-		let mut sam = SamConnection::connect(sam_addr)?;
-		let create_session_msg = format!(
-			// values for SIGNATURE_TYPE and leaseSetEncType taken from
-			// https://github.com/eyedeekay/goSam/blob/62cade9ebc26e48ff32a517ef94212fc90aa92cd/client.go#L169
-			// https://github.com/eyedeekay/goSam/blob/62cade9ebc26e48ff32a517ef94212fc90aa92cd/client.go#L166
-			"SESSION CREATE STYLE={style} ID={nickname} DESTINATION={destination} {options}\n",
-			style = style.to_string(),
-			nickname = nickname,
-			destination = destination,
-			options = options.options(),
-		);
+		Self::create_until(sam_addr, destination, nickname, style, options, None)
+	}
 
-		sam.send(create_session_msg, sam_session_status)?;
+	/// Same as `create`, failing with [I2PError::SAMTimeout] if the session
+	/// isn't created within `timeout`.
+	///
+	/// `timeout` then also bounds connecting to the bridge and waiting for
+	/// its `STREAM STATUS` when opening or accepting streams on the session,
+	/// see [Session::set_timeout].
+	pub fn create_timeout<A: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		nickname: &str,
+		style: SessionStyle,
+		options: SAMOptions,
+		timeout: Duration,
+	) -> Result<Session> {
+		let deadline = Instant::now() + timeout;
+		let mut session = Self::create_until(
+			sam_addr,
+			destination,
+			nickname,
+			style,
+			options,
+			Some(deadline),
+		)?;
+		session.timeout = Some(timeout);
+		Ok(session)
+	}
+
+	fn create_until<A: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		nickname: &str,
+		style: SessionStyle,
+		options: SAMOptions,
+		deadline: Option<Instant>,
+	) -> Result<Session> {
+		options.validate()?;
+		let sam = SamConnection::open(sam_addr, deadline)?;
+		let shutdown = ShutdownHandle::new();
+		let (sam, generation) = Generation::create(
+			sam,
+			destination,
+			nickname,
+			style,
+			options,
+			deadline,
+			&shutdown,
+		)?;
 
 		//let local_dest = sam.naming_lookup("ME")?;
 
@@ -199,6 +322,13 @@ impl Session {
 			//local_dest,
 			local_dest: "empty".to_string(),
 			nickname: nickname.to_string(),
+			shutdown,
+			timeout: None,
+			silent: false,
+			current: Arc::new(Current {
+				generation: RwLock::new(Arc::new(generation)),
+				forwards: Mutex::default(),
+			}),
 		})
 	}
 
@@ -226,8 +356,61 @@ impl Session {
 		)
 	}
 
+	/// Convenience constructor to create a new PRIMARY session (SAM 3.3),
+	/// to which subsessions sharing its destination can be added.
+	pub fn primary<A: ToSocketAddrs>(sam_addr: A, destination: &str) -> Result<Session> {
+		Self::create(
+			sam_addr,
+			destination,
+			&nickname(),
+			SessionStyle::Primary,
+			SAMOptions::default(),
+		)
+	}
+
+	/// Adds a subsession to this PRIMARY session. `options` are appended to
+	/// the `SESSION ADD` command, e.g. `FROM_PORT=80 LISTEN_PORT=80`.
+	///
+	/// The returned session shares the primary session's destination and
+	/// control socket, and is used like any other session of its style.
+	pub fn add_subsession(
+		&self,
+		style: SessionStyle,
+		nickname: &str,
+		options: &str,
+	) -> Result<Session> {
+		let mut sam = self.sam.duplicate()?;
+		let add_session_msg = format!("SESSION ADD STYLE={style} ID={nickname} {options}\n");
+		sam.until(self.deadline(), |sam| {
+			sam.send(add_session_msg, sam_session_status)
+		})?;
+
+		let generation = Generation {
+			nickname: nickname.to_string(),
+			sam_api: self.sam_api()?,
+			recipe: None,
+			options: RwLock::default(),
+			access: RwLock::default(),
+			control: sam.duplicate()?,
+			_registration: None,
+			replaced: ShutdownHandle::new(),
+		};
+		Ok(Session {
+			sam,
+			local_dest: self.local_dest.clone(),
+			nickname: nickname.to_string(),
+			shutdown: self.shutdown.clone(),
+			timeout: self.timeout,
+			silent: self.silent,
+			current: Arc::new(Current {
+				generation: RwLock::new(Arc::new(generation)),
+				forwards: Mutex::default(),
+			}),
+		})
+	}
+
 	pub fn sam_api(&self) -> Result<SocketAddr> {
-		self.sam.conn.peer_addr().map_err(|e| e.into())
+		Ok(self.generation().sam_api)
 	}
 
 	pub fn naming_lookup(&mut self, name: &str) -> Result<String> {
@@ -239,12 +422,304 @@ impl Session {
 			sam: s,
 			local_dest: self.local_dest.clone(),
 			nickname: self.nickname.clone(),
+			shutdown: self.shutdown.clone(),
+			timeout: self.timeout,
+			silent: self.silent,
+			current: self.current.clone(),
 		})
 	}
 	/// attempts to return a handle to the underlying socket
 	pub fn try_clone(&self) -> std::io::Result<TcpStream> {
 		self.sam.try_clone()
 	}
+
+	/// Returns the handle shutting this session down, shared with its
+	/// duplicates and subsessions.
+	pub fn shutdown_handle(&self) -> ShutdownHandle {
+		self.shutdown.clone()
+	}
+
+	pub fn timeout(&self) -> Option<Duration> {
+		self.timeout
+	}
+
+	/// Bounds connecting to the bridge and waiting for its reply when
+	/// opening or accepting streams on this session. Waiting for a peer to
+	/// open a stream isn't bounded, see [StreamForward::accept_timeout].
+	pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+		self.timeout = timeout;
+	}
+
+	pub fn is_silent(&self) -> bool {
+		self.silent
+	}
+
+	/// Opens, accepts and forwards streams on this session in silent mode
+	/// (`SILENT=true`), where the bridge writes nothing but the stream's
+	/// data on stream sockets:
+	///
+	/// * connecting returns as soon as the `STREAM CONNECT` is sent. If the
+	///   stream can't be opened, the bridge closes the socket without a
+	///   status, so the failure shows up as the stream ending: reads return
+	///   0 bytes and writes fail.
+	/// * accepting waits for the peer's first bytes, as nothing else tells
+	///   that a stream came in, and fails with [I2PError::SilentStreamClosed]
+	///   if the socket is closed before. The peer's address is unknown, it is
+	///   returned empty with port 0.
+	/// * forwarded connections carry no header line, so accepting them
+	///   returns as soon as the bridge connects, with an empty peer address.
+	///
+	/// The bridge still replies to `STREAM FORWARD` and to the commands
+	/// that don't open streams. As peers are unknown, listeners drop every
+	/// stream when the session has an access list
+	/// ([allow_only](crate::sam_options::SAMOptionsBuilder::allow_only)).
+	pub fn set_silent(&mut self, silent: bool) {
+		self.silent = silent;
+	}
+
+	/// Applies `options` to this session and its duplicates.
+	///
+	/// SAM has no command changing the options of a session, so only the
+	/// access list and connection limits, which listeners also enforce
+	/// themselves, are applied in place. Routers enforcing them keep the
+	/// values the session was created with.
+	///
+	/// Any other change recreates the session, make-before-break:
+	///
+	/// * a new session is created with the same destination keys and a new
+	///   nickname, failing leaves this session as it was.
+	/// * forwards are registered again on it, and accepts pending on the
+	///   old session are issued again on the new one. Streams are opened on
+	///   the new session from then on.
+	/// * the old session is closed once the last stream opened or accepted
+	///   on it is.
+	///
+	/// The `nickname` of duplicates made before isn't updated, but they use
+	/// the new session all the same.
+	///
+	/// Bridges refusing a second session on the same destination, as the
+	/// Java one does, answer with `DUPLICATED_DEST`. The old session is then
+	/// closed before creating the new one, ending its streams. If the new
+	/// session can't be created, the old one is created again with its
+	/// options, and failing that too, [I2PError::SessionLost] is returned:
+	/// the session stays closed until its options are updated again.
+	///
+	/// Fails if the options are invalid, and for PRIMARY sessions and
+	/// subsessions.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::sam_options::SAMOptions;
+	/// use i2p::Session;
+	///
+	/// let mut session = Session::transient(i2p::sam::DEFAULT_API).unwrap();
+	/// session
+	///     .update_options(SAMOptions::builder().inbound_quantity(4).build().unwrap())
+	///     .unwrap();
+	/// ```
+	pub fn update_options(&mut self, options: SAMOptions) -> Result<()> {
+		options.validate()?;
+		let current = self.generation();
+		let (destination, style) = match &current.recipe {
+			Some((_, SessionStyle::Primary)) => {
+				return Err(I2PError::OptionsUpdateUnsupported(
+					"PRIMARY session".to_string(),
+				))
+			}
+			Some(recipe) => recipe.clone(),
+			None => return Err(I2PError::OptionsUpdateUnsupported("subsession".to_string())),
+		};
+		// a session lost on a previous update is created again
+		if !current.replaced.is_shutdown()
+			&& bridge_pairs(&current.options()) == bridge_pairs(&options)
+		{
+			*write(&current.access) = AccessFilter::new(options.streaming_options.as_ref());
+			*write(&current.options) = options;
+			return Ok(());
+		}
+
+		let deadline = self.deadline();
+		let nickname = nickname();
+		let establish = |options| {
+			let sam = SamConnection::open(current.sam_api, deadline)?;
+			let (sam, generation) = Generation::create(
+				sam,
+				&destination,
+				&nickname,
+				style.clone(),
+				options,
+				deadline,
+				&self.shutdown,
+			)?;
+			let generation = Arc::new(generation);
+			let forwards = self.forward_again(&generation, deadline)?;
+			Ok((sam, generation, forwards))
+		};
+		let established = match establish(options.clone()) {
+			Err(I2PError::SAMDuplicatedDest(_)) => {
+				debug!("the bridge refused a second session on the destination, closing the old one first");
+				// no lock is held meanwhile, users of the old session fail on
+				// its closed sockets instead of waiting
+				current.replaced.shutdown();
+				let _ = current.control.conn.shutdown(Shutdown::Both);
+				match establish(options) {
+					Ok(established) => established,
+					Err(err) => {
+						debug!(
+							"creating the session failed, restoring the old options: {}",
+							err
+						);
+						let restored = establish(current.options())
+							.map_err(|err| I2PError::SessionLost(Box::new(err)))?;
+						self.replaced(&nickname, restored);
+						return Err(err);
+					}
+				}
+			}
+			res => res?,
+		};
+		self.replaced(&nickname, established);
+		Ok(())
+	}
+
+	/// Registers the session's forwards on `generation`.
+	fn forward_again(
+		&self,
+		generation: &Generation,
+		deadline: Option<Instant>,
+	) -> Result<Vec<Forward>> {
+		let targets: Vec<_> = {
+			let mut forwards = lock(&self.current.forwards);
+			forwards.retain(|target| target.strong_count() > 0);
+			forwards.iter().filter_map(Weak::upgrade).collect()
+		};
+		targets
+			.into_iter()
+			.map(|target| {
+				let (sam, registration) =
+					open_forward(generation, &target, deadline, &self.shutdown)?;
+				Ok((target, sam, registration))
+			})
+			.collect()
+	}
+
+	/// Switches this handle over to the session replacing the old one, and
+	/// closes the old one.
+	fn replaced(
+		&mut self,
+		nickname: &str,
+		(sam, generation, forwards): (SamConnection, Arc<Generation>, Vec<Forward>),
+	) {
+		let old = std::mem::replace(&mut *write(&self.current.generation), generation);
+		old.replaced.shutdown();
+		for (target, sam, registration) in forwards {
+			*lock(&target.renewed) = Some((sam, registration));
+		}
+		self.sam = sam;
+		self.nickname = nickname.to_string();
+	}
+
+	fn generation(&self) -> Arc<Generation> {
+		self.current
+			.generation
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clone()
+	}
+
+	/// The private keys of the session's destination, None for
+	/// subsessions.
+	pub(crate) fn private_keys(&self) -> Option<String> {
+		let generation = self.generation();
+		generation.recipe.as_ref().map(|(keys, _)| keys.clone())
+	}
+
+	fn deadline(&self) -> Option<Instant> {
+		self.timeout.map(|timeout| Instant::now() + timeout)
+	}
+}
+
+impl Generation {
+	/// Creates a session on the control socket `sam`, returning the socket.
+	fn create(
+		mut sam: SamConnection,
+		destination: &str,
+		nickname: &str,
+		style: SessionStyle,
+		options: SAMOptions,
+		deadline: Option<Instant>,
+		shutdown: &ShutdownHandle,
+	) -> Result<(SamConnection, Generation)> {
+		// the key type only applies to the keys the bridge generates
+		let signature_type = match destination {
+			"TRANSIENT" => format!("SIGNATURE_TYPE={} ", options.signature_type),
+			_ => String::new(),
+		};
+		let create_session_msg = format!(
+			// values for SIGNATURE_TYPE and leaseSetEncType taken from
+			// https://github.com/eyedeekay/goSam/blob/62cade9ebc26e48ff32a517ef94212fc90aa92cd/client.go#L169
+			// https://github.com/eyedeekay/goSam/blob/62cade9ebc26e48ff32a517ef94212fc90aa92cd/client.go#L166
+			"SESSION CREATE STYLE={style} ID={nickname} DESTINATION={destination} {signature_type}{options}\n",
+			style = style,
+			nickname = nickname,
+			destination = destination,
+			options = options.options(),
+		);
+
+		let reply = sam.until(deadline, |sam| {
+			sam.send(create_session_msg, sam_session_status)
+		})?;
+		// the bridge replies with the private keys, generated if TRANSIENT
+		let destination = reply
+			.get("DESTINATION")
+			.cloned()
+			.unwrap_or_else(|| destination.to_string());
+
+		// closing the control socket destroys the session
+		let registration = shutdown.register_socket(&sam.conn)?;
+
+		let generation = Generation {
+			nickname: nickname.to_string(),
+			sam_api: sam.conn.peer_addr()?,
+			recipe: Some((destination, style)),
+			access: RwLock::new(AccessFilter::new(options.streaming_options.as_ref())),
+			options: RwLock::new(options),
+			control: sam.duplicate()?,
+			_registration: Some(registration),
+			replaced: ShutdownHandle::new(),
+		};
+		Ok((sam, generation))
+	}
+
+	fn options(&self) -> SAMOptions {
+		self.options
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clone()
+	}
+
+	fn access(&self) -> AccessFilter {
+		self.access
+			.read()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clone()
+	}
+}
+
+/// The options the bridge sees, leaving out those listeners enforce.
+fn bridge_pairs(options: &SAMOptions) -> BTreeMap<String, String> {
+	let mut pairs = options.pairs();
+	pairs.retain(|key, _| !FILTER_OPTIONS.contains(&key.as_str()));
+	pairs
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+	mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write<T>(lock: &RwLock<T>) -> std::sync::RwLockWriteGuard<'_, T> {
+	lock.write().unwrap_or_else(PoisonError::into_inner)
 }
 
 impl StreamConnect {
@@ -259,31 +734,99 @@ impl StreamConnect {
 		Self::with_session(&session, destination, port)
 	}
 
+	/// Same as `new`, failing with [I2PError::SAMTimeout] if the session
+	/// isn't created and the stream opened within `timeout`.
+	pub fn new_timeout<A: ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		port: u16,
+		timeout: Duration,
+	) -> Result<StreamConnect> {
+		let deadline = Instant::now() + timeout;
+		let mut session = Session::create_until(
+			sam_addr,
+			"TRANSIENT",
+			&nickname(),
+			SessionStyle::Stream,
+			SAMOptions::default(),
+			Some(deadline),
+		)?;
+		session.timeout = Some(timeout);
+		Self::open(&session, destination, 0, port, Some(deadline))
+	}
+
 	/// Create a new SAM client connection to the provided destination and port
 	/// using the provided session.
 	pub fn with_session(session: &Session, dest: &str, port: u16) -> Result<StreamConnect> {
-		let mut sam = SamConnection::connect(session.sam_api()?).unwrap();
-		let dest = sam.naming_lookup(dest)?;
+		Self::with_ports(session, dest, 0, port)
+	}
 
-		let mut stream_msg = format!(
-			"STREAM CONNECT ID={nickname} DESTINATION={destination} SILENT=false\n",
-			nickname = session.nickname,
-			destination = dest,
-		);
-		if port > 0 {
-			stream_msg.push_str(&format!(" TO_PORT={port}\n"));
-		} else {
-			stream_msg.push('\n');
-		}
+	/// Same as `with_session`, also setting the port the stream is opened
+	/// from. Zero leaves either port unset.
+	pub fn with_ports(
+		session: &Session,
+		dest: &str,
+		from_port: u16,
+		to_port: u16,
+	) -> Result<StreamConnect> {
+		Self::open(session, dest, from_port, to_port, session.deadline())
+	}
 
-		sam.send(stream_msg, sam_stream_status)?;
+	/// Same as `with_ports`, failing with [I2PError::SAMTimeout] if the
+	/// stream isn't opened within `timeout`.
+	pub fn with_ports_timeout(
+		session: &Session,
+		dest: &str,
+		from_port: u16,
+		to_port: u16,
+		timeout: Duration,
+	) -> Result<StreamConnect> {
+		Self::open(
+			session,
+			dest,
+			from_port,
+			to_port,
+			Some(Instant::now() + timeout),
+		)
+	}
+
+	fn open(
+		session: &Session,
+		dest: &str,
+		from_port: u16,
+		to_port: u16,
+		deadline: Option<Instant>,
+	) -> Result<StreamConnect> {
+		let shutdown = &session.shutdown;
+		let generation = session.generation();
+		let mut sam = shutdown.check(SamConnection::open(generation.sam_api, deadline))?;
+		let dest = shutdown.interruptible(sam.conn.try_clone()?, || {
+			sam.until(deadline, |sam| {
+				let dest = sam.naming_lookup(dest)?;
+				let stream_msg = stream_connect_msg(
+					&generation.nickname,
+					&dest,
+					from_port,
+					to_port,
+					session.silent,
+				);
+				if session.silent {
+					sam.send_silent(stream_msg)?;
+				} else {
+					sam.send(stream_msg, sam_stream_status)?;
+				}
+				Ok(dest)
+			})
+		})?;
 
 		Ok(StreamConnect {
 			sam,
 			session: session.duplicate()?,
 			peer_dest: dest,
-			peer_port: port,
-			local_port: 0,
+			peer_port: to_port,
+			local_port: from_port,
+			reset: Arc::default(),
+			generation,
 		})
 	}
 
@@ -308,6 +851,40 @@ impl StreamConnect {
 		self.sam.conn.shutdown(how).map_err(|e| e.into())
 	}
 
+	pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+		self.check_reset()?;
+		self.sam.conn.peek(buf).map_err(|e| self.map_io_error(e))
+	}
+
+	/// Restores the socket settings a user of the stream may have changed,
+	/// before the stream is handed to someone else.
+	pub(crate) fn reset_settings(&self) -> io::Result<()> {
+		let conn = &self.sam.conn;
+		conn.set_nonblocking(false)?;
+		conn.set_read_timeout(None)?;
+		conn.set_write_timeout(None)?;
+		conn.set_nodelay(false)
+	}
+
+	fn check_reset(&self) -> io::Result<()> {
+		if self.reset.load(Ordering::SeqCst) {
+			return Err(reset_error());
+		}
+		Ok(())
+	}
+
+	/// Reports resets, and every I/O from then on, as
+	/// [io::ErrorKind::ConnectionReset].
+	fn map_io_error(&self, err: io::Error) -> io::Error {
+		match err.kind() {
+			io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+				self.reset.store(true, Ordering::SeqCst);
+				reset_error()
+			}
+			_ => err,
+		}
+	}
+
 	pub fn duplicate(&self) -> Result<StreamConnect> {
 		Ok(StreamConnect {
 			sam: self.sam.duplicate()?,
@@ -315,6 +892,8 @@ impl StreamConnect {
 			peer_dest: self.peer_dest.clone(),
 			peer_port: self.peer_port,
 			local_port: self.local_port,
+			reset: self.reset.clone(),
+			generation: self.generation.clone(),
 		})
 	}
 	/// calls try_clone against the Session object
@@ -325,6 +904,15 @@ impl StreamConnect {
 	pub fn try_clone_sam(&self) -> std::io::Result<TcpStream> {
 		self.sam.try_clone()
 	}
+	/// Returns a handle keeping the session the stream was opened or
+	/// accepted on up, for handles of the stream such as
+	/// [to_tokio_stream](Self::to_tokio_stream) that don't.
+	#[cfg(all(feature = "http", feature = "tokio"))]
+	pub(crate) fn session_guard(&self) -> SessionGuard {
+		SessionGuard {
+			_generation: self.generation.clone(),
+		}
+	}
 	pub fn to_tokio_stream(&self) -> Result<tokio::net::TcpStream> {
 		self.set_nonblocking(true)?;
 		Ok(tokio::net::TcpStream::from_std(self.sam.conn.try_clone()?)?)
@@ -333,16 +921,18 @@ impl StreamConnect {
 
 impl Read for StreamConnect {
 	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-		self.sam.conn.read(buf)
+		self.check_reset()?;
+		self.sam.conn.read(buf).map_err(|e| self.map_io_error(e))
 	}
 }
 
 impl Write for StreamConnect {
 	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-		self.sam.conn.write(buf)
+		self.check_reset()?;
+		self.sam.conn.write(buf).map_err(|e| self.map_io_error(e))
 	}
 	fn flush(&mut self) -> io::Result<()> {
-		self.sam.conn.flush()
+		self.sam.conn.flush().map_err(|e| self.map_io_error(e))
 	}
 }
 
@@ -369,87 +959,284 @@ impl StreamForward {
 	}
 
 	pub fn accept(&self) -> Result<(StreamConnect, I2pSocketAddr)> {
-		let mut sam_conn = SamConnection::connect(self.session.sam_api()?).unwrap();
+		self.accept_until(None, None)
+	}
 
-		let accept_stream_msg = format!(
-			"STREAM ACCEPT ID={nickname} SILENT=false\n",
-			nickname = self.session.nickname,
-		);
-		sam_conn.send(accept_stream_msg, sam_stream_status)?;
+	/// Same as `accept`, failing with [I2PError::SAMTimeout] if no stream
+	/// comes in within `timeout`. The pending `STREAM ACCEPT` is then
+	/// cancelled.
+	pub fn accept_timeout(&self, timeout: Duration) -> Result<(StreamConnect, I2pSocketAddr)> {
+		self.accept_until(Some(Instant::now() + timeout), None)
+	}
 
-		let mut stream = StreamConnect {
-			sam: sam_conn,
-			session: self.session.duplicate()?,
-			peer_dest: "".to_string(),
-			// port only provided with SAM v3.2+ (not on i2pd)
-			peer_port: 0,
-			local_port: 0,
-		};
+	/// Same as `accept`, also failing with [I2PError::Shutdown] once `stop`
+	/// is shut down, which cancels the pending `STREAM ACCEPT` but leaves the
+	/// session up.
+	pub(crate) fn accept_stoppable(
+		&self,
+		stop: &ShutdownHandle,
+	) -> Result<(StreamConnect, I2pSocketAddr)> {
+		self.accept_until(None, Some(stop))
+	}
 
-		// TODO use a parser combinator, perhaps move down to sam.rs
-		let destination: String = {
-			let mut buf_read = io::BufReader::new(stream.duplicate()?);
-			let mut dest_line = String::new();
-			buf_read.read_line(&mut dest_line)?;
-			dest_line.split(' ').next().unwrap_or("").trim().to_string()
-		};
-		if destination.is_empty() {
-			return Err(
-				I2PError::SAMKeyNotFound("No b64 destination in accept".to_string()).into(),
-			);
+	fn accept_until(
+		&self,
+		deadline: Option<Instant>,
+		stop: Option<&ShutdownHandle>,
+	) -> Result<(StreamConnect, I2pSocketAddr)> {
+		loop {
+			// pending accepts don't keep a replaced session up
+			let generation = Arc::downgrade(&self.session.generation());
+			let header = match self.accept_on(&generation, deadline, stop) {
+				Ok(header) => header,
+				Err(e) if self.migrated(&generation) => {
+					debug!("accepting again on the new session after: {e}");
+					continue;
+				}
+				Err(e) => return Err(e),
+			};
+			match generation.upgrade() {
+				Some(generation) => return self.accepted(header.0, header.1, generation),
+				// the stream ended along with its session
+				None => continue,
+			}
 		}
+	}
 
-		let addr = I2pSocketAddr::new(I2pAddr::from_b64(&destination)?, 0);
-		stream.peer_dest = destination;
+	/// Accepts a stream on the session `generation`, failing if it is
+	/// replaced in the meantime.
+	fn accept_on(
+		&self,
+		generation: &Weak<Generation>,
+		deadline: Option<Instant>,
+		stop: Option<&ShutdownHandle>,
+	) -> Result<(SamConnection, Option<StreamHeader>)> {
+		let (nickname, replaced) = match generation.upgrade() {
+			Some(generation) => (generation.nickname.clone(), generation.replaced.clone()),
+			None => return Err(I2PError::Shutdown),
+		};
+		let shutdown = &self.session.shutdown;
+		let status_deadline = deadline.or_else(|| self.session.deadline());
+		let mut sam_conn = shutdown.check(SamConnection::open(
+			self.session.sam_api()?,
+			status_deadline,
+		))?;
 
-		Ok((stream, addr))
+		let silent = self.session.silent;
+		let socket = sam_conn.conn.try_clone()?;
+		let _stop = match stop {
+			Some(stop) => Some(stop.register_socket(&sam_conn.conn)?),
+			None => None,
+		};
+		let header = shutdown.interruptible(sam_conn.conn.try_clone()?, || {
+			replaced.interruptible(socket, || {
+				let accept_stream_msg = format!("STREAM ACCEPT ID={nickname} SILENT={silent}\n");
+				if silent {
+					sam_conn.send_silent(accept_stream_msg)?;
+					return sam_conn.until(deadline, |sam| match sam.conn.peek(&mut [0u8])? {
+						0 => Err(I2PError::SilentStreamClosed),
+						_ => Ok(None),
+					});
+				}
+				sam_conn.until(status_deadline, |sam| {
+					sam.send(accept_stream_msg, sam_stream_status)
+				})?;
+				sam_conn
+					.until(deadline, |sam| StreamHeader::read(&mut sam.conn))
+					.map(Some)
+			})
+		});
+		let header = match stop {
+			Some(stop) => stop.check(header)?,
+			None => header?,
+		};
+		Ok((sam_conn, header))
 	}
 
-	pub fn forward(&mut self, host: &str, port: &str) -> Result<(StreamConnect, I2pSocketAddr)> {
-		let forward_stream_msg = format!(
-			"STREAM FORWARD ID={nickname} PORT={port} HOST={host}",
-			nickname = self.session.nickname
-		);
-		let resp = self.session.sam.send(forward_stream_msg, sam_stream_status)?;
-		log::info!("resp {:#?}", resp);
-		let mut stream = StreamConnect {
-			sam: self.session.sam.duplicate()?,
+	/// Whether an accept on `generation` failed because the session was
+	/// replaced, rather than shut down.
+	fn migrated(&self, generation: &Weak<Generation>) -> bool {
+		let replaced = generation
+			.upgrade()
+			.is_none_or(|generation| generation.replaced.is_shutdown());
+		replaced && !self.session.shutdown.is_shutdown()
+	}
+
+	/// Builds an accepted stream, from its header unless it's silent.
+	fn accepted(
+		&self,
+		sam: SamConnection,
+		header: Option<StreamHeader>,
+		generation: Arc<Generation>,
+	) -> Result<(StreamConnect, I2pSocketAddr)> {
+		let (header, addr) = match header {
+			Some(header) => {
+				let addr = header.peer_addr()?;
+				(header, addr)
+			}
+			None => (
+				StreamHeader::default(),
+				I2pSocketAddr::new(I2pAddr::new(""), 0),
+			),
+		};
+
+		let stream = StreamConnect {
+			sam,
 			session: self.session.duplicate()?,
-			peer_dest: "".to_string(),
-			peer_port: 0,
-			local_port: 0,
+			peer_dest: header.dest,
+			// ports are only provided with SAM v3.2+, zero otherwise
+			peer_port: header.from_port,
+			local_port: header.to_port,
+			reset: Arc::default(),
+			generation,
 		};
+		Ok((stream, addr))
+	}
+
+	/// Asks the SAM bridge to forward incoming streams to `host:port`.
+	///
+	/// For each stream, the bridge opens a TCP connection to `host:port` and
+	/// writes a [StreamHeader] line before the stream's data. Forwarding
+	/// stops when the returned [Forwarding] is dropped.
+	///
+	/// Forwarded streams don't go through a listener, so the session's
+	/// access list and connection limits are left to the router: only
+	/// [I2pListener](crate::net::I2pListener) accepts and
+	/// [ServerTunnel](crate::tunnel::ServerTunnel) enforce them client-side.
+	pub fn forward(&self, host: &str, port: u16) -> Result<Forwarding> {
+		self.forward_with(host, port, false)
+	}
+
+	/// Same as `forward`, but the SAM bridge connects to `host:port` over
+	/// TLS (SAM 3.3).
+	pub fn forward_ssl(&self, host: &str, port: u16) -> Result<Forwarding> {
+		self.forward_with(host, port, true)
+	}
+
+	fn forward_with(&self, host: &str, port: u16, ssl: bool) -> Result<Forwarding> {
+		let target = Arc::new(ForwardTarget {
+			host: host.to_string(),
+			port,
+			ssl,
+			silent: self.session.silent,
+			renewed: Mutex::default(),
+		});
+		let (sam, registration) = open_forward(
+			&self.session.generation(),
+			&target,
+			self.session.deadline(),
+			&self.session.shutdown,
+		)?;
+		lock(&self.session.current.forwards).push(Arc::downgrade(&target));
+
+		Ok(Forwarding {
+			sam,
+			host: host.to_string(),
+			port,
+			registration,
+			target,
+		})
+	}
 
-		// TODO use a parser combinator, perhaps move down to sam.rs
-		let destination: String = {
-			let mut buf_read = io::BufReader::new(stream.duplicate()?);
-			let mut dest_line = String::new();
-			let mut from_port_line = String::new();
-			let mut to_port_line = String::new();
-			buf_read.read_line(&mut dest_line)?;
-			buf_read.read_line(&mut from_port_line)?;
-			buf_read.read_line(&mut to_port_line)?;
-			let dest_line = dest_line.split(' ').next().unwrap_or("").trim().to_string();
-			log::info!("dest_line({dest_line}) from_port_line({from_port_line}) to_port_line({to_port_line})");
-			dest_line
+	/// Forwards incoming streams to a local `listener`. When the listener is
+	/// bound to a wildcard address, the bridge is asked to connect to the
+	/// address this host uses to reach it.
+	pub fn forward_to(&self, listener: &TcpListener) -> Result<Forwarding> {
+		let addr = listener.local_addr()?;
+		let host = if addr.ip().is_unspecified() {
+			self.session.sam.conn.local_addr()?.ip()
+		} else {
+			addr.ip()
 		};
-		if destination.is_empty() {
-			return Err(
-				I2PError::SAMKeyNotFound("No b64 destination in accept".to_string()).into(),
-			);
-		}
+		self.forward(&host.to_string(), addr.port())
+	}
 
-		let addr = I2pSocketAddr::new(I2pAddr::from_b64(&destination)?, 0);
-		stream.peer_dest = destination;
+	/// Accepts a stream forwarded to `listener`, reading its header line.
+	/// Connections closed before sending one are skipped.
+	pub fn accept_forwarded(
+		&self,
+		listener: &TcpListener,
+	) -> Result<(StreamConnect, I2pSocketAddr)> {
+		self.accept_forwarded_until(listener, None, None)
+	}
 
-		Ok((stream, addr))
+	/// Same as `accept_forwarded`, failing with [I2PError::SAMTimeout] if no
+	/// stream comes in within `timeout`.
+	pub fn accept_forwarded_timeout(
+		&self,
+		listener: &TcpListener,
+		timeout: Duration,
+	) -> Result<(StreamConnect, I2pSocketAddr)> {
+		self.accept_forwarded_until(listener, Some(Instant::now() + timeout), None)
+	}
 
+	/// Same as `accept_forwarded`, also failing with [I2PError::Shutdown]
+	/// once `stop` is shut down.
+	pub(crate) fn accept_forwarded_stoppable(
+		&self,
+		listener: &TcpListener,
+		stop: &ShutdownHandle,
+	) -> Result<(StreamConnect, I2pSocketAddr)> {
+		self.accept_forwarded_until(listener, None, Some(stop))
+	}
+
+	fn accept_forwarded_until(
+		&self,
+		listener: &TcpListener,
+		deadline: Option<Instant>,
+		stop: Option<&ShutdownHandle>,
+	) -> Result<(StreamConnect, I2pSocketAddr)> {
+		let shutdown = &self.session.shutdown;
+		let waiter = Waiter::new(listener)?;
+		// wakes up the accept below once the deadline has passed
+		let _timer = deadline.map(|deadline| WakeTimer::start(&waiter, deadline));
+		loop {
+			let (conn, _) = {
+				let _registration = shutdown.register_waiter(&waiter)?;
+				let _stop = match stop {
+					Some(stop) => Some(stop.register_waiter(&waiter)?),
+					None => None,
+				};
+				let accepted = shutdown.check(listener.accept().map_err(|e| e.into()));
+				match stop {
+					Some(stop) => stop.check(accepted)?,
+					None => accepted?,
+				}
+			};
+			let Some(conn) = waiter.sort(conn) else {
+				if let Some(deadline) = deadline {
+					remaining(deadline)?;
+				}
+				continue;
+			};
+			let mut sam = SamConnection { conn };
+			if self.session.silent {
+				return self.accepted(sam, None, self.session.generation());
+			}
+			let header = shutdown.interruptible(sam.conn.try_clone()?, || {
+				sam.until(deadline, |sam| StreamHeader::read(&mut sam.conn))
+			});
+			match header {
+				Ok(header) => return self.accepted(sam, Some(header), self.session.generation()),
+				// e.g. a probe
+				Err(e) if is_eof(&e) => continue,
+				Err(e) => return Err(e),
+			}
+		}
 	}
 
 	pub fn local_addr(&self) -> Result<(String, u16)> {
 		Ok((self.session.local_dest.clone(), 0))
 	}
 
+	pub fn shutdown_handle(&self) -> ShutdownHandle {
+		self.session.shutdown_handle()
+	}
+
+	pub(crate) fn access_filter(&self) -> AccessFilter {
+		self.session.generation().access()
+	}
+
 	pub fn duplicate(&self) -> Result<StreamForward> {
 		Ok(StreamForward {
 			session: self.session.duplicate()?,
@@ -457,16 +1244,605 @@ impl StreamForward {
 	}
 }
 
+/// An active `STREAM FORWARD` registration.
+///
+/// The SAM bridge keeps forwarding incoming streams for as long as the
+/// connection the forward was requested on stays open, that is until this
+/// value is dropped.
+#[derive(Debug)]
+pub struct Forwarding {
+	#[cfg(feature = "public-conn")]
+	pub sam: SamConnection,
+	#[cfg(not(feature = "public-conn"))]
+	sam: SamConnection,
+	host: String,
+	port: u16,
+	/// Stops the forwarding when the session is shut down.
+	registration: Registration,
+	/// Holds the registration on the session replacing the one this
+	/// forward was requested on.
+	target: Arc<ForwardTarget>,
+}
+
+/// A forward registered again on a new session, with its control socket.
+type Forward = (Arc<ForwardTarget>, SamConnection, Registration);
+
+/// Where a [Forwarding] sends streams, registered again by
+/// [Session::update_options].
+#[derive(Debug)]
+struct ForwardTarget {
+	host: String,
+	port: u16,
+	ssl: bool,
+	silent: bool,
+	/// The registration on the latest session, if it was replaced.
+	renewed: Mutex<Option<(SamConnection, Registration)>>,
+}
+
+/// Asks the bridge to forward the streams of `generation` to `target`.
+fn open_forward(
+	generation: &Generation,
+	target: &ForwardTarget,
+	deadline: Option<Instant>,
+	shutdown: &ShutdownHandle,
+) -> Result<(SamConnection, Registration)> {
+	let mut sam = SamConnection::open(generation.sam_api, deadline)?;
+	let mut forward_stream_msg = format!(
+		"STREAM FORWARD ID={nickname} PORT={port} HOST={host} SILENT={silent}",
+		nickname = generation.nickname,
+		port = target.port,
+		host = target.host,
+		silent = target.silent,
+	);
+	if target.ssl {
+		forward_stream_msg.push_str(" SSL=true");
+	}
+	forward_stream_msg.push('\n');
+	sam.until(deadline, |sam| {
+		sam.send(forward_stream_msg, sam_stream_status)
+	})?;
+	let registration = shutdown.register_socket(&sam.conn)?;
+	Ok((sam, registration))
+}
+
+impl Forwarding {
+	/// Returns the host and port streams are forwarded to.
+	pub fn forward_addr(&self) -> (&str, u16) {
+		(&self.host, self.port)
+	}
+
+	/// Creates a new handle to the same registration. Forwarding stops once
+	/// every handle is dropped.
+	pub fn duplicate(&self) -> Result<Forwarding> {
+		let sam = self.sam.duplicate()?;
+		Ok(Forwarding {
+			registration: self.registration.duplicate(&sam.conn)?,
+			sam,
+			host: self.host.clone(),
+			port: self.port,
+			target: self.target.clone(),
+		})
+	}
+}
+
+/// Upper bound on the length of a [StreamHeader] line. Destinations with
+/// certificates stay well under 1 KiB in base64.
+const MAX_STREAM_HEADER_LEN: usize = 4096;
+
+/// Upper bound on the length of a reply from the SAM bridge.
+const MAX_REPLY_LEN: usize = 64 * 1024;
+
+fn reset_error() -> io::Error {
+	io::Error::new(io::ErrorKind::ConnectionReset, "stream reset by peer")
+}
+
+/// Returns the time left until `deadline`, failing once it has passed.
+fn remaining(deadline: Instant) -> Result<Duration> {
+	deadline
+		.checked_duration_since(Instant::now())
+		.filter(|timeout| !timeout.is_zero())
+		.ok_or_else(|| I2PError::SAMTimeout("deadline elapsed".to_string()))
+}
+
+/// Maps the I/O error of an operation that timed out to
+/// [I2PError::SAMTimeout].
+fn map_timeout(err: I2PError, what: &str) -> I2PError {
+	match err {
+		I2PError::Io(ref e)
+			if matches!(
+				e.kind(),
+				io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+			) =>
+		{
+			I2PError::SAMTimeout(what.to_string())
+		}
+		_ => err,
+	}
+}
+
+fn is_eof(err: &I2PError) -> bool {
+	matches!(err, I2PError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
+/// Connects to the first of `addr` reachable before `deadline`.
+fn connect_until<A: ToSocketAddrs>(addr: A, deadline: Instant) -> Result<TcpStream> {
+	let mut last_err = None;
+	for addr in addr.to_socket_addrs()? {
+		match TcpStream::connect_timeout(&addr, remaining(deadline)?) {
+			Ok(conn) => return Ok(conn),
+			Err(e) => last_err = Some(e),
+		}
+	}
+	let err = last_err.unwrap_or_else(|| io::Error::from(io::ErrorKind::InvalidInput));
+	Err(map_timeout(err.into(), "connecting to the SAM bridge"))
+}
+
+/// Reads a line, including its newline, a byte at a time so that nothing
+/// past it is consumed from `reader`.
+fn read_line<R: Read>(reader: &mut R, max_len: usize) -> Result<String> {
+	let mut line = Vec::new();
+	let mut byte = [0u8];
+	while line.last() != Some(&b'\n') {
+		if line.len() >= max_len {
+			return Err(I2PError::SAMInvalidMessage("line too long".to_string()));
+		}
+		if reader.read(&mut byte)? == 0 {
+			return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+		}
+		line.push(byte[0]);
+	}
+	Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// The line the SAM bridge writes ahead of the data of an accepted or
+/// forwarded stream: the peer's destination followed, since SAM 3.2, by
+/// `FROM_PORT` and `TO_PORT`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamHeader {
+	/// The peer's base64 destination.
+	pub dest: String,
+	pub from_port: u16,
+	pub to_port: u16,
+}
+
+impl StreamHeader {
+	/// Parses a header line, including its trailing newline.
+	pub fn parse(line: &str) -> Result<StreamHeader> {
+		let (_, (dest, opts)) = sam_stream_header(line).map_err(I2PError::from)?;
+		if dest.is_empty() {
+			return Err(I2PError::SAMKeyNotFound(
+				"No b64 destination in stream header".to_string(),
+			));
+		}
+		let mut header = StreamHeader {
+			dest: dest.to_string(),
+			from_port: 0,
+			to_port: 0,
+		};
+		for (key, value) in opts.unwrap_or_default() {
+			let port = || -> Result<u16> {
+				value
+					.parse()
+					.map_err(|_| I2PError::SAMInvalidMessage(line.trim_end().to_string()))
+			};
+			match key {
+				"FROM_PORT" => header.from_port = port()?,
+				"TO_PORT" => header.to_port = port()?,
+				_ => {}
+			}
+		}
+		Ok(header)
+	}
+
+	/// Reads and parses a header line from `reader`. The line is read a byte
+	/// at a time so none of the stream's data is consumed.
+	pub fn read<R: Read>(reader: &mut R) -> Result<StreamHeader> {
+		StreamHeader::parse(&read_line(reader, MAX_STREAM_HEADER_LEN)?)
+	}
+
+	/// Returns the peer's address, with its `.b32.i2p` name and port.
+	pub fn peer_addr(&self) -> Result<I2pSocketAddr> {
+		Ok(I2pSocketAddr::new(
+			I2pAddr::from_b64(&self.dest)?,
+			self.from_port,
+		))
+	}
+}
+
+fn stream_connect_msg(
+	nickname: &str,
+	dest: &str,
+	from_port: u16,
+	to_port: u16,
+	silent: bool,
+) -> String {
+	let mut msg = format!("STREAM CONNECT ID={nickname} DESTINATION={dest} SILENT={silent}");
+	if from_port > 0 {
+		msg.push_str(&format!(" FROM_PORT={from_port}"));
+	}
+	if to_port > 0 {
+		msg.push_str(&format!(" TO_PORT={to_port}"));
+	}
+	msg.push('\n');
+	msg
+}
+
 pub fn nickname() -> String {
 	format!("i2prs-{}", crate::utils::rand_string(8))
 }
 
-/*
-As of Rust version 1.26, it is possible to convert a String to &'static str without using unsafe code:
-This converts the String instance into a boxed str and immediately leaks it. This frees all excess capacity the string may currently occupy.
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock_bridge::MockBridge;
+	use crate::net::{I2pListener, I2pStream, BASE64_I2P};
+	use std::thread;
+
+	#[test]
+	fn stream_connect() {
+		assert_eq!(
+			stream_connect_msg("id", "dest", 0, 0, false),
+			"STREAM CONNECT ID=id DESTINATION=dest SILENT=false\n"
+		);
+		assert_eq!(
+			stream_connect_msg("id", "dest", 0, 80, false),
+			"STREAM CONNECT ID=id DESTINATION=dest SILENT=false TO_PORT=80\n"
+		);
+		assert_eq!(
+			stream_connect_msg("id", "dest", 1234, 80, false),
+			"STREAM CONNECT ID=id DESTINATION=dest SILENT=false FROM_PORT=1234 TO_PORT=80\n"
+		);
+		assert_eq!(
+			stream_connect_msg("id", "dest", 0, 0, true),
+			"STREAM CONNECT ID=id DESTINATION=dest SILENT=true\n"
+		);
+	}
+
+	#[test]
+	fn stream_header() {
+		let dest = BASE64_I2P.encode(&[0u8; 387]);
+		let header = StreamHeader::parse(&format!("{dest} FROM_PORT=1234 TO_PORT=80\n")).unwrap();
+		assert_eq!(
+			header,
+			StreamHeader {
+				dest: dest.clone(),
+				from_port: 1234,
+				to_port: 80,
+			}
+		);
+		assert_eq!(
+			header.peer_addr().unwrap(),
+			I2pSocketAddr::new(I2pAddr::from_b64(&dest).unwrap(), 1234)
+		);
+
+		let data = format!("{dest}\nHELLO");
+		let mut reader = data.as_bytes();
+		let header = StreamHeader::read(&mut reader).unwrap();
+		assert_eq!((header.from_port, header.to_port), (0, 0));
+		assert_eq!(reader, b"HELLO");
+		assert!(StreamHeader::read(&mut &b"no newline"[..]).is_err());
+
+		assert!(StreamHeader::parse("\n").is_err());
+		assert!(StreamHeader::parse(&format!("{dest} FROM_PORT=x\n")).is_err());
+	}
+
+	#[test]
+	fn timeouts() {
+		fn is_timeout<T>(res: Result<T>) -> bool {
+			matches!(res, Err(I2PError::SAMTimeout(_)))
+		}
+		let timeout = Duration::from_millis(200);
+		let bridge = MockBridge::start();
+		let start = Instant::now();
+
+		let create = Session::create_timeout(
+			bridge.addr(),
+			"TRANSIENT",
+			"stall-session",
+			SessionStyle::Stream,
+			SAMOptions::default(),
+			timeout,
+		);
+		assert!(is_timeout(create));
+
+		// no peer ever accepts on this bridge
+		let idle = MockBridge::start();
+		let mut session = Session::transient(idle.addr()).unwrap();
+		session.set_timeout(Some(timeout));
+		assert!(is_timeout(I2pStream::connect_with_session(
+			&session,
+			"nowhere.i2p:80"
+		)));
+
+		let session = Session::transient(bridge.addr()).unwrap();
+		// the bridge never replies to the lookup
+		assert!(is_timeout(I2pStream::connect_with_session_timeout(
+			&session,
+			"stall.i2p:80",
+			timeout
+		)));
+		let listener = I2pListener::bind_with_session(&session).unwrap();
+		assert!(is_timeout(listener.accept_timeout(timeout)));
+		let forwarding =
+			I2pListener::bind_forwarding_with_session(&session, "127.0.0.1:0").unwrap();
+		assert!(is_timeout(forwarding.accept_timeout(timeout)));
+		assert!(start.elapsed() < Duration::from_secs(2));
+
+		// the forwarding listener still accepts once a stream comes in
+		let accept = thread::spawn(move || forwarding.accept_timeout(Duration::from_secs(5)));
+		thread::sleep(timeout);
+		let _client = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		let (stream, _) = accept.join().unwrap().unwrap();
+		assert_eq!(stream.local_addr().unwrap().port(), 80);
+	}
+
+	#[test]
+	fn silent_streams() {
+		let bridge = MockBridge::start();
+		let mut session = Session::transient(bridge.addr()).unwrap();
+		session.set_silent(true);
+
+		let listener = I2pListener::bind_with_session(&session).unwrap();
+		let accept = thread::spawn(move || {
+			let (first, addr) = listener.accept().unwrap();
+			assert_eq!(addr, I2pSocketAddr::new(I2pAddr::new(""), 0));
+			(first, listener.accept())
+		});
+		let mut client = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		client.write_all(b"hi").unwrap();
+		// a stream closed before any data fails the accept
+		while bridge.commands("STREAM ACCEPT").len() < 2 {
+			thread::sleep(Duration::from_millis(10));
+		}
+		drop(I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap());
+		let (mut first, second) = accept.join().unwrap();
+		let mut buf = [0u8; 2];
+		first.read_exact(&mut buf).unwrap();
+		assert_eq!(&buf, b"hi");
+		assert!(matches!(second, Err(I2PError::SilentStreamClosed)));
+		assert!(bridge
+			.commands("STREAM")
+			.iter()
+			.all(|c| c.contains("SILENT=true")));
+
+		// forwarded connections carry no header line
+		let forwarding =
+			I2pListener::bind_forwarding_with_session(&session, "127.0.0.1:0").unwrap();
+		let mut client = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		client.write_all(b"hi").unwrap();
+		let (mut stream, _) = forwarding.accept().unwrap();
+		stream.read_exact(&mut buf).unwrap();
+		assert_eq!(&buf, b"hi");
+
+		// a failed connect closes the stream without a status
+		let idle = MockBridge::start();
+		let mut session = Session::transient(idle.addr()).unwrap();
+		session.set_silent(true);
+		let mut stream = I2pStream::connect_with_session(&session, "nowhere.i2p:80").unwrap();
+		assert_eq!(stream.read(&mut buf).unwrap(), 0);
+	}
+
+	#[test]
+	fn half_close() {
+		let bridge = MockBridge::start();
+		let session = Session::transient(bridge.addr()).unwrap();
+		let listener = I2pListener::bind_with_session(&session).unwrap();
+		let server = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let mut request = Vec::new();
+			stream.read_to_end(&mut request).unwrap();
+			stream.write_all(b"pong").unwrap();
+			stream.flush().unwrap();
+			request
+		});
+
+		let mut client = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		client.write_all(b"ping").unwrap();
+		client.flush().unwrap();
+		client.shutdown(Shutdown::Write).unwrap();
+		// the server only answers once it has seen the end of the request
+		let mut response = Vec::new();
+		client.read_to_end(&mut response).unwrap();
+		assert_eq!(server.join().unwrap(), b"ping");
+		assert_eq!(response, b"pong");
+		assert_eq!(
+			client.write(b"more").unwrap_err().kind(),
+			io::ErrorKind::BrokenPipe
+		);
+	}
+
+	#[test]
+	fn transient_signature_type() {
+		let bridge = MockBridge::start();
+		let options = SAMOptions::builder()
+			.signature_type(SignatureType::RedDsaSha512Ed25519)
+			.build()
+			.unwrap();
+		Session::create(
+			bridge.addr(),
+			"TRANSIENT",
+			&nickname(),
+			SessionStyle::Stream,
+			options.clone(),
+		)
+		.unwrap();
+		Session::create(
+			bridge.addr(),
+			&MockBridge::dest(),
+			&nickname(),
+			SessionStyle::Stream,
+			options,
+		)
+		.unwrap();
+		let creates = bridge.commands("SESSION CREATE");
+		assert!(creates[0].contains(" SIGNATURE_TYPE=RedDSA_SHA512_Ed25519 "));
+		assert!(!creates[1].contains("SIGNATURE_TYPE"));
+	}
+
+	#[test]
+	fn update_options() {
+		let bridge = MockBridge::start();
+		let mut session = Session::transient(bridge.addr()).unwrap();
+		let old = session.nickname.clone();
+		let listener = I2pListener::bind_with_session(&session).unwrap();
+		let accept = thread::spawn(move || {
+			(0..2)
+				.map(|_| listener.accept().unwrap().0)
+				.collect::<Vec<_>>()
+		});
+		bridge.wait_accepts(&old, 1);
+		let first = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		bridge.wait_accepts(&old, 1);
+
+		let options = SAMOptions::builder().inbound_length(1).build().unwrap();
+		session.update_options(options.clone()).unwrap();
+		assert_ne!(session.nickname, old);
+		let creates = bridge.commands("SESSION CREATE");
+		assert_eq!(creates.len(), 2);
+		assert!(creates[0].contains("DESTINATION=TRANSIENT SIGNATURE_TYPE=EdDSA_SHA512_Ed25519 "));
+		// the keys are reused, so their type is not given again
+		assert!(!creates[1].contains("SIGNATURE_TYPE"));
+		assert!(creates[1].contains(&format!("ID={}", session.nickname)));
+		assert!(creates[1].contains(&format!("DESTINATION={}", MockBridge::dest())));
+		assert!(creates[1].contains("inbound.length=1"));
+		// the listener accepts on the new session, the old one is up until
+		// its streams are closed
+		bridge.wait_accepts(&session.nickname, 1);
+		let second = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		let accepted = accept.join().unwrap();
+		assert!(bridge.is_live(&old));
+		drop((first, accepted));
+		bridge.wait_destroyed(&old);
+		assert!(bridge.is_live(&session.nickname));
+		drop(second);
+
+		// the access list is applied without recreating the session
+		let nickname = session.nickname.clone();
+		let peer = I2pAddr::from_b64(&MockBridge::dest()).unwrap();
+		let options = SAMOptions::builder()
+			.inbound_length(1)
+			.deny(vec![peer])
+			.build()
+			.unwrap();
+		session.update_options(options).unwrap();
+		assert_eq!(session.nickname, nickname);
+		assert_eq!(bridge.commands("SESSION CREATE").len(), 2);
+
+		let mut invalid = SAMOptions::default();
+		invalid.set("inbound.length", "9").unwrap();
+		assert!(session.update_options(invalid).is_err());
+		let mut primary = Session::primary(bridge.addr(), "TRANSIENT").unwrap();
+		let mut subsession = primary
+			.add_subsession(SessionStyle::Stream, "sub", "FROM_PORT=80")
+			.unwrap();
+		for session in [&mut primary, &mut subsession] {
+			let err = session.update_options(SAMOptions::default()).unwrap_err();
+			assert!(matches!(err, I2PError::OptionsUpdateUnsupported(_)));
+		}
+	}
+
+	#[test]
+	fn update_options_exclusive_destination() {
+		let bridge = MockBridge::start();
+		let mut session = Session::create(
+			bridge.addr(),
+			"exclusive-keys",
+			"exclusive",
+			SessionStyle::Stream,
+			SAMOptions::default(),
+		)
+		.unwrap();
+		let forwarding =
+			I2pListener::bind_forwarding_with_session(&session, "127.0.0.1:0").unwrap();
+		let _client = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		let (_stream, _) = forwarding.accept().unwrap();
+
+		// the old session is closed first, despite its streams
+		let options = SAMOptions::builder().inbound_length(1).build().unwrap();
+		session.update_options(options).unwrap();
+		bridge.wait_destroyed("exclusive");
+		let creates = bridge.commands("SESSION CREATE");
+		assert_eq!(creates.len(), 3);
+		assert!(creates[2].contains("DESTINATION=exclusive-keys"));
+
+		// streams are forwarded from the new session
+		let forwards = bridge.commands("STREAM FORWARD");
+		assert_eq!(forwards.len(), 2);
+		assert!(forwards[1].contains(&format!("ID={}", session.nickname)));
+		let _client = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		assert!(forwarding.accept_timeout(Duration::from_secs(5)).is_ok());
+	}
+
+	#[test]
+	fn update_options_restores_session() {
+		let bridge = MockBridge::start();
+		let mut session = Session::create(
+			bridge.addr(),
+			"exclusive-restored",
+			"restored",
+			SessionStyle::Stream,
+			SAMOptions::default(),
+		)
+		.unwrap();
+		let forwarding =
+			I2pListener::bind_forwarding_with_session(&session, "127.0.0.1:0").unwrap();
+
+		// the bridge refuses the new session once the old one is closed
+		let refused = SAMOptions::builder().nickname("refused").build().unwrap();
+		let err = session.update_options(refused).unwrap_err();
+		assert!(matches!(err, I2PError::SAMI2PError(_)), "{:?}", err);
+		bridge.wait_destroyed("restored");
+		let creates = bridge.commands("SESSION CREATE");
+		assert_eq!(creates.len(), 4);
+		assert!(creates[2].contains("inbound.nickname=refused"));
+		assert!(!creates[3].contains("refused"));
+		assert!(bridge.is_live(&session.nickname));
+		assert_eq!(
+			session.generation().options().pairs(),
+			SAMOptions::default().pairs()
+		);
 
-Note that there are almost always solutions that are preferable over leaking objects, e.g. using the crossbeam crate if you want to share state between threads.
-*/
-fn string_to_static_str(s: String) -> &'static str {
-	Box::leak(s.into_boxed_str())
+		// the session is back, with its forwards
+		let _client = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		assert!(forwarding.accept_timeout(Duration::from_secs(5)).is_ok());
+	}
+
+	#[test]
+	fn reset() {
+		let bridge = MockBridge::start();
+		let session = Session::transient(bridge.addr()).unwrap();
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let conn = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (peer, _) = listener.accept().unwrap();
+		let mut stream = StreamConnect {
+			sam: SamConnection { conn },
+			session: session.duplicate().unwrap(),
+			peer_dest: String::new(),
+			peer_port: 0,
+			local_port: 0,
+			reset: Arc::default(),
+			generation: session.generation(),
+		};
+		let mut duplicate = stream.duplicate().unwrap();
+
+		// closing a socket with unread data resets the connection
+		stream.write_all(b"unread").unwrap();
+		thread::sleep(Duration::from_millis(50));
+		drop(peer);
+		thread::sleep(Duration::from_millis(50));
+		let mut buf = [0u8; 4];
+		assert_eq!(
+			stream.read(&mut buf).unwrap_err().kind(),
+			io::ErrorKind::ConnectionReset
+		);
+		// the socket alone would report an orderly close from now on
+		assert_eq!(
+			stream.read(&mut buf).unwrap_err().kind(),
+			io::ErrorKind::ConnectionReset
+		);
+		assert_eq!(
+			duplicate.write(b"x").unwrap_err().kind(),
+			io::ErrorKind::ConnectionReset
+		);
+	}
 }
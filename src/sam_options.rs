@@ -2,17 +2,53 @@
 //! I2CP client and router options taken from https://geti2p.net/en/docs/protocol/i2cp
 //! SAMv3 options taken from https://geti2p.net/en/docs/api/samv3#options
 
+use crate::error::Result;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
 use std::str::FromStr;
+use std::time::Duration;
 
+use log::debug;
+use serde::de::{self, Deserializer};
+use serde::ser::{SerializeMap, Serializer};
 use serde::{Deserialize, Serialize};
+use thiserror::Error as ThisError;
+
+use crate::error::I2PError;
+use crate::net::{I2pAddr, B32_EXT, BASE32_I2P, BASE64_I2P};
+
+/// Valid tunnel lengths, in hops.
+const TUNNEL_LENGTH: RangeInclusive<u8> = 0..=7;
+/// Valid tunnel quantities, numbers above 6 need a router from 0.9 or later.
+const TUNNEL_QUANTITY: RangeInclusive<u8> = 1..=16;
+const TUNNEL_BACKUP_QUANTITY: RangeInclusive<u8> = 0..=16;
+const TUNNEL_LENGTH_VARIANCE: RangeInclusive<i8> = -7..=7;
+const TUNNEL_IP_RESTRICTION: RangeInclusive<u8> = 0..=4;
+const OUTBOUND_PRIORITY: RangeInclusive<i8> = -25..=25;
+/// Minimum idle time (ms) before reducing tunnels or closing the session.
+const MIN_IDLE_TIME: u64 = 300_000;
+/// Valid streaming window sizes, in messages.
+const STREAMING_WINDOW_SIZE: RangeInclusive<u32> = 1..=128;
 
 /// options used when interacting with the SAM bridge
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Serialized as a map of the SAM and I2CP option names, e.g.
+/// `inbound.length`, to their values, see also [SAMOptions::from_pairs].
+#[derive(Debug, Clone)]
 pub struct SAMOptions {
 	pub from_port: Option<u16>,
 	pub to_port: Option<u16>,
 	pub i2cp_options: Option<I2CPOptions>,
+	pub streaming_options: Option<StreamingOptions>,
+	/// The type of the keys the bridge generates for `TRANSIENT` sessions.
+	/// Sessions created from existing keys keep the type of those keys.
 	pub signature_type: SignatureType,
+	/// Options without a typed field, passed to the SAM bridge as is. They
+	/// are merged with the typed options when serialized, and
+	/// [SAMOptions::validate] checks that they don't conflict.
+	pub extra: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -103,7 +139,11 @@ pub struct I2CPClientOptions {
 	/// Router hostname. If the client is running in the same JVM as a router, this option is ignored, and the client connects to that router internally
 	pub tcp_host: Option<String>,
 	/// Router I2CP port. If the client is running in the same JVM as a router, this option is ignored, and the client connects to that router internally.
-	pub tcp_port: Option<u8>,
+	pub tcp_port: Option<u16>,
+	/// The signature type of the destination, when the client creates it.
+	pub destination_sig_type: Option<SignatureType>,
+	/// The clients allowed to read an encrypted LS2 with per-client authentication. See proposal 123.
+	pub lease_set_clients: Option<Vec<LeaseSetClient>>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -134,6 +174,8 @@ pub struct I2CPTunnelInboundOptions {
 	pub quantity: Option<u8>,
 	/// Used for consistent peer ordering across restarts.
 	pub random_key: Option<String>,
+	/// Name of the tunnels, e.g. in the router console.
+	pub nickname: Option<String>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -154,15 +196,107 @@ pub struct I2CPTunnelOutboundOptions {
 	pub quantity: Option<u8>,
 	/// Used for consistent peer ordering across restarts.
 	pub random_key: Option<String>,
+	/// Name of the tunnels, e.g. in the router console.
+	pub nickname: Option<String>,
+}
+
+/// Options of the streaming library, see https://geti2p.net/en/docs/api/streaming#options
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct StreamingOptions {
+	/// Whether to respond to incoming pings
+	pub answer_pings: Option<bool>,
+	/// (ms) How long to wait for data to send along with the SYN of a new connection, -1 to send the SYN right away
+	pub connect_delay: Option<i32>,
+	/// (ms) How long to wait for a connection to be established
+	pub connect_timeout: Option<u32>,
+	/// Whether to reject incoming messages of other protocols than streaming
+	pub enforce_protocol: Option<bool>,
+	/// What to do once a connection was idle for the inactivity timeout
+	pub inactivity_action: Option<InactivityAction>,
+	/// (ms) Idle time before the inactivity action
+	pub inactivity_timeout: Option<u32>,
+	/// Number of messages sent before waiting for an ACK
+	pub initial_window_size: Option<u32>,
+	/// Maximum number of streams open at once, -1 for no limit
+	pub max_concurrent_streams: Option<i32>,
+	/// Incoming connections allowed from a single peer per minute, 0 for no limit
+	pub max_conns_per_minute: Option<u32>,
+	/// Incoming connections allowed from a single peer per hour, 0 for no limit
+	pub max_conns_per_hour: Option<u32>,
+	/// Incoming connections allowed from a single peer per day, 0 for no limit
+	pub max_conns_per_day: Option<u32>,
+	/// Incoming connections allowed in total per minute, 0 for no limit
+	pub max_total_conns_per_minute: Option<u32>,
+	/// Incoming connections allowed in total per hour, 0 for no limit
+	pub max_total_conns_per_hour: Option<u32>,
+	/// Incoming connections allowed in total per day, 0 for no limit
+	pub max_total_conns_per_day: Option<u32>,
+	/// Maximum size of the payload of a message, in bytes
+	pub max_message_size: Option<u32>,
+	/// Maximum number of messages sent before waiting for an ACK, at most 128
+	pub max_window_size: Option<u32>,
+	/// Interactive is not supported by the Java router, which uses bulk
+	pub profile: Option<StreamingProfile>,
+	/// Only accept connections from the destinations of the access list
+	pub enable_access_list: Option<bool>,
+	/// Refuse connections from the destinations of the access list, unless enable_access_list is set
+	pub enable_black_list: Option<bool>,
+	/// The destinations allowed or refused, see enable_access_list and enable_black_list
+	pub access_list: Option<AccessList>,
+}
+
+/// Destinations allowed or refused to connect, set as `i2cp.accessList`.
+///
+/// Given as B32 addresses, and sent to the router as the base 64 hashes of
+/// the destinations.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessList(Vec<I2pAddr>);
+
+/// What to do with a connection idle for `i2p.streaming.inactivityTimeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum InactivityAction {
+	Nothing = 0,
+	Disconnect = 1,
+	/// Send a duplicate ACK, keeping the connection alive
+	Send = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum StreamingProfile {
+	Bulk = 1,
+	Interactive = 2,
+}
+
+/// A client allowed to read an encrypted LS2 with per-client
+/// authentication, set as `i2cp.leaseSetClient.dh.N` or
+/// `i2cp.leaseSetClient.psk.N`. See proposal 123.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LeaseSetClient {
+	/// Authenticated by DH with its base 64 X25519 public key
+	Dh { name: String, public_key: String },
+	/// Authenticated by a pre-shared base 64 X25519 private key
+	Psk { name: String, private_key: String },
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 /// The base 64 of the offline signature. See proposal 123.
 pub struct LeaseSetOfflineSignature(String);
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 /// The encryption type to be used, as of 0.9.38. Interpreted client-side, but also passed to the router in the SessionConfig, to declare intent and check support. As of 0.9.39, may be comma-separated values for multiple types. See PublicKey in common strutures spec for values. See proposals 123, 144, and 145.
 /// https://doc.rust-lang.org/book/ch19-03-advanced-traits.html#using-the-newtype-pattern-to-implement-external-traits-on-external-types
-pub struct LeaseSetEncType(String);
+pub struct LeaseSetEncType(Vec<EncType>);
+
+/// A leaseset encryption type, see PublicKey in the common structures spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(u16)]
+pub enum EncType {
+	/// ElGamal-2048, the legacy type
+	ElGamal = 0,
+	/// ECIES-X25519-AEAD-Ratchet, as of 0.9.46
+	EciesX25519 = 4,
+}
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 /// A base 64 X25519 private key for the router to use to decrypt the encrypted LS2 locally, only if per-client authentication is enabled. Optionally preceded by the key type and ':'. Only "ECIES_X25519:" is supported, which is the default. See proposal 123. Do not confuse with i2cp.leaseSetPrivateKey which is for the leaseset encryption keys.
 /// https://doc.rust-lang.org/book/ch19-03-advanced-traits.html#using-the-newtype-pattern-to-implement-external-traits-on-external-types
@@ -186,24 +320,32 @@ pub struct LeaseSetTransientPublicKey(String);
 pub struct LeaseSetSigningPrivateKey(String);
 
 /// The expiration of the offline signature, 4 bytes, seconds since the epoch. See proposal 123.
-pub type LeaseSetOfflineExpiration = [u8; 4];
-#[derive(Debug, Clone, Serialize, Deserialize)]
+pub type LeaseSetOfflineExpiration = u32;
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
 /// The type of leaseset to be sent in the CreateLeaseSet2 Message. Interpreted client-side, but also passed to the router in the SessionConfig, to declare intent and check support. See proposal 123.
-pub struct LeaseSetType(u8);
+pub enum LeaseSetType {
+	#[default]
+	LeaseSet = 1,
+	LeaseSet2 = 3,
+	EncryptedLeaseSet2 = 5,
+	MetaLeaseSet = 7,
+}
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 /// The sig type of the blinded key for encrypted LS2. Default depends on the destination sig type. See proposal 123.
-pub struct LeaseSetBlindedType(u16);
+pub struct LeaseSetBlindedType(pub SignatureType);
 
 /// The type of authentication for encrypted LS2. 0 for no per-client authentication (the default); 1 for DH per-client authentication; 2 for PSK per-client authentication. See proposal 123.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[repr(u64)]
 pub enum LeaseSetAuthType {
+	#[default]
 	NoPerClient = 0_u64,
 	DHPerClient = 1_u64,
 	PSKPerClient = 2_u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[repr(u16)]
 pub enum SignatureType {
 	/// Legacy Router Identities and Destinations, never explicitly set
@@ -220,6 +362,7 @@ pub enum SignatureType {
 	/// offline only, never used in key  certificates for router identities or destinations
 	RsaSha512_4096 = 6,
 	/// recent router identities and destinations
+	#[default]
 	EdDsaSha512Ed25519 = 7,
 	/// offline only, never used in key  certificates for router identities or destinations
 	EdDsaSha512Ed25519ph = 8,
@@ -227,10 +370,11 @@ pub enum SignatureType {
 	RedDsaSha512Ed25519 = 11,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 /// Guaranteed is disabled; None implemented in 0.8.1; the streaming lib default is None as of 0.8.1, the client side default is None as of 0.9.4
 pub enum MessageReliability {
 	BestEffort,
+	#[default]
 	None,
 }
 
@@ -256,7 +400,9 @@ impl Default for SAMOptions {
 					..Default::default()
 				}),
 			}),
+			streaming_options: None,
 			signature_type: SignatureType::EdDsaSha512Ed25519,
+			extra: BTreeMap::new(),
 		}
 	}
 }
@@ -270,435 +416,1888 @@ impl SAMOptions {
 	}
 }
 
-impl I2CPOptions {
-	pub fn string(&self) -> String {
-		let mut options = String::default();
-		if let Some(router_options) = &self.router_options {
-			let router_options_str = router_options.string();
-			if !router_options_str.is_empty() {
-				options.push_str(router_options_str.as_str());
+/// An option that is malformed or out of its valid range.
+#[derive(Debug, Clone, PartialEq, Eq, ThisError)]
+#[error("{key}={value}: {reason}")]
+pub struct InvalidOption {
+	/// The I2CP or SAM name of the option, e.g. `inbound.length`.
+	pub key: String,
+	pub value: String,
+	pub reason: String,
+}
+
+impl InvalidOption {
+	pub fn new(key: &str, value: &str, reason: &str) -> InvalidOption {
+		InvalidOption {
+			key: key.to_string(),
+			value: value.to_string(),
+			reason: reason.to_string(),
+		}
+	}
+}
+
+impl From<InvalidOption> for I2PError {
+	fn from(err: InvalidOption) -> I2PError {
+		I2PError::InvalidOptions(vec![err])
+	}
+}
+
+/// Collects the options failing validation.
+#[derive(Default)]
+struct Validation(Vec<InvalidOption>);
+
+impl Validation {
+	fn range<T>(&mut self, key: &str, value: Option<T>, range: RangeInclusive<T>)
+	where
+		T: PartialOrd + fmt::Display,
+	{
+		if let Some(value) = value {
+			if !range.contains(&value) {
+				let reason = format!("out of range {}..={}", range.start(), range.end());
+				self.0
+					.push(InvalidOption::new(key, &value.to_string(), &reason));
 			}
 		}
-		if let Some(client_options) = &self.client_options {
-			let client_options_str = client_options.string();
-			if !client_options_str.is_empty() {
-				options.push_str(client_options_str.as_str());
+	}
+
+	fn min_idle_time(&mut self, key: &str, value: Option<u64>) {
+		if let Some(value) = value {
+			if value < MIN_IDLE_TIME {
+				let reason = format!("below the minimum of {MIN_IDLE_TIME} ms");
+				self.0
+					.push(InvalidOption::new(key, &value.to_string(), &reason));
 			}
 		}
-		options
 	}
-}
 
-impl I2CPRouterOptions {
-	pub fn string(&self) -> String {
-		let mut options = String::default();
-		if let Some(client_message_timeout) = &self.client_message_timeout {
-			options.push_str(&format!("clientMessageTimeout={client_message_timeout} "));
+	/// Checks that options set more than once have the same value.
+	fn conflicts(&mut self, pairs: Vec<(String, String)>) {
+		let mut values = BTreeMap::new();
+		for (key, value) in pairs {
+			match values.get(&key) {
+				Some(first) if *first != value => {
+					let reason = format!("conflicts with {key}={first}");
+					self.0.push(InvalidOption::new(&key, &value, &reason));
+				}
+				Some(_) => {}
+				None => {
+					values.insert(key, value);
+				}
+			}
 		}
-		if let Some(crypto_options) = &self.crypto_options {
-			let crypto_options_str = crypto_options.string();
-			if !crypto_options_str.is_empty() {
-				options.push_str(crypto_options_str.as_str());
+	}
+
+	/// Checks that the extra options can be sent to the SAM bridge, and that
+	/// those with a typed field parse. Returns `options` with these extras
+	/// moved to their typed fields, so that their ranges get checked too.
+	fn extra(&mut self, options: &SAMOptions) -> SAMOptions {
+		let mut typed = options.clone();
+		typed.extra.clear();
+		for (key, value) in &options.extra {
+			let special = |c: char| c.is_whitespace() || matches!(c, '"' | '=');
+			if key.is_empty() || key.contains(special) {
+				self.0
+					.push(InvalidOption::new(key, value, "not a valid option name"));
+			} else if let Err(err) = typed.set_option(key, value) {
+				self.0.push(err);
 			}
 		}
-		if let Some(dont_publish_lease_set) = &self.dont_publish_lease_set {
-			options.push_str(&format!(
-				"i2cp.dontPublishLeaseSet={dont_publish_lease_set} "
-			));
+		typed
+	}
+
+	fn enc_type(&mut self, value: Option<&LeaseSetEncType>) {
+		if let Some(value) = value {
+			let types = value.types();
+			let duplicate = types
+				.iter()
+				.enumerate()
+				.any(|(i, t)| types[..i].contains(t));
+			if types.is_empty() || duplicate {
+				self.0.push(InvalidOption::new(
+					"i2cp.leaseSetEncType",
+					&value.to_string(),
+					"empty or repeated encryption types",
+				));
+			}
 		}
-		if let Some(fast_receive) = &self.fast_receive {
-			options.push_str(&format!("i2cp.fastReceive={fast_receive} "));
+	}
+}
+
+impl SAMOptions {
+	/// Returns a builder starting from the default options.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use i2p::sam_options::{EncType, SAMOptions};
+	///
+	/// let options = SAMOptions::builder()
+	///     .inbound_length(2)
+	///     .outbound_length(2)
+	///     .inbound_quantity(3)
+	///     .lease_set_enc_type(vec![EncType::EciesX25519])
+	///     .build()
+	///     .unwrap();
+	///
+	/// assert!(SAMOptions::builder().inbound_length(8).build().is_err());
+	/// ```
+	pub fn builder() -> SAMOptionsBuilder {
+		SAMOptionsBuilder::default()
+	}
+
+	/// Preset for destinations that must be hard to deanonymize, at the cost
+	/// of latency and bandwidth: 3 or 4 hop tunnels, 3 of them and a backup
+	/// each way, kept up while idle.
+	///
+	/// Presets return a builder, so any of their options can be overridden.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use i2p::sam_options::SAMOptions;
+	///
+	/// let options = SAMOptions::high_anonymity()
+	///     .inbound_quantity(4)
+	///     .build()
+	///     .unwrap();
+	/// assert_eq!(options.pairs()["inbound.length"], "3");
+	/// assert_eq!(options.pairs()["inbound.quantity"], "4");
+	/// ```
+	pub fn high_anonymity() -> SAMOptionsBuilder {
+		SAMOptions::builder().tunnels(3, 1, 3, 1).always_up()
+	}
+
+	/// Preset matching the router defaults, stated so that they don't depend
+	/// on the router: 3 hop tunnels, 2 of them each way, kept up while idle.
+	pub fn balanced() -> SAMOptionsBuilder {
+		SAMOptions::builder().tunnels(3, 0, 2, 0).always_up()
+	}
+
+	/// Preset trading anonymity for round trip time: 1 hop tunnels, 2 of
+	/// them and 2 backups each way, kept up while idle, and the interactive
+	/// streaming profile.
+	pub fn low_latency() -> SAMOptionsBuilder {
+		SAMOptions::builder()
+			.tunnels(1, 0, 2, 2)
+			.always_up()
+			.streaming_profile(StreamingProfile::Interactive)
+	}
+
+	/// Preset for moving large amounts of data: 2 hop tunnels, 5 of them and
+	/// a backup each way to spread the load, and the bulk streaming profile.
+	/// Tunnels are reduced to 2 after 10 minutes without activity.
+	pub fn bulk_transfer() -> SAMOptionsBuilder {
+		SAMOptions::builder()
+			.tunnels(2, 0, 5, 1)
+			.reduce_on_idle(Duration::from_secs(10 * 60), 2)
+			.keep_open()
+			.streaming_profile(StreamingProfile::Bulk)
+	}
+
+	/// Preset for chat-like traffic of small messages: 2 hop tunnels, 3 of
+	/// them and a backup each way, kept up while idle, and the interactive
+	/// streaming profile.
+	pub fn interactive() -> SAMOptionsBuilder {
+		SAMOptions::builder()
+			.tunnels(2, 0, 3, 1)
+			.always_up()
+			.streaming_profile(StreamingProfile::Interactive)
+	}
+
+	/// Preset for clients idle most of the time: 3 hop tunnels, 2 of them
+	/// each way, reduced to 1 after 20 minutes without activity. The session
+	/// is closed after 30 minutes without activity, so servers shouldn't
+	/// use it as is.
+	pub fn idle_friendly() -> SAMOptionsBuilder {
+		SAMOptions::builder()
+			.tunnels(3, 0, 2, 0)
+			.reduce_on_idle(Duration::from_secs(20 * 60), 1)
+			.close_on_idle(Duration::from_secs(30 * 60))
+	}
+
+	/// Checks the options against their valid ranges, and the router and
+	/// client options against each other, failing with
+	/// [I2PError::InvalidOptions] listing every invalid one. Sessions are
+	/// only created with valid options.
+	pub fn validate(&self) -> Result<()> {
+		let mut validation = Validation::default();
+		validation.conflicts(self.all_pairs());
+		let typed = validation.extra(self);
+		if let Some(i2cp_options) = &typed.i2cp_options {
+			if let Some(router_options) = &i2cp_options.router_options {
+				router_options.validate(&mut validation);
+			}
+			if let Some(client_options) = &i2cp_options.client_options {
+				client_options.validate(&mut validation);
+			}
 		}
-		if let Some(lease_set_auth_type) = &self.lease_set_auth_type {
-			options.push_str(&format!(
-				"i2cp.leaseSetAuthType={} ",
-				lease_set_auth_type.to_string()
-			));
+		if let Some(streaming_options) = &typed.streaming_options {
+			streaming_options.validate(&mut validation);
 		}
-		if let Some(lease_set_enc_type) = &self.lease_set_enc_type {
-			options.push_str(&format!(
-				"i2cp.leaseSetEncType={} ",
-				lease_set_enc_type.to_string()
-			));
+		if validation.0.is_empty() {
+			Ok(())
+		} else {
+			Err(I2PError::InvalidOptions(validation.0))
 		}
-		if let Some(lease_set_offline_expiration) = &self.lease_set_offline_expiration {
-			options.push_str(&format!(
-				"i2cp.leaseSetOfflineExpiration={} ",
-				String::from_utf8(lease_set_offline_expiration[..].to_vec()).unwrap()
-			))
-		}
-		if let Some(lease_set_priv_key) = &self.lease_set_priv_key {
-			options.push_str(&format!(
-				"i2cp.leaseSetPrivKey={} ",
-				lease_set_priv_key.to_string()
-			))
-		}
-		if let Some(lease_set_secret) = &self.lease_set_secret {
-			options.push_str(&format!(
-				"i2cp.leaseSetSecret={} ",
-				lease_set_secret.to_string()
-			));
+	}
+}
+
+impl I2CPRouterOptions {
+	fn validate(&self, validation: &mut Validation) {
+		validation.enc_type(self.lease_set_enc_type.as_ref());
+		if let Some(inbound) = &self.inbound {
+			validation.range("inbound.length", inbound.length, TUNNEL_LENGTH);
+			validation.range("inbound.quantity", inbound.quantity, TUNNEL_QUANTITY);
+			validation.range(
+				"inbound.backupQuantity",
+				inbound.backup_quantity,
+				TUNNEL_BACKUP_QUANTITY,
+			);
+			validation.range(
+				"inbound.lengthVariance",
+				inbound.length_variance,
+				TUNNEL_LENGTH_VARIANCE,
+			);
+			validation.range(
+				"inbound.IPRestriction",
+				inbound.ip_restriction,
+				TUNNEL_IP_RESTRICTION,
+			);
 		}
-		if let Some(lease_set_transient_public_key) = &self.lease_set_transient_public_key {
-			options.push_str(&format!(
-				"i2cp.leaseSetTransientPublicKey={} ",
-				lease_set_transient_public_key.to_string()
-			));
+		if let Some(outbound) = &self.outbound {
+			validation.range("outbound.length", outbound.length, TUNNEL_LENGTH);
+			validation.range("outbound.quantity", outbound.quantity, TUNNEL_QUANTITY);
+			validation.range(
+				"outbound.backupQuantity",
+				outbound.backup_quantity,
+				TUNNEL_BACKUP_QUANTITY,
+			);
+			validation.range(
+				"outbound.lengthVariance",
+				outbound.length_variance,
+				TUNNEL_LENGTH_VARIANCE,
+			);
+			validation.range(
+				"outbound.IPRestriction",
+				outbound.ip_restriction,
+				TUNNEL_IP_RESTRICTION,
+			);
+			validation.range("outbound.priority", outbound.priority, OUTBOUND_PRIORITY);
 		}
-		if let Some(lease_set_type) = &self.lease_set_type {
-			options.push_str(&format!(
-				"i2cp.leaseSetType={} ",
-				lease_set_type.to_string()
-			));
+	}
+}
+
+impl I2CPClientOptions {
+	fn validate(&self, validation: &mut Validation) {
+		validation.enc_type(self.lease_set_enc_type.as_ref());
+		validation.range("i2cp.reduceQuantity", self.reduce_quantity, TUNNEL_QUANTITY);
+		validation.min_idle_time("i2cp.reduceIdleTime", self.reduce_idle_time);
+		validation.min_idle_time("i2cp.closeIdleTime", self.close_idle_time);
+		validation.range("i2cp.tcp.port", self.tcp_port, 1..=u16::MAX);
+	}
+}
+
+/// Builds [SAMOptions], see [SAMOptions::builder].
+#[derive(Debug, Clone, Default)]
+pub struct SAMOptionsBuilder {
+	options: SAMOptions,
+}
+
+impl SAMOptionsBuilder {
+	/// Sets the key type of `TRANSIENT` destinations, see
+	/// [SAMOptions::signature_type].
+	pub fn signature_type(mut self, signature_type: SignatureType) -> Self {
+		self.options.signature_type = signature_type;
+		self
+	}
+
+	pub fn from_port(mut self, port: u16) -> Self {
+		self.options.from_port = Some(port);
+		self
+	}
+
+	pub fn to_port(mut self, port: u16) -> Self {
+		self.options.to_port = Some(port);
+		self
+	}
+
+	/// Number of hops of inbound tunnels, 0 to 7.
+	pub fn inbound_length(mut self, length: u8) -> Self {
+		self.options.inbound().length = Some(length);
+		self
+	}
+
+	/// Number of inbound tunnels, 1 to 16.
+	pub fn inbound_quantity(mut self, quantity: u8) -> Self {
+		self.options.inbound().quantity = Some(quantity);
+		self
+	}
+
+	pub fn inbound_backup_quantity(mut self, quantity: u8) -> Self {
+		self.options.inbound().backup_quantity = Some(quantity);
+		self
+	}
+
+	/// Random amount of hops added to inbound tunnels, -7 to 7.
+	pub fn inbound_length_variance(mut self, variance: i8) -> Self {
+		self.options.inbound().length_variance = Some(variance);
+		self
+	}
+
+	pub fn inbound_allow_zero_hop(mut self, allow: bool) -> Self {
+		self.options.inbound().allow_zero_hop = Some(allow);
+		self
+	}
+
+	/// Number of hops of outbound tunnels, 0 to 7.
+	pub fn outbound_length(mut self, length: u8) -> Self {
+		self.options.outbound().length = Some(length);
+		self
+	}
+
+	/// Number of outbound tunnels, 1 to 16.
+	pub fn outbound_quantity(mut self, quantity: u8) -> Self {
+		self.options.outbound().quantity = Some(quantity);
+		self
+	}
+
+	pub fn outbound_backup_quantity(mut self, quantity: u8) -> Self {
+		self.options.outbound().backup_quantity = Some(quantity);
+		self
+	}
+
+	/// Random amount of hops added to outbound tunnels, -7 to 7.
+	pub fn outbound_length_variance(mut self, variance: i8) -> Self {
+		self.options.outbound().length_variance = Some(variance);
+		self
+	}
+
+	pub fn outbound_allow_zero_hop(mut self, allow: bool) -> Self {
+		self.options.outbound().allow_zero_hop = Some(allow);
+		self
+	}
+
+	/// Priority of outbound messages, -25 to 25.
+	pub fn outbound_priority(mut self, priority: i8) -> Self {
+		self.options.outbound().priority = Some(priority);
+		self
+	}
+
+	/// Encryption types of the leaseset, in order of preference.
+	pub fn lease_set_enc_type<T: Into<LeaseSetEncType>>(mut self, enc_type: T) -> Self {
+		let enc_type = enc_type.into();
+		self.options.client().lease_set_enc_type = Some(enc_type.clone());
+		self.options.router().lease_set_enc_type = Some(enc_type);
+		self
+	}
+
+	pub fn lease_set_type(mut self, lease_set_type: LeaseSetType) -> Self {
+		self.options.router().lease_set_type = Some(lease_set_type);
+		self
+	}
+
+	pub fn lease_set_auth_type(mut self, auth_type: LeaseSetAuthType) -> Self {
+		self.options.client().lease_set_auth_type = Some(auth_type.clone());
+		self.options.router().lease_set_auth_type = Some(auth_type);
+		self
+	}
+
+	pub fn lease_set_secret(mut self, secret: LeaseSetSecret) -> Self {
+		self.options.client().lease_set_secret = Some(secret.clone());
+		self.options.router().lease_set_secret = Some(secret);
+		self
+	}
+
+	/// Encrypts the leaseset with `key`.
+	pub fn lease_set_key(mut self, key: LeaseSetKey) -> Self {
+		let client = self.options.client();
+		client.encrypt_lease_set = Some(true);
+		client.lease_set_key = Some(key);
+		self
+	}
+
+	/// Should generally be set for clients, and not for servers.
+	pub fn dont_publish_lease_set(mut self, dont_publish: bool) -> Self {
+		self.options.router().dont_publish_lease_set = Some(dont_publish);
+		self
+	}
+
+	/// Reduces the number of tunnels to `quantity` after `idle_time`, at
+	/// least 5 minutes, without activity.
+	pub fn reduce_on_idle(mut self, idle_time: Duration, quantity: u8) -> Self {
+		let client = self.options.client();
+		client.reduce_on_idle = Some(true);
+		client.reduce_idle_time = Some(idle_time.as_millis() as u64);
+		client.reduce_quantity = Some(quantity);
+		self
+	}
+
+	/// Closes the session after `idle_time`, at least 5 minutes, without
+	/// activity.
+	pub fn close_on_idle(mut self, idle_time: Duration) -> Self {
+		let client = self.options.client();
+		client.close_on_idle = Some(true);
+		client.close_idle_time = Some(idle_time.as_millis() as u64);
+		self
+	}
+
+	/// Sets the tunnels' length, length variance, quantity and backup
+	/// quantity, the same both ways.
+	fn tunnels(self, length: u8, variance: i8, quantity: u8, backup: u8) -> Self {
+		self.inbound_length(length)
+			.outbound_length(length)
+			.inbound_length_variance(variance)
+			.outbound_length_variance(variance)
+			.inbound_quantity(quantity)
+			.outbound_quantity(quantity)
+			.inbound_backup_quantity(backup)
+			.outbound_backup_quantity(backup)
+	}
+
+	/// Keeps every tunnel up while the session is idle.
+	fn always_up(mut self) -> Self {
+		let client = self.options.client();
+		client.reduce_on_idle = Some(false);
+		client.reduce_idle_time = None;
+		client.reduce_quantity = None;
+		self.keep_open()
+	}
+
+	/// Keeps the session open while it is idle.
+	fn keep_open(mut self) -> Self {
+		let client = self.options.client();
+		client.close_on_idle = Some(false);
+		client.close_idle_time = None;
+		self
+	}
+
+	/// The streaming library's profile, bulk by default. Java routers don't
+	/// fully implement the interactive profile.
+	pub fn streaming_profile(mut self, profile: StreamingProfile) -> Self {
+		self.options.streaming().profile = Some(profile);
+		self
+	}
+
+	/// Names the inbound and outbound tunnels, e.g. in the router console.
+	pub fn nickname(mut self, nickname: &str) -> Self {
+		self.options.inbound().nickname = Some(nickname.to_string());
+		self.options.outbound().nickname = Some(nickname.to_string());
+		self
+	}
+
+	/// Allows `client` to read the encrypted leaseset.
+	pub fn lease_set_client(mut self, client: LeaseSetClient) -> Self {
+		self.options
+			.client()
+			.lease_set_clients
+			.get_or_insert_with(Vec::new)
+			.push(client);
+		self
+	}
+
+	/// Only accepts connections from `addrs`, B32 addresses. Replaces a
+	/// list set with [deny](Self::deny).
+	pub fn allow_only(mut self, addrs: Vec<I2pAddr>) -> Self {
+		let streaming = self.options.streaming();
+		streaming.enable_access_list = Some(true);
+		streaming.enable_black_list = None;
+		streaming.access_list = Some(AccessList(addrs));
+		self
+	}
+
+	/// Refuses connections from `addrs`, B32 addresses. Replaces a list set
+	/// with [allow_only](Self::allow_only).
+	pub fn deny(mut self, addrs: Vec<I2pAddr>) -> Self {
+		let streaming = self.options.streaming();
+		streaming.enable_black_list = Some(true);
+		streaming.enable_access_list = None;
+		streaming.access_list = Some(AccessList(addrs));
+		self
+	}
+
+	/// Replaces the streaming options, including the profile set by presets.
+	pub fn streaming_options(mut self, streaming_options: StreamingOptions) -> Self {
+		self.options.streaming_options = Some(streaming_options);
+		self
+	}
+
+	/// Sets an option without a typed field, see [SAMOptions::extra].
+	pub fn option(mut self, key: &str, value: &str) -> Self {
+		self.options
+			.extra
+			.insert(key.to_string(), value.to_string());
+		self
+	}
+
+	pub fn message_reliability(mut self, reliability: MessageReliability) -> Self {
+		self.options.client().message_reliability = Some(reliability.clone());
+		self.options.router().message_reliability = Some(reliability);
+		self
+	}
+
+	pub fn gzip(mut self, gzip: bool) -> Self {
+		self.options.client().gzip = Some(gzip);
+		self
+	}
+
+	/// Address of the router's I2CP interface.
+	pub fn i2cp_addr(mut self, host: &str, port: u16) -> Self {
+		let client = self.options.client();
+		client.tcp_host = Some(host.to_string());
+		client.tcp_port = Some(port);
+		self
+	}
+
+	/// Credentials for the router's I2CP interface.
+	pub fn credentials(mut self, username: &str, password: &str) -> Self {
+		let router = self.options.router();
+		router.username = Some(username.to_string());
+		router.password = Some(password.to_string());
+		self
+	}
+
+	/// Returns the options, failing as [SAMOptions::validate] does.
+	pub fn build(self) -> Result<SAMOptions> {
+		self.options.validate()?;
+		Ok(self.options)
+	}
+}
+
+impl SAMOptions {
+	/// The options as a map of their SAM and I2CP names to their values,
+	/// e.g. `inbound.length` to `3`, ordered by name. Options set in both the
+	/// router and client options appear once, with the client's value if the
+	/// two conflict, which [SAMOptions::validate] reports.
+	pub fn pairs(&self) -> BTreeMap<String, String> {
+		self.all_pairs().into_iter().collect()
+	}
+
+	/// The options, with those set in both the router and client options
+	/// repeated.
+	fn all_pairs(&self) -> Vec<(String, String)> {
+		let mut pairs = Vec::new();
+		push(&mut pairs, "FROM_PORT", &self.from_port);
+		push(&mut pairs, "TO_PORT", &self.to_port);
+		if let Some(i2cp_options) = &self.i2cp_options {
+			pairs.extend(i2cp_options.pairs());
 		}
-		if let Some(message_reliability) = &self.message_reliability {
-			options.push_str(&format!(
-				"i2cp.messageReliability={} ",
-				message_reliability.to_string()
-			));
+		if let Some(streaming_options) = &self.streaming_options {
+			pairs.extend(streaming_options.pairs());
 		}
-		if let Some(password) = &self.password {
-			options.push_str(&format!("i2cp.password={password} "));
+		pairs.extend(self.extra.clone());
+		pairs
+	}
+
+	/// Sets the option named `key`, e.g. `inbound.length`, from its string
+	/// value. Options shared by the client and the router, such as
+	/// `i2cp.leaseSetEncType`, are set for both, and options without a typed
+	/// field are set in [SAMOptions::extra].
+	pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+		self.set_option(key, value).map_err(I2PError::from)
+	}
+
+	/// Parses options from key/value pairs, on top of the default options,
+	/// failing with every invalid one.
+	///
+	/// # Examples
+	///
+	/// ```
+	/// use i2p::sam_options::SAMOptions;
+	///
+	/// let options = SAMOptions::from_pairs([("inbound.length", "3"), ("outbound.quantity", "2")])
+	///     .unwrap();
+	/// ```
+	pub fn from_pairs<I, K, V>(pairs: I) -> Result<SAMOptions>
+	where
+		I: IntoIterator<Item = (K, V)>,
+		K: AsRef<str>,
+		V: AsRef<str>,
+	{
+		Self::parse_pairs(pairs)
+	}
+
+	/// Parses the options of the tunnel named `tunnel` in a Java I2P
+	/// `i2ptunnel.config`, or in a file of `i2ptunnel.config.d`, from its
+	/// `option.*` properties.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::sam_options::SAMOptions;
+	///
+	/// let config = std::fs::read_to_string("i2ptunnel.config").unwrap();
+	/// let options = SAMOptions::from_i2ptunnel_config(&config, "eepsite").unwrap();
+	/// ```
+	pub fn from_i2ptunnel_config(config: &str, tunnel: &str) -> Result<SAMOptions> {
+		let properties: Vec<_> = config
+			.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+			.filter_map(|line| line.split_once('='))
+			.map(|(key, value)| (key.trim(), value.trim()))
+			.collect();
+		// properties are prefixed by `tunnel.N.` in the single file format
+		let prefix = properties
+			.iter()
+			.find_map(|(key, value)| match key.strip_suffix("name") {
+				Some(prefix) if *value == tunnel && is_tunnel_prefix(prefix) => {
+					Some(prefix.to_string())
+				}
+				_ => None,
+			})
+			.ok_or_else(|| I2PError::TunnelNotFound(tunnel.to_string()))?;
+		let options = properties.into_iter().filter_map(|(key, value)| {
+			let key = key.strip_prefix(prefix.as_str())?.strip_prefix("option.")?;
+			Some((key, value))
+		});
+		Self::parse_pairs(options)
+	}
+
+	/// Parses the options of the tunnel in the `[tunnel]` section of an i2pd
+	/// `tunnels.conf`, from its I2CP, streaming and crypto options and its
+	/// `signaturetype`. Other tunnel parameters, such as `host`, are
+	/// ignored.
+	///
+	/// # Examples
+	///
+	/// ```no_run
+	/// use i2p::sam_options::SAMOptions;
+	///
+	/// let config = std::fs::read_to_string("/etc/i2pd/tunnels.conf").unwrap();
+	/// let options = SAMOptions::from_i2pd_tunnels_conf(&config, "eepsite").unwrap();
+	/// ```
+	pub fn from_i2pd_tunnels_conf(config: &str, tunnel: &str) -> Result<SAMOptions> {
+		let mut section = None;
+		let mut found = false;
+		let mut options = Vec::new();
+		for line in config.lines().map(str::trim) {
+			if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+				continue;
+			}
+			if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+				section = Some(name.trim());
+				found |= section == Some(tunnel);
+				continue;
+			}
+			if section != Some(tunnel) {
+				continue;
+			}
+			if let Some((key, value)) = line.split_once('=') {
+				let key = match key.trim() {
+					"signaturetype" => "SIGNATURE_TYPE",
+					key if I2PD_OPTION_PREFIXES.iter().any(|p| key.starts_with(p)) => key,
+					key => {
+						debug!("ignoring tunnel parameter {key}");
+						continue;
+					}
+				};
+				options.push((key, value.trim()));
+			}
 		}
-		if let Some(username) = &self.username {
-			options.push_str(&format!("i2cp.username={username} "));
+		if !found {
+			return Err(I2PError::TunnelNotFound(tunnel.to_string()));
 		}
-		if let Some(inbound) = &self.inbound {
-			let inbound_str = inbound.string();
-			if !inbound_str.is_empty() {
-				options.push_str(inbound_str.as_str());
+		Self::parse_pairs(options)
+	}
+
+	fn parse_pairs<I, K, V>(pairs: I) -> Result<SAMOptions, I2PError>
+	where
+		I: IntoIterator<Item = (K, V)>,
+		K: AsRef<str>,
+		V: AsRef<str>,
+	{
+		let mut options = SAMOptions::default();
+		let mut errors = Vec::new();
+		for (key, value) in pairs {
+			let (key, value) = (key.as_ref(), value.as_ref());
+			if let Err(err) = options.set_option(key, value) {
+				errors.push(err);
 			}
 		}
-		if let Some(outbound) = &self.outbound {
-			let outbound_str = outbound.string();
-			if !outbound_str.is_empty() {
-				options.push_str(outbound_str.as_str());
+		if errors.is_empty() {
+			Ok(options)
+		} else {
+			Err(I2PError::InvalidOptions(errors))
+		}
+	}
+
+	fn set_option(&mut self, key: &str, value: &str) -> Result<(), InvalidOption> {
+		macro_rules! set {
+			($($field:tt)+) => {
+				$($field)+ = Some(OptionValue::parse(key, value)?)
+			};
+		}
+		match key {
+			"FROM_PORT" => set!(self.from_port),
+			"TO_PORT" => set!(self.to_port),
+			"SIGNATURE_TYPE" => self.signature_type = OptionValue::parse(key, value)?,
+			"clientMessageTimeout" => set!(self.router().client_message_timeout),
+			"crypto.lowTagThreshold" => set!(self.crypto().low_tag_threshold),
+			"crypto.ratchet.inboundTags" => set!(self.crypto().ratchet_inbound_tags),
+			"crypto.ratchet.outboundTags" => set!(self.crypto().ratchet_outbound_tags),
+			"crypto.tagsToSend" => set!(self.crypto().tags_to_send),
+			"i2cp.dontPublishLeaseSet" => set!(self.router().dont_publish_lease_set),
+			"i2cp.leaseSetOfflineExpiration" => {
+				set!(self.router().lease_set_offline_expiration)
+			}
+			"i2cp.leaseSetOfflineSignature" => set!(self.router().lease_set_offline_signature),
+			"i2cp.leaseSetPrivKey" => set!(self.router().lease_set_priv_key),
+			"i2cp.leaseSetTransientPublicKey" => {
+				set!(self.router().lease_set_transient_public_key)
+			}
+			"i2cp.leaseSetType" => set!(self.router().lease_set_type),
+			"i2cp.password" => set!(self.router().password),
+			"i2cp.username" => set!(self.router().username),
+			"shouldBundleReplyInfo" => set!(self.router().should_bundle_reply_info),
+			"i2cp.closeIdleTime" => set!(self.client().close_idle_time),
+			"i2cp.closeOnIdle" => set!(self.client().close_on_idle),
+			"i2cp.encryptLeaseSet" => set!(self.client().encrypt_lease_set),
+			"i2cp.gzip" => set!(self.client().gzip),
+			"i2cp.leaseSetBlindedType" => set!(self.client().lease_set_blinded_type),
+			"i2cp.leaseSetKey" => set!(self.client().lease_set_key),
+			"i2cp.leaseSetPrivateKey" => set!(self.client().lease_set_private_key),
+			"i2cp.leaseSetSigningPrivateKey" => set!(self.client().lease_set_signing_private_key),
+			"i2cp.reduceIdleTime" => set!(self.client().reduce_idle_time),
+			"i2cp.reduceOnIdle" => set!(self.client().reduce_on_idle),
+			"i2cp.reduceQuantity" => set!(self.client().reduce_quantity),
+			"i2cp.ssl" => set!(self.client().ssl),
+			"i2cp.tcp.host" => set!(self.client().tcp_host),
+			"i2cp.tcp.port" => set!(self.client().tcp_port),
+			"i2cp.fastReceive" => {
+				set!(self.client().fast_receive);
+				set!(self.router().fast_receive);
+			}
+			"i2cp.leaseSetAuthType" => {
+				set!(self.client().lease_set_auth_type);
+				set!(self.router().lease_set_auth_type);
+			}
+			"i2cp.leaseSetEncType" => {
+				set!(self.client().lease_set_enc_type);
+				set!(self.router().lease_set_enc_type);
+			}
+			"i2cp.leaseSetSecret" => {
+				set!(self.client().lease_set_secret);
+				set!(self.router().lease_set_secret);
+			}
+			"i2cp.messageReliability" => {
+				set!(self.client().message_reliability);
+				set!(self.router().message_reliability);
 			}
+			"inbound.allowZeroHop" => set!(self.inbound().allow_zero_hop),
+			"inbound.backupQuantity" => set!(self.inbound().backup_quantity),
+			"inbound.IPRestriction" => set!(self.inbound().ip_restriction),
+			"inbound.length" => set!(self.inbound().length),
+			"inbound.lengthVariance" => set!(self.inbound().length_variance),
+			"inbound.quantity" => set!(self.inbound().quantity),
+			"inbound.randomKey" => set!(self.inbound().random_key),
+			"outbound.allowZeroHop" => set!(self.outbound().allow_zero_hop),
+			"outbound.backupQuantity" => set!(self.outbound().backup_quantity),
+			"outbound.IPRestriction" => set!(self.outbound().ip_restriction),
+			"outbound.length" => set!(self.outbound().length),
+			"outbound.lengthVariance" => set!(self.outbound().length_variance),
+			"outbound.priority" => set!(self.outbound().priority),
+			"outbound.quantity" => set!(self.outbound().quantity),
+			"outbound.randomKey" => set!(self.outbound().random_key),
+			"inbound.nickname" => set!(self.inbound().nickname),
+			"outbound.nickname" => set!(self.outbound().nickname),
+			"i2cp.destination.sigType" => set!(self.client().destination_sig_type),
+			"i2p.streaming.answerPings" => set!(self.streaming().answer_pings),
+			"i2p.streaming.connectDelay" => set!(self.streaming().connect_delay),
+			"i2p.streaming.connectTimeout" => set!(self.streaming().connect_timeout),
+			"i2p.streaming.enforceProtocol" => set!(self.streaming().enforce_protocol),
+			"i2p.streaming.inactivityAction" => set!(self.streaming().inactivity_action),
+			"i2p.streaming.inactivityTimeout" => set!(self.streaming().inactivity_timeout),
+			"i2p.streaming.initialWindowSize" => set!(self.streaming().initial_window_size),
+			"i2p.streaming.maxConcurrentStreams" => {
+				set!(self.streaming().max_concurrent_streams)
+			}
+			"i2p.streaming.maxConnsPerMinute" => set!(self.streaming().max_conns_per_minute),
+			"i2p.streaming.maxConnsPerHour" => set!(self.streaming().max_conns_per_hour),
+			"i2p.streaming.maxConnsPerDay" => set!(self.streaming().max_conns_per_day),
+			"i2p.streaming.maxTotalConnsPerMinute" => {
+				set!(self.streaming().max_total_conns_per_minute)
+			}
+			"i2p.streaming.maxTotalConnsPerHour" => {
+				set!(self.streaming().max_total_conns_per_hour)
+			}
+			"i2p.streaming.maxTotalConnsPerDay" => {
+				set!(self.streaming().max_total_conns_per_day)
+			}
+			"i2p.streaming.maxMessageSize" => set!(self.streaming().max_message_size),
+			"i2p.streaming.maxWindowSize" => set!(self.streaming().max_window_size),
+			"i2p.streaming.profile" => set!(self.streaming().profile),
+			"i2cp.enableAccessList" => set!(self.streaming().enable_access_list),
+			"i2cp.enableBlackList" => set!(self.streaming().enable_black_list),
+			"i2cp.accessList" => set!(self.streaming().access_list),
+			_ => match key.strip_prefix("i2cp.leaseSetClient.") {
+				Some(client) => {
+					let client = LeaseSetClient::parse_option(client, key, value)?;
+					self.client()
+						.lease_set_clients
+						.get_or_insert_with(Vec::new)
+						.push(client);
+				}
+				None => {
+					self.extra.insert(key.to_string(), value.to_string());
+				}
+			},
+		}
+		Ok(())
+	}
+
+	fn i2cp(&mut self) -> &mut I2CPOptions {
+		self.i2cp_options.get_or_insert_with(Default::default)
+	}
+
+	fn router(&mut self) -> &mut I2CPRouterOptions {
+		self.i2cp()
+			.router_options
+			.get_or_insert_with(Default::default)
+	}
+
+	fn client(&mut self) -> &mut I2CPClientOptions {
+		self.i2cp()
+			.client_options
+			.get_or_insert_with(Default::default)
+	}
+
+	fn streaming(&mut self) -> &mut StreamingOptions {
+		self.streaming_options.get_or_insert_with(Default::default)
+	}
+
+	fn crypto(&mut self) -> &mut I2CPRouterCryptoOptions {
+		self.router()
+			.crypto_options
+			.get_or_insert_with(Default::default)
+	}
+
+	fn inbound(&mut self) -> &mut I2CPTunnelInboundOptions {
+		self.router().inbound.get_or_insert_with(Default::default)
+	}
+
+	fn outbound(&mut self) -> &mut I2CPTunnelOutboundOptions {
+		self.router().outbound.get_or_insert_with(Default::default)
+	}
+}
+
+/// Prefixes of the options of i2pd tunnels passed to the router.
+const I2PD_OPTION_PREFIXES: [&str; 6] = [
+	"inbound.",
+	"outbound.",
+	"i2cp.",
+	"i2p.streaming.",
+	"crypto.",
+	"explicitPeers",
+];
+
+/// Whether `prefix` is empty or of the form `tunnel.N.`.
+fn is_tunnel_prefix(prefix: &str) -> bool {
+	prefix.is_empty()
+		|| prefix
+			.strip_prefix("tunnel.")
+			.and_then(|p| p.strip_suffix('.'))
+			.is_some_and(|n| n.parse::<u32>().is_ok())
+}
+
+/// Parses space-separated `key=value` options, as sent to the SAM bridge.
+/// Values may be double quoted.
+///
+/// # Examples
+///
+/// ```
+/// use i2p::sam_options::SAMOptions;
+///
+/// let options: SAMOptions = "inbound.length=3 outbound.quantity=2 i2cp.leaseSetEncType=4,0"
+///     .parse()
+///     .unwrap();
+/// ```
+impl FromStr for SAMOptions {
+	type Err = I2PError;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut pairs = Vec::new();
+		let mut chars = s.trim().chars().peekable();
+		while chars.peek().is_some() {
+			let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+			let mut value = String::new();
+			if chars.peek() == Some(&'"') {
+				chars.next();
+				while let Some(c) = chars.next() {
+					match c {
+						'"' => break,
+						'\\' => value.extend(chars.next()),
+						c => value.push(c),
+					}
+				}
+			}
+			value.extend(chars.by_ref().take_while(|c| !c.is_whitespace()));
+			while chars.next_if(|c| c.is_whitespace()).is_some() {}
+			if key.is_empty() || key.contains(char::is_whitespace) {
+				let option = InvalidOption::new(&key, &value, "expected key=value");
+				return Err(option.into());
+			}
+			pairs.push((key, value));
 		}
-		if let Some(should_bundle_reply_info) = &self.should_bundle_reply_info {
-			options.push_str(&format!(
-				"shouldBundleReplyInfo={should_bundle_reply_info} "
-			));
+		Self::parse_pairs(pairs)
+	}
+}
+
+/// Serializes to a map of the SAM and I2CP option names to their string
+/// values, including `SIGNATURE_TYPE`.
+impl Serialize for SAMOptions {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		let pairs = self.pairs();
+		let mut map = serializer.serialize_map(Some(pairs.len() + 1))?;
+		map.serialize_entry("SIGNATURE_TYPE", &self.signature_type.to_string())?;
+		for (key, value) in &pairs {
+			map.serialize_entry(key, value)?;
+		}
+		map.end()
+	}
+}
+
+/// Deserializes from a map of the SAM and I2CP option names to their
+/// values, on top of the default options. Nested maps are joined with `.`,
+/// so that TOML dotted keys such as `inbound.length = 3` work unquoted, and
+/// lists with `,`.
+///
+/// # Examples
+///
+/// ```
+/// use i2p::sam_options::SAMOptions;
+///
+/// let options: SAMOptions =
+///     serde_json::from_str(r#"{"inbound.length": 3, "i2cp.leaseSetEncType": [4, 0]}"#).unwrap();
+/// ```
+impl<'de> Deserialize<'de> for SAMOptions {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let mut pairs = Vec::new();
+		for (key, value) in BTreeMap::<String, RawValue>::deserialize(deserializer)? {
+			value.flatten(key, &mut pairs);
+		}
+		Self::parse_pairs(pairs).map_err(de::Error::custom)
+	}
+}
+
+/// An option value in a self-describing format such as TOML or JSON.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawValue {
+	Bool(bool),
+	Int(i64),
+	Str(String),
+	List(Vec<RawValue>),
+	Map(BTreeMap<String, RawValue>),
+}
+
+impl RawValue {
+	fn flatten(self, key: String, pairs: &mut Vec<(String, String)>) {
+		match self {
+			Self::Map(map) => {
+				for (k, v) in map {
+					v.flatten(format!("{key}.{k}"), pairs);
+				}
+			}
+			value => pairs.push((key, value.to_string())),
 		}
-		options
 	}
 }
 
-impl I2CPClientOptions {
-	pub fn string(&self) -> String {
-		let mut options = String::default();
-		if let Some(close_idle_time) = &self.close_idle_time {
-			options.push_str(&format!("i2cp.closeIdleTime={close_idle_time} "));
+impl fmt::Display for RawValue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Bool(b) => write!(f, "{b}"),
+			Self::Int(i) => write!(f, "{i}"),
+			Self::Str(s) => f.write_str(s),
+			Self::List(values) => {
+				let values: Vec<String> = values.iter().map(RawValue::to_string).collect();
+				f.write_str(&values.join(","))
+			}
+			Self::Map(_) => f.write_str("{..}"),
 		}
-		if let Some(close_on_idle) = &self.close_on_idle {
-			options.push_str(&format!("i2cp.closeOnIdle={close_on_idle} "));
+	}
+}
+
+/// A typed option value, parsed from its string form.
+trait OptionValue: Sized {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption>;
+}
+
+macro_rules! number_option {
+	($($ty:ty),+) => {$(
+		impl OptionValue for $ty {
+			fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+				value.parse().map_err(|_| InvalidOption::new(key, value, "not a valid number"))
+			}
 		}
-		if let Some(encrypt_lease_set) = &self.encrypt_lease_set {
-			options.push_str(&format!("i2cp.encryptLeaseSet={encrypt_lease_set} "));
+	)+};
+}
+
+number_option!(u8, u16, u32, u64, i8, i32);
+
+impl OptionValue for bool {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+		if value.eq_ignore_ascii_case("true") {
+			Ok(true)
+		} else if value.eq_ignore_ascii_case("false") {
+			Ok(false)
+		} else {
+			Err(InvalidOption::new(key, value, "not true or false"))
 		}
-		if let Some(fast_receive) = &self.fast_receive {
-			options.push_str(&format!("i2cp.fastReceive={fast_receive} "));
+	}
+}
+
+impl OptionValue for String {
+	fn parse(_key: &str, value: &str) -> Result<Self, InvalidOption> {
+		Ok(value.to_string())
+	}
+}
+
+/// Parses a signature type from its number or name, e.g. `7` or
+/// `EdDSA_SHA512_Ed25519`.
+impl OptionValue for SignatureType {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+		match value.parse::<u16>() {
+			Ok(number) => SignatureType::try_from(number),
+			Err(_) => SignatureType::from_str(value),
+		}
+		.map_err(|_| InvalidOption::new(key, value, "unknown signature type"))
+	}
+}
+
+impl OptionValue for LeaseSetBlindedType {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+		SignatureType::parse(key, value).map(LeaseSetBlindedType)
+	}
+}
+
+impl OptionValue for LeaseSetAuthType {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+		match value {
+			"0" => Ok(Self::NoPerClient),
+			"1" => Ok(Self::DHPerClient),
+			"2" => Ok(Self::PSKPerClient),
+			_ => Err(InvalidOption::new(key, value, "not 0, 1 or 2")),
 		}
-		if let Some(gzip) = &self.gzip {
-			options.push_str(&format!("i2cp.gzip={gzip} "));
+	}
+}
+
+impl OptionValue for LeaseSetType {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+		match value {
+			"1" => Ok(Self::LeaseSet),
+			"3" => Ok(Self::LeaseSet2),
+			"5" => Ok(Self::EncryptedLeaseSet2),
+			"7" => Ok(Self::MetaLeaseSet),
+			_ => Err(InvalidOption::new(key, value, "not 1, 3, 5 or 7")),
 		}
-		if let Some(lease_set_auth_type) = &self.lease_set_auth_type {
-			options.push_str(&format!(
-				"i2cp.leaseSetAuthType={} ",
-				lease_set_auth_type.to_string()
-			));
+	}
+}
+
+impl OptionValue for InactivityAction {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+		match value {
+			"0" => Ok(Self::Nothing),
+			"1" => Ok(Self::Disconnect),
+			"2" => Ok(Self::Send),
+			_ => Err(InvalidOption::new(key, value, "not 0, 1 or 2")),
 		}
-		if let Some(lease_set_blinded_type) = &self.lease_set_blinded_type {
-			options.push_str(&format!(
-				"i2cp.leaseSetBlindedType={} ",
-				lease_set_blinded_type.to_string()
-			));
+	}
+}
+
+impl OptionValue for StreamingProfile {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+		match value {
+			"1" => Ok(Self::Bulk),
+			"2" => Ok(Self::Interactive),
+			_ => Err(InvalidOption::new(key, value, "not 1 or 2")),
 		}
-		if let Some(lease_set_enc_type) = &self.lease_set_enc_type {
-			options.push_str(&format!(
-				"i2cp.leaseSetEncType={} ",
-				lease_set_enc_type.to_string()
-			));
+	}
+}
+
+impl LeaseSetClient {
+	/// Parses the client of `i2cp.leaseSetClient.<option>`, where option is
+	/// `dh.N` or `psk.N`, from its `name:key` value.
+	fn parse_option(option: &str, key: &str, value: &str) -> Result<Self, InvalidOption> {
+		let (name, client_key) = value
+			.split_once(':')
+			.filter(|(name, _)| !name.is_empty())
+			.ok_or_else(|| InvalidOption::new(key, value, "not of the form name:key"))?;
+		let client_key = base64_option(key, client_key, false)?;
+		let (name, auth) = (name.to_string(), option.split_once('.'));
+		match auth {
+			Some(("dh", n)) if n.parse::<u32>().is_ok() => Ok(Self::Dh {
+				name,
+				public_key: client_key,
+			}),
+			Some(("psk", n)) if n.parse::<u32>().is_ok() => Ok(Self::Psk {
+				name,
+				private_key: client_key,
+			}),
+			_ => Err(InvalidOption::new(key, value, "not a dh.N or psk.N client")),
 		}
-		if let Some(lease_set_key) = &self.lease_set_key {
-			options.push_str(&format!("i2cp.leaseSetKey={} ", lease_set_key.to_string()));
+	}
+}
+
+/// Parses comma or space separated base 64 destination hashes, as the Java
+/// streaming library does, or B32 addresses.
+impl OptionValue for AccessList {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+		let mut addrs = Vec::new();
+		for entry in value.split([',', ' ']).filter(|e| !e.is_empty()) {
+			let addr = if entry.ends_with(B32_EXT) {
+				Some(I2pAddr::new(entry))
+			} else {
+				BASE64_I2P
+					.decode(entry.as_bytes())
+					.ok()
+					.filter(|hash| hash.len() == 32)
+					.map(|hash| I2pAddr::new(&format!("{}{B32_EXT}", BASE32_I2P.encode(&hash))))
+			};
+			match addr.filter(|addr| b32_hash(addr).is_some()) {
+				Some(addr) => addrs.push(addr),
+				None => {
+					return Err(InvalidOption::new(
+						key,
+						entry,
+						"not a destination hash or B32 address",
+					))
+				}
+			}
 		}
-		if let Some(lease_set_private_key) = &self.lease_set_private_key {
-			options.push_str(&format!(
-				"i2cp.leaseSetPrivateKey={} ",
-				lease_set_private_key.to_string()
-			));
+		Ok(AccessList(addrs))
+	}
+}
+
+impl OptionValue for MessageReliability {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+		if value.eq_ignore_ascii_case("BestEffort") {
+			Ok(Self::BestEffort)
+		} else if value.eq_ignore_ascii_case("None") {
+			Ok(Self::None)
+		} else {
+			Err(InvalidOption::new(key, value, "not BestEffort or None"))
 		}
-		if let Some(lease_set_secret) = &self.lease_set_secret {
-			options.push_str(&format!(
-				"i2cp.leaseSetSecret={} ",
-				lease_set_secret.to_string()
-			));
+	}
+}
+
+impl OptionValue for EncType {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+		match value {
+			"0" => Ok(Self::ElGamal),
+			"4" => Ok(Self::EciesX25519),
+			_ if value.eq_ignore_ascii_case("ELGAMAL_2048") => Ok(Self::ElGamal),
+			_ if value.eq_ignore_ascii_case("ECIES_X25519") => Ok(Self::EciesX25519),
+			_ => Err(InvalidOption::new(key, value, "unknown encryption type")),
 		}
-		if let Some(lease_set_signing_private_key) = &self.lease_set_signing_private_key {
-			options.push_str(&format!(
-				"i2cp.leaseSetSigningPrivateKey={} ",
-				lease_set_signing_private_key.to_string()
-			));
+	}
+}
+
+impl OptionValue for LeaseSetEncType {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+		value
+			.split(',')
+			.map(|t| EncType::parse(key, t.trim()))
+			.collect::<Result<_, _>>()
+			.map(LeaseSetEncType)
+	}
+}
+
+macro_rules! base64_option {
+	($($ty:ident: $typed:expr),+) => {$(
+		impl OptionValue for $ty {
+			fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+				base64_option(key, value, $typed).map($ty)
+			}
 		}
-		if let Some(message_reliability) = &self.message_reliability {
-			options.push_str(&format!(
-				"i2cp.messageReliability={} ",
-				message_reliability.to_string()
-			));
+	)+};
+}
+
+base64_option!(
+	LeaseSetOfflineSignature: false,
+	LeaseSetPrivKey: true,
+	LeaseSetPrivateKey: true,
+	LeaseSetSecret: false,
+	LeaseSetTransientPublicKey: true,
+	LeaseSetSigningPrivateKey: true
+);
+
+impl OptionValue for LeaseSetKey {
+	fn parse(key: &str, value: &str) -> Result<Self, InvalidOption> {
+		let value = base64_option(key, value, false)?;
+		if value.len() != 44 {
+			return Err(InvalidOption::new(key, &value, "not a 32 byte key"));
 		}
-		if let Some(reduce_idle_time) = &self.reduce_idle_time {
-			options.push_str(&format!("i2cp.reduceIdleTime={reduce_idle_time} "));
+		Ok(Self(value))
+	}
+}
+
+impl I2CPOptions {
+	pub fn string(&self) -> String {
+		format_pairs(self.pairs())
+	}
+
+	fn pairs(&self) -> Vec<(String, String)> {
+		let mut pairs = Vec::new();
+		if let Some(router_options) = &self.router_options {
+			pairs.extend(router_options.pairs());
 		}
-		if let Some(reduce_on_idle) = &self.reduce_on_idle {
-			options.push_str(&format!("i2cp.reduceOnIdle={reduce_on_idle} "));
+		if let Some(client_options) = &self.client_options {
+			pairs.extend(client_options.pairs());
 		}
-		if let Some(ssl) = &self.ssl {
-			options.push_str(&format!("i2cp.ssl={ssl} "));
+		pairs
+	}
+}
+
+impl I2CPRouterOptions {
+	pub fn string(&self) -> String {
+		format_pairs(self.pairs())
+	}
+
+	fn pairs(&self) -> Vec<(String, String)> {
+		let mut pairs = Vec::new();
+		push(
+			&mut pairs,
+			"clientMessageTimeout",
+			&self.client_message_timeout,
+		);
+		if let Some(crypto_options) = &self.crypto_options {
+			pairs.extend(crypto_options.pairs());
+		}
+		push(
+			&mut pairs,
+			"i2cp.dontPublishLeaseSet",
+			&self.dont_publish_lease_set,
+		);
+		push(&mut pairs, "i2cp.fastReceive", &self.fast_receive);
+		push(
+			&mut pairs,
+			"i2cp.leaseSetAuthType",
+			&self.lease_set_auth_type,
+		);
+		push(&mut pairs, "i2cp.leaseSetEncType", &self.lease_set_enc_type);
+		push(
+			&mut pairs,
+			"i2cp.leaseSetOfflineExpiration",
+			&self.lease_set_offline_expiration,
+		);
+		push(
+			&mut pairs,
+			"i2cp.leaseSetOfflineSignature",
+			&self.lease_set_offline_signature,
+		);
+		push(&mut pairs, "i2cp.leaseSetPrivKey", &self.lease_set_priv_key);
+		push(&mut pairs, "i2cp.leaseSetSecret", &self.lease_set_secret);
+		push(
+			&mut pairs,
+			"i2cp.leaseSetTransientPublicKey",
+			&self.lease_set_transient_public_key,
+		);
+		push(&mut pairs, "i2cp.leaseSetType", &self.lease_set_type);
+		push(
+			&mut pairs,
+			"i2cp.messageReliability",
+			&self.message_reliability,
+		);
+		push(&mut pairs, "i2cp.password", &self.password);
+		push(&mut pairs, "i2cp.username", &self.username);
+		if let Some(inbound) = &self.inbound {
+			pairs.extend(inbound.pairs());
 		}
-		if let Some(tcp_host) = &self.tcp_host {
-			options.push_str(&format!("i2cp.tcp.host={tcp_host} "));
+		if let Some(outbound) = &self.outbound {
+			pairs.extend(outbound.pairs());
+		}
+		push(
+			&mut pairs,
+			"shouldBundleReplyInfo",
+			&self.should_bundle_reply_info,
+		);
+		pairs
+	}
+}
+
+impl I2CPClientOptions {
+	pub fn string(&self) -> String {
+		format_pairs(self.pairs())
+	}
+
+	fn pairs(&self) -> Vec<(String, String)> {
+		let mut pairs = Vec::new();
+		push(&mut pairs, "i2cp.closeIdleTime", &self.close_idle_time);
+		push(&mut pairs, "i2cp.closeOnIdle", &self.close_on_idle);
+		push(&mut pairs, "i2cp.encryptLeaseSet", &self.encrypt_lease_set);
+		push(&mut pairs, "i2cp.fastReceive", &self.fast_receive);
+		push(&mut pairs, "i2cp.gzip", &self.gzip);
+		push(
+			&mut pairs,
+			"i2cp.leaseSetAuthType",
+			&self.lease_set_auth_type,
+		);
+		push(
+			&mut pairs,
+			"i2cp.leaseSetBlindedType",
+			&self.lease_set_blinded_type,
+		);
+		push(&mut pairs, "i2cp.leaseSetEncType", &self.lease_set_enc_type);
+		push(&mut pairs, "i2cp.leaseSetKey", &self.lease_set_key);
+		push(
+			&mut pairs,
+			"i2cp.leaseSetPrivateKey",
+			&self.lease_set_private_key,
+		);
+		push(&mut pairs, "i2cp.leaseSetSecret", &self.lease_set_secret);
+		push(
+			&mut pairs,
+			"i2cp.leaseSetSigningPrivateKey",
+			&self.lease_set_signing_private_key,
+		);
+		push(
+			&mut pairs,
+			"i2cp.messageReliability",
+			&self.message_reliability,
+		);
+		push(&mut pairs, "i2cp.reduceIdleTime", &self.reduce_idle_time);
+		push(&mut pairs, "i2cp.reduceOnIdle", &self.reduce_on_idle);
+		push(&mut pairs, "i2cp.reduceQuantity", &self.reduce_quantity);
+		push(&mut pairs, "i2cp.ssl", &self.ssl);
+		push(&mut pairs, "i2cp.tcp.host", &self.tcp_host);
+		push(&mut pairs, "i2cp.tcp.port", &self.tcp_port);
+		push(
+			&mut pairs,
+			"i2cp.destination.sigType",
+			&self.destination_sig_type,
+		);
+		let (mut dh, mut psk) = (0, 0);
+		for client in self.lease_set_clients.iter().flatten() {
+			let (key, name, client_key) = match client {
+				LeaseSetClient::Dh { name, public_key } => {
+					dh += 1;
+					(
+						format!("i2cp.leaseSetClient.dh.{}", dh - 1),
+						name,
+						public_key,
+					)
+				}
+				LeaseSetClient::Psk { name, private_key } => {
+					psk += 1;
+					(
+						format!("i2cp.leaseSetClient.psk.{}", psk - 1),
+						name,
+						private_key,
+					)
+				}
+			};
+			pairs.push((key, format!("{name}:{client_key}")));
+		}
+		pairs
+	}
+}
+
+impl StreamingOptions {
+	pub fn string(&self) -> String {
+		format_pairs(self.pairs())
+	}
+
+	fn pairs(&self) -> Vec<(String, String)> {
+		let mut pairs = Vec::new();
+		push(&mut pairs, "i2p.streaming.answerPings", &self.answer_pings);
+		push(
+			&mut pairs,
+			"i2p.streaming.connectDelay",
+			&self.connect_delay,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.connectTimeout",
+			&self.connect_timeout,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.enforceProtocol",
+			&self.enforce_protocol,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.inactivityAction",
+			&self.inactivity_action,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.inactivityTimeout",
+			&self.inactivity_timeout,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.initialWindowSize",
+			&self.initial_window_size,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.maxConcurrentStreams",
+			&self.max_concurrent_streams,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.maxConnsPerMinute",
+			&self.max_conns_per_minute,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.maxConnsPerHour",
+			&self.max_conns_per_hour,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.maxConnsPerDay",
+			&self.max_conns_per_day,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.maxTotalConnsPerMinute",
+			&self.max_total_conns_per_minute,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.maxTotalConnsPerHour",
+			&self.max_total_conns_per_hour,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.maxTotalConnsPerDay",
+			&self.max_total_conns_per_day,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.maxMessageSize",
+			&self.max_message_size,
+		);
+		push(
+			&mut pairs,
+			"i2p.streaming.maxWindowSize",
+			&self.max_window_size,
+		);
+		push(&mut pairs, "i2p.streaming.profile", &self.profile);
+		push(
+			&mut pairs,
+			"i2cp.enableAccessList",
+			&self.enable_access_list,
+		);
+		push(&mut pairs, "i2cp.enableBlackList", &self.enable_black_list);
+		push(&mut pairs, "i2cp.accessList", &self.access_list);
+		pairs
+	}
+
+	fn validate(&self, validation: &mut Validation) {
+		validation.range(
+			"i2p.streaming.connectDelay",
+			self.connect_delay,
+			-1..=i32::MAX,
+		);
+		validation.range(
+			"i2p.streaming.maxConcurrentStreams",
+			self.max_concurrent_streams,
+			-1..=i32::MAX,
+		);
+		validation.range(
+			"i2p.streaming.initialWindowSize",
+			self.initial_window_size,
+			STREAMING_WINDOW_SIZE,
+		);
+		validation.range(
+			"i2p.streaming.maxWindowSize",
+			self.max_window_size,
+			STREAMING_WINDOW_SIZE,
+		);
+		// both lists would be read from i2cp.accessList
+		if self.enable_access_list == Some(true) && self.enable_black_list == Some(true) {
+			validation.0.push(InvalidOption::new(
+				"i2cp.enableBlackList",
+				"true",
+				"conflicts with i2cp.enableAccessList=true",
+			));
 		}
-		if let Some(tcp_port) = &self.tcp_port {
-			options.push_str(&format!("i2cp.tcp.port={tcp_port} "));
+		for addr in self.access_list.iter().flat_map(AccessList::addrs) {
+			if b32_hash(addr).is_none() {
+				validation.0.push(InvalidOption::new(
+					"i2cp.accessList",
+					&addr.string(),
+					"not a B32 address",
+				));
+			}
 		}
-		options
 	}
 }
 
 impl I2CPRouterCryptoOptions {
 	pub fn string(&self) -> String {
-		let mut options = String::default();
-		if let Some(low_tag_threshold) = &self.low_tag_threshold {
-			options.push_str(&format!("crypto.lowTagThreshold={low_tag_threshold} "));
-		}
-		if let Some(inbound_tags) = &self.ratchet_inbound_tags {
-			options.push_str(&format!("crypto.ratchet.inboundTags={inbound_tags} "));
-		}
-		if let Some(outbound_tags) = &self.ratchet_outbound_tags {
-			options.push_str(&format!("crypto.ratchet.outboundTags={outbound_tags} "));
-		}
-		if let Some(tags_to_send) = &self.tags_to_send {
-			options.push_str(&format!("crypto.tagsToSend={tags_to_send} "));
-		}
-		options
+		format_pairs(self.pairs())
+	}
+
+	fn pairs(&self) -> Vec<(String, String)> {
+		let mut pairs = Vec::new();
+		push(
+			&mut pairs,
+			"crypto.lowTagThreshold",
+			&self.low_tag_threshold,
+		);
+		push(
+			&mut pairs,
+			"crypto.ratchet.inboundTags",
+			&self.ratchet_inbound_tags,
+		);
+		push(
+			&mut pairs,
+			"crypto.ratchet.outboundTags",
+			&self.ratchet_outbound_tags,
+		);
+		push(&mut pairs, "crypto.tagsToSend", &self.tags_to_send);
+		pairs
 	}
 }
 
 impl I2CPTunnelInboundOptions {
 	pub fn string(&self) -> String {
-		let mut options = String::default();
-		if let Some(allow_zero_hop) = &self.allow_zero_hop {
-			options.push_str(&format!("inbound.allowZeroHop={allow_zero_hop} "));
-		}
-		if let Some(backup_quantity) = &self.backup_quantity {
-			options.push_str(&format!("inbound.backupQuantity={backup_quantity} "));
-		}
-		if let Some(ip_restriction) = &self.ip_restriction {
-			options.push_str(&format!("inbound.IPRestriction={ip_restriction} "));
-		}
-		if let Some(length) = &self.length {
-			options.push_str(&format!("inbound.length={length} "));
-		}
-		if let Some(length_variance) = &self.length_variance {
-			options.push_str(&format!("inbound.lengthVariance{length_variance} "));
-		}
-		if let Some(quantity) = &self.quantity {
-			options.push_str(&format!("inbound.quantity={quantity} "));
-		}
-		if let Some(random_key) = &self.random_key {
-			options.push_str(&format!("inbound.randomKey={random_key} "));
-		}
-		options
+		format_pairs(self.pairs())
+	}
+
+	fn pairs(&self) -> Vec<(String, String)> {
+		let mut pairs = Vec::new();
+		push(&mut pairs, "inbound.allowZeroHop", &self.allow_zero_hop);
+		push(&mut pairs, "inbound.backupQuantity", &self.backup_quantity);
+		push(&mut pairs, "inbound.IPRestriction", &self.ip_restriction);
+		push(&mut pairs, "inbound.length", &self.length);
+		push(&mut pairs, "inbound.lengthVariance", &self.length_variance);
+		push(&mut pairs, "inbound.quantity", &self.quantity);
+		push(&mut pairs, "inbound.randomKey", &self.random_key);
+		push(&mut pairs, "inbound.nickname", &self.nickname);
+		pairs
 	}
 }
 
 impl I2CPTunnelOutboundOptions {
 	pub fn string(&self) -> String {
-		let mut options = String::default();
-		if let Some(allow_zero_hop) = &self.allow_zero_hop {
-			options.push_str(&format!("outbound.allowZeroHop={allow_zero_hop} "));
-		}
-		if let Some(backup_quantity) = &self.backup_quantity {
-			options.push_str(&format!("outbound.backupQuantity={backup_quantity} "));
-		}
-		if let Some(ip_restriction) = &self.ip_restriction {
-			options.push_str(&format!("outbound.IPRestriction={ip_restriction} "));
-		}
-		if let Some(length) = &self.length {
-			options.push_str(&format!("outbound.length={length} "));
-		}
-		if let Some(length_variance) = &self.length_variance {
-			options.push_str(&format!("outbound.lengthVariance{length_variance} "));
-		}
-		if let Some(priority) = &self.priority {
-			options.push_str(&format!("outbound.priority={priority} "));
-		}
-		if let Some(quantity) = &self.quantity {
-			options.push_str(&format!("outbound.quantity={quantity} "));
-		}
-		if let Some(random_key) = &self.random_key {
-			options.push_str(&format!("outbound.randomKey={random_key} "));
+		format_pairs(self.pairs())
+	}
+
+	fn pairs(&self) -> Vec<(String, String)> {
+		let mut pairs = Vec::new();
+		push(&mut pairs, "outbound.allowZeroHop", &self.allow_zero_hop);
+		push(&mut pairs, "outbound.backupQuantity", &self.backup_quantity);
+		push(&mut pairs, "outbound.IPRestriction", &self.ip_restriction);
+		push(&mut pairs, "outbound.length", &self.length);
+		push(&mut pairs, "outbound.lengthVariance", &self.length_variance);
+		push(&mut pairs, "outbound.priority", &self.priority);
+		push(&mut pairs, "outbound.quantity", &self.quantity);
+		push(&mut pairs, "outbound.randomKey", &self.random_key);
+		push(&mut pairs, "outbound.nickname", &self.nickname);
+		pairs
+	}
+}
+
+/// Appends `key` and the string form of `value`, if set.
+fn push<T: fmt::Display>(pairs: &mut Vec<(String, String)>, key: &str, value: &Option<T>) {
+	if let Some(value) = value {
+		pairs.push((key.to_string(), value.to_string()));
+	}
+}
+
+/// Formats `key=value ` options, ordered by name and without duplicates.
+fn format_pairs(pairs: Vec<(String, String)>) -> String {
+	let pairs: BTreeMap<_, _> = pairs.into_iter().collect();
+	pairs
+		.iter()
+		.map(|(key, value)| format!("{key}={} ", quote(value)))
+		.collect()
+}
+
+/// Quotes `value` for the SAM bridge if it is empty or contains spaces,
+/// quotes, backslashes or `=`.
+fn quote(value: &str) -> Cow<'_, str> {
+	let special = |c: char| c.is_whitespace() || matches!(c, '"' | '\\' | '=');
+	if !value.is_empty() && !value.contains(special) {
+		return Cow::Borrowed(value);
+	}
+	let mut quoted = String::with_capacity(value.len() + 2);
+	quoted.push('"');
+	for c in value.chars() {
+		if matches!(c, '"' | '\\') {
+			quoted.push('\\');
 		}
-		options
+		quoted.push(c);
 	}
+	quoted.push('"');
+	Cow::Owned(quoted)
 }
 
-impl ToString for LeaseSetType {
-	fn to_string(&self) -> String {
-		format!("{}", self.0)
+impl fmt::Display for InactivityAction {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", *self as u8)
 	}
 }
 
-impl ToString for LeaseSetBlindedType {
-	fn to_string(&self) -> String {
-		format!("{}", self.0)
+impl fmt::Display for StreamingProfile {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", *self as u8)
 	}
 }
 
-impl ToString for LeaseSetAuthType {
-	fn to_string(&self) -> String {
-		match self {
-			Self::NoPerClient => String::from("0"),
-			Self::DHPerClient => String::from("1"),
-			Self::PSKPerClient => String::from("2"),
-		}
+impl fmt::Display for LeaseSetType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", *self as u8)
+	}
+}
+
+impl fmt::Display for LeaseSetBlindedType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0.clone() as u16)
+	}
+}
+
+impl fmt::Display for LeaseSetAuthType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::NoPerClient => "0",
+			Self::DHPerClient => "1",
+			Self::PSKPerClient => "2",
+		})
+	}
+}
+
+impl fmt::Display for LeaseSetOfflineSignature {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
 	}
 }
 
-impl ToString for LeaseSetOfflineSignature {
-	fn to_string(&self) -> String {
-		self.0.clone()
+impl fmt::Display for LeaseSetEncType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let types: Vec<String> = self.0.iter().map(EncType::to_string).collect();
+		f.write_str(&types.join(","))
 	}
 }
 
-impl ToString for LeaseSetEncType {
-	fn to_string(&self) -> String {
-		self.0.clone()
+impl fmt::Display for EncType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", *self as u16)
 	}
 }
 
-impl ToString for LeaseSetPrivKey {
-	fn to_string(&self) -> String {
-		self.0.clone()
+impl fmt::Display for LeaseSetPrivKey {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
 	}
 }
 
-impl ToString for LeaseSetPrivateKey {
-	fn to_string(&self) -> String {
-		self.0.clone()
+impl fmt::Display for LeaseSetPrivateKey {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
 	}
 }
-impl ToString for LeaseSetKey {
-	fn to_string(&self) -> String {
-		self.0.clone()
+impl fmt::Display for LeaseSetKey {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
 	}
 }
 
-impl ToString for LeaseSetSecret {
-	fn to_string(&self) -> String {
-		self.0.clone()
+impl fmt::Display for LeaseSetSecret {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
 	}
 }
-impl ToString for LeaseSetTransientPublicKey {
-	fn to_string(&self) -> String {
-		self.0.clone()
+impl fmt::Display for LeaseSetTransientPublicKey {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
 	}
 }
-impl ToString for LeaseSetSigningPrivateKey {
-	fn to_string(&self) -> String {
-		self.0.clone()
+impl fmt::Display for LeaseSetSigningPrivateKey {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
 	}
 }
 
+/// Prefers ECIES-X25519, falling back to ElGamal for older peers.
 impl Default for LeaseSetEncType {
 	fn default() -> LeaseSetEncType {
-		LeaseSetEncType::from("4,0")
+		LeaseSetEncType(vec![EncType::EciesX25519, EncType::ElGamal])
 	}
 }
 
-impl From<String> for LeaseSetEncType {
-	fn from(val: String) -> LeaseSetEncType {
-		LeaseSetEncType(val)
+impl From<Vec<EncType>> for LeaseSetEncType {
+	fn from(types: Vec<EncType>) -> LeaseSetEncType {
+		LeaseSetEncType(types)
 	}
 }
 
-impl From<&str> for LeaseSetEncType {
-	fn from(val: &str) -> LeaseSetEncType {
-		LeaseSetEncType(val.to_string())
+impl From<EncType> for LeaseSetEncType {
+	fn from(enc_type: EncType) -> LeaseSetEncType {
+		LeaseSetEncType(vec![enc_type])
 	}
 }
 
-impl Default for LeaseSetAuthType {
-	fn default() -> Self {
-		Self::NoPerClient
+impl LeaseSetEncType {
+	pub fn types(&self) -> &[EncType] {
+		&self.0
 	}
 }
 
-impl Default for LeaseSetType {
-	fn default() -> Self {
-		LeaseSetType(1)
+impl AccessList {
+	pub fn new(addrs: Vec<I2pAddr>) -> AccessList {
+		AccessList(addrs)
+	}
+
+	pub fn addrs(&self) -> &[I2pAddr] {
+		&self.0
 	}
 }
 
-impl Default for MessageReliability {
-	fn default() -> Self {
-		Self::None
+impl From<Vec<I2pAddr>> for AccessList {
+	fn from(addrs: Vec<I2pAddr>) -> AccessList {
+		AccessList(addrs)
 	}
 }
 
-impl ToString for MessageReliability {
-	fn to_string(&self) -> String {
-		match self {
-			Self::BestEffort => String::from("BestEffort"),
-			Self::None => String::from("None"),
-		}
+/// Lists the base 64 hashes of the destinations, comma-separated. Addresses
+/// other than B32 ones, which [SAMOptions::validate] reports, are listed
+/// as is.
+impl fmt::Display for AccessList {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let hashes: Vec<String> = self
+			.0
+			.iter()
+			.map(|addr| match b32_hash(addr) {
+				Some(hash) => BASE64_I2P.encode(&hash),
+				None => addr.string(),
+			})
+			.collect();
+		f.write_str(&hashes.join(","))
 	}
 }
 
-impl ToString for SAMOptions {
-	fn to_string(&self) -> String {
-		let mut options = String::default();
-		if let Some(from_port) = &self.from_port {
-			options.push_str(&format!("FROM_PORT={from_port} "));
-		}
-		if let Some(to_port) = &self.to_port {
-			options.push_str(&format!("TO_PORT={to_port} "));
-		}
-		if let Some(i2cp_options) = &self.i2cp_options {
-			let i2cp_options_str = i2cp_options.string();
-			if !i2cp_options_str.is_empty() {
-				options.push_str(i2cp_options_str.as_str());
-			}
-		}
-		// make sure to remove duplicate options
-		// todo: there is likely a better way of handling this
-		let mut options_parts: Vec<_> = options.split(' ').collect();
-		options_parts.sort_unstable();
-		options_parts.dedup();
-		let mut options_parsed = String::default();
-		for option_part in options_parts.clone() {
-			options_parsed.push_str(&format!("{option_part} "));
-		}
-		options_parsed
+/// Returns the hash of the destination of a B32 address.
+fn b32_hash(addr: &I2pAddr) -> Option<Vec<u8>> {
+	let addr = addr.string().to_ascii_lowercase();
+	let hash = BASE32_I2P
+		.decode(addr.strip_suffix(B32_EXT)?.as_bytes())
+		.ok()?;
+	Some(hash).filter(|hash| hash.len() == 32)
+}
+
+/// Parses comma-separated encryption types, e.g. `4,0`.
+impl FromStr for LeaseSetEncType {
+	type Err = I2PError;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(Self::parse("i2cp.leaseSetEncType", s)?)
 	}
 }
 
-impl ToString for SignatureType {
-	fn to_string(&self) -> String {
-		match self {
-			Self::DsaSha1 => "DSA_SHA1".to_string(),
-			Self::EcdsaSha256P256 => "ECDSA_SHA256_P256".to_string(),
-			Self::EcdsaSha384P384 => "ECDSA_SHA384_P384".to_string(),
-			Self::EcdsaSha512P521 => "ECDSA_SHA512_P521".to_string(),
-			Self::RsaSha256_2048 => "RSA_SHA256_2048".to_string(),
-			Self::RsaSha384_3072 => "RSA_SHA384_3072".to_string(),
-			Self::RsaSha512_4096 => "RSA_SHA512_4096".to_string(),
-			Self::EdDsaSha512Ed25519 => "EdDSA_SHA512_Ed25519".to_string(),
-			Self::EdDsaSha512Ed25519ph => "EdDSA_SHA512_Ed25519ph".to_string(),
-			Self::RedDsaSha512Ed25519 => "RedDSA_SHA512_Ed25519".to_string(),
+/// Parses an encryption type from its number or name, e.g. `4` or
+/// `ECIES_X25519`.
+impl FromStr for EncType {
+	type Err = I2PError;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(Self::parse("i2cp.leaseSetEncType", s)?)
+	}
+}
+
+impl LeaseSetOfflineSignature {
+	pub fn new(signature: &str) -> Result<Self> {
+		Self::parse("i2cp.leaseSetOfflineSignature", signature).map_err(I2PError::from)
+	}
+}
+
+impl LeaseSetPrivKey {
+	/// `key` is optionally preceded by its type and ':', only
+	/// `ECIES_X25519:` is supported.
+	pub fn new(key: &str) -> Result<Self> {
+		Self::parse("i2cp.leaseSetPrivKey", key).map_err(I2PError::from)
+	}
+}
+
+impl LeaseSetPrivateKey {
+	/// `keys` are comma-separated, each optionally preceded by its
+	/// encryption type and ':'.
+	pub fn new(keys: &str) -> Result<Self> {
+		Self::parse("i2cp.leaseSetPrivateKey", keys).map_err(I2PError::from)
+	}
+}
+
+impl LeaseSetKey {
+	/// `key` is a base 64 SessionKey of 32 bytes.
+	pub fn new(key: &str) -> Result<Self> {
+		Self::parse("i2cp.leaseSetKey", key).map_err(I2PError::from)
+	}
+}
+
+impl LeaseSetSecret {
+	/// Encodes `secret` to base 64.
+	pub fn new(secret: &str) -> Self {
+		Self(BASE64_I2P.encode(secret.as_bytes()))
+	}
+}
+
+impl LeaseSetTransientPublicKey {
+	/// `key` is optionally preceded by its signature type and ':'.
+	pub fn new(key: &str) -> Result<Self> {
+		Self::parse("i2cp.leaseSetTransientPublicKey", key).map_err(I2PError::from)
+	}
+}
+
+impl LeaseSetSigningPrivateKey {
+	/// `key` is optionally preceded by its signature type and ':'.
+	pub fn new(key: &str) -> Result<Self> {
+		Self::parse("i2cp.leaseSetSigningPrivateKey", key).map_err(I2PError::from)
+	}
+}
+
+/// Checks that `value` is made of comma-separated base 64 strings, each
+/// preceded by a type and ':' if `typed`.
+fn base64_option(key: &str, value: &str, typed: bool) -> Result<String, InvalidOption> {
+	for part in value.split(',') {
+		let data = match part.split_once(':') {
+			Some((_, data)) if typed => data,
+			_ => part,
+		};
+		if data.is_empty() || BASE64_I2P.decode(data.as_bytes()).is_err() {
+			return Err(InvalidOption::new(key, value, "not valid base 64"));
 		}
 	}
+	Ok(value.to_string())
+}
+
+impl fmt::Display for MessageReliability {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::BestEffort => "BestEffort",
+			Self::None => "None",
+		})
+	}
+}
+
+impl fmt::Display for SAMOptions {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let options: Vec<_> = self
+			.pairs()
+			.iter()
+			.map(|(key, value)| format!("{key}={}", quote(value)))
+			.collect();
+		f.write_str(&options.join(" "))
+	}
+}
+
+impl fmt::Display for SignatureType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Self::DsaSha1 => "DSA_SHA1",
+			Self::EcdsaSha256P256 => "ECDSA_SHA256_P256",
+			Self::EcdsaSha384P384 => "ECDSA_SHA384_P384",
+			Self::EcdsaSha512P521 => "ECDSA_SHA512_P521",
+			Self::RsaSha256_2048 => "RSA_SHA256_2048",
+			Self::RsaSha384_3072 => "RSA_SHA384_3072",
+			Self::RsaSha512_4096 => "RSA_SHA512_4096",
+			Self::EdDsaSha512Ed25519 => "EdDSA_SHA512_Ed25519",
+			Self::EdDsaSha512Ed25519ph => "EdDSA_SHA512_Ed25519ph",
+			Self::RedDsaSha512Ed25519 => "RedDSA_SHA512_Ed25519",
+		})
+	}
 }
 
 impl FromStr for SignatureType {
@@ -731,11 +2330,6 @@ impl FromStr for SignatureType {
 }
 
 
-impl Default for SignatureType {
-	fn default() -> Self {
-		Self::EdDsaSha512Ed25519
-	}
-}
 
 impl TryFrom<u16> for SignatureType {
 	type Error = String;
@@ -762,7 +2356,11 @@ mod test {
 	use super::*;
 	#[test]
 	fn test_sigs() {
-		let mut sam_conn = SamConnection::connect(DEFAULT_API).unwrap();
+		// needs a running router
+		let Ok(mut sam_conn) = SamConnection::connect(DEFAULT_API) else {
+			eprintln!("no SAM bridge at {DEFAULT_API}, skipping");
+			return;
+		};
 		let (pubkey, seckey) = sam_conn
 			.generate_destination(SignatureType::RedDsaSha512Ed25519)
 			.unwrap();
@@ -796,4 +2394,337 @@ mod test {
 		assert_eq!(SignatureType::from_str("RedDsa_Sha512_Ed25519").unwrap(), SignatureType::RedDsaSha512Ed25519);
 		assert!(SignatureType::from_str("foobar").is_err());
 	}
+
+	#[test]
+	fn test_builder() {
+		let options = SAMOptions::builder()
+			.inbound_length(2)
+			.inbound_quantity(3)
+			.outbound_length(0)
+			.outbound_priority(-25)
+			.lease_set_enc_type(EncType::EciesX25519)
+			.lease_set_type(LeaseSetType::LeaseSet2)
+			.build()
+			.unwrap();
+		assert_eq!(
+			options.options(),
+			"i2cp.leaseSetEncType=4 i2cp.leaseSetType=3 inbound.length=2 inbound.quantity=3 \
+			outbound.length=0 outbound.priority=-25"
+		);
+	}
+
+	#[test]
+	fn test_presets() {
+		let presets = [
+			SAMOptions::high_anonymity,
+			SAMOptions::balanced,
+			SAMOptions::low_latency,
+			SAMOptions::bulk_transfer,
+			SAMOptions::interactive,
+			SAMOptions::idle_friendly,
+		];
+		for preset in presets {
+			let pairs = preset().build().unwrap().pairs();
+			assert_eq!(pairs["inbound.length"], pairs["outbound.length"]);
+			assert_eq!(pairs["inbound.quantity"], pairs["outbound.quantity"]);
+			assert!(pairs.contains_key("i2cp.reduceOnIdle"));
+			assert!(pairs.contains_key("i2cp.closeOnIdle"));
+		}
+
+		let pairs = SAMOptions::low_latency().build().unwrap().pairs();
+		assert_eq!(pairs["inbound.length"], "1");
+		assert_eq!(pairs["i2cp.reduceOnIdle"], "false");
+		assert_eq!(pairs["i2p.streaming.profile"], "2");
+		let pairs = SAMOptions::idle_friendly().build().unwrap().pairs();
+		assert_eq!(pairs["i2cp.reduceQuantity"], "1");
+		assert_eq!(pairs["i2cp.closeIdleTime"], "1800000");
+
+		// presets are overridden field by field
+		let options = SAMOptions::bulk_transfer()
+			.outbound_quantity(3)
+			.reduce_on_idle(Duration::from_secs(600), 1)
+			.build()
+			.unwrap();
+		assert_eq!(options.pairs()["inbound.quantity"], "5");
+		assert_eq!(options.pairs()["outbound.quantity"], "3");
+		assert_eq!(options.pairs()["i2cp.reduceQuantity"], "1");
+		assert!(SAMOptions::balanced().inbound_length(8).build().is_err());
+	}
+
+	#[test]
+	fn test_validate() {
+		assert!(SAMOptions::default().validate().is_ok());
+		assert!(SAMOptions::builder().inbound_length(7).build().is_ok());
+
+		let err = SAMOptions::builder()
+			.inbound_length(8)
+			.outbound_quantity(0)
+			.reduce_on_idle(Duration::from_secs(60), 1)
+			.lease_set_enc_type(vec![EncType::ElGamal, EncType::ElGamal])
+			.build()
+			.unwrap_err();
+		let errors = match err {
+			I2PError::InvalidOptions(errors) => errors,
+			_ => panic!("unexpected error {err}"),
+		};
+		let keys: Vec<_> = errors.iter().map(|e| e.key.as_str()).collect();
+		assert_eq!(
+			keys,
+			[
+				"i2cp.leaseSetEncType",
+				"inbound.length",
+				"outbound.quantity",
+				"i2cp.leaseSetEncType",
+				"i2cp.reduceIdleTime",
+			]
+		);
+		assert_eq!(errors[1].value, "8");
+		assert_eq!(errors[1].reason, "out of range 0..=7");
+	}
+
+	#[test]
+	fn test_enc_types() {
+		assert_eq!(LeaseSetEncType::default().to_string(), "4,0");
+		assert_eq!(
+			LeaseSetEncType::from_str("ECIES_X25519,0").unwrap().types(),
+			[EncType::EciesX25519, EncType::ElGamal]
+		);
+		assert!(LeaseSetEncType::from_str("4,1").is_err());
+		assert!(EncType::from_str("").is_err());
+	}
+
+	#[test]
+	fn test_lease_set_key() {
+		let key = BASE64_I2P.encode(&[7u8; 32]);
+		assert!(LeaseSetKey::new(&key).is_ok());
+		assert!(LeaseSetKey::new(&BASE64_I2P.encode(&[7u8; 16])).is_err());
+		assert!(LeaseSetKey::new("not base 64!").is_err());
+	}
+
+	#[test]
+	fn test_access_list() {
+		let hash = BASE64_I2P.encode(&[7u8; 32]);
+		let b32 = I2pAddr::new(&format!("{}{B32_EXT}", BASE32_I2P.encode(&[7u8; 32])));
+		let list = AccessList::parse("i2cp.accessList", &format!("{hash}, {b32}")).unwrap();
+		assert_eq!(list.addrs(), [b32.clone(), b32.clone()]);
+		assert_eq!(list.to_string(), format!("{hash},{hash}"));
+		assert!(AccessList::parse("i2cp.accessList", "example.i2p").is_err());
+
+		let options = SAMOptions::builder().deny(vec![b32.clone()]).build().unwrap();
+		assert_eq!(options.pairs()["i2cp.accessList"], hash);
+		assert_eq!(options.pairs()["i2cp.enableBlackList"], "true");
+		let denied = SAMOptions::builder().deny(vec![I2pAddr::new("example.i2p")]);
+		assert!(denied.build().is_err());
+
+		// the last list set wins
+		let options = SAMOptions::builder()
+			.allow_only(vec![I2pAddr::new("example.i2p")])
+			.deny(vec![b32])
+			.build()
+			.unwrap();
+		assert_eq!(options.pairs()["i2cp.enableBlackList"], "true");
+		assert!(!options.pairs().contains_key("i2cp.enableAccessList"));
+		let both = SAMOptions::from_pairs([
+			("i2cp.enableAccessList", "true"),
+			("i2cp.enableBlackList", "true"),
+			("i2cp.accessList", hash.as_str()),
+		])
+		.unwrap();
+		assert!(both.validate().is_err());
+	}
+
+	fn inbound(options: &SAMOptions) -> &I2CPTunnelInboundOptions {
+		let i2cp_options = options.i2cp_options.as_ref().unwrap();
+		let router_options = i2cp_options.router_options.as_ref().unwrap();
+		router_options.inbound.as_ref().unwrap()
+	}
+
+	#[test]
+	fn test_parse() {
+		let options: SAMOptions = "inbound.length=3 outbound.quantity=2 \
+			i2cp.leaseSetEncType=4,0 i2cp.tcp.host=\"local host\" SIGNATURE_TYPE=11"
+			.parse()
+			.unwrap();
+		assert_eq!(inbound(&options).length, Some(3));
+		assert_eq!(options.signature_type, SignatureType::RedDsaSha512Ed25519);
+		let pairs = options.pairs();
+		assert_eq!(pairs["outbound.quantity"], "2");
+		assert_eq!(pairs["i2cp.tcp.host"], "local host");
+
+		let err = SAMOptions::from_str("inbound.length=x foo=1 i2cp.gzip=yes").unwrap_err();
+		let I2PError::InvalidOptions(errors) = err else {
+			panic!("unexpected error {err}");
+		};
+		let keys: Vec<_> = errors.iter().map(|e| e.key.as_str()).collect();
+		assert_eq!(keys, ["inbound.length", "i2cp.gzip"]);
+		assert_eq!(errors[1].reason, "not true or false");
+		assert!(SAMOptions::from_str("inbound length=1").is_err());
+	}
+
+	#[test]
+	fn test_serialize() {
+		let mut options = SAMOptions::builder()
+			.inbound_length_variance(-1)
+			.outbound_length_variance(2)
+			.credentials("user", r#"pass "word"\"#)
+			.from_port(80)
+			.build()
+			.unwrap();
+		let string = options.to_string();
+		assert_eq!(
+			string,
+			"FROM_PORT=80 i2cp.leaseSetEncType=4,0 i2cp.password=\"pass \\\"word\\\"\\\\\" \
+			i2cp.username=user inbound.lengthVariance=-1 outbound.lengthVariance=2"
+		);
+		let parsed: SAMOptions = string.parse().unwrap();
+		assert_eq!(parsed.pairs(), options.pairs());
+
+		options.set("i2cp.leaseSetEncType", "4").unwrap();
+		options.client().lease_set_enc_type = Some(EncType::ElGamal.into());
+		assert_eq!(options.pairs()["i2cp.leaseSetEncType"], "0");
+		let err = options.validate().unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"Invalid SAM options: i2cp.leaseSetEncType=0: conflicts with i2cp.leaseSetEncType=4"
+		);
+	}
+
+	#[test]
+	fn test_extra_options() {
+		let key = BASE64_I2P.encode(&[1u8; 32]);
+		let options: SAMOptions = format!(
+			"i2p.streaming.maxConnsPerMinute=5 i2p.streaming.profile=1 inbound.nickname=site \
+			i2cp.destination.sigType=7 i2cp.leaseSetClient.dh.0=alice:{key} \
+			i2cp.leaseSetClient.psk.0=bob:{key} i2p.streaming.newOption=x"
+		)
+		.parse()
+		.unwrap();
+		let streaming = options.streaming_options.as_ref().unwrap();
+		assert_eq!(streaming.max_conns_per_minute, Some(5));
+		assert_eq!(streaming.profile, Some(StreamingProfile::Bulk));
+		assert_eq!(inbound(&options).nickname.as_deref(), Some("site"));
+		let client_options = options.i2cp_options.as_ref().unwrap();
+		let client_options = client_options.client_options.as_ref().unwrap();
+		assert_eq!(
+			client_options.lease_set_clients.as_ref().unwrap()[0],
+			LeaseSetClient::Dh {
+				name: "alice".to_string(),
+				public_key: key.clone(),
+			}
+		);
+		assert_eq!(options.extra["i2p.streaming.newOption"], "x");
+		let pairs = options.pairs();
+		assert_eq!(pairs["i2cp.leaseSetClient.psk.0"], format!("bob:{key}"));
+		assert_eq!(pairs["i2p.streaming.newOption"], "x");
+		assert_eq!(pairs["i2cp.destination.sigType"], "EdDSA_SHA512_Ed25519");
+		let parsed: SAMOptions = options.to_string().parse().unwrap();
+		assert_eq!(parsed.pairs(), pairs);
+		assert!(options.validate().is_ok());
+
+		let err = SAMOptions::builder()
+			.inbound_length(2)
+			.option("inbound.length", "3")
+			.option("outbound.quantity", "many")
+			.option("bad key", "1")
+			.streaming_options(StreamingOptions {
+				max_window_size: Some(256),
+				..Default::default()
+			})
+			.build()
+			.unwrap_err();
+		let I2PError::InvalidOptions(errors) = &err else {
+			panic!("unexpected error {err}");
+		};
+		let keys: Vec<_> = errors.iter().map(|e| e.key.as_str()).collect();
+		assert_eq!(
+			keys,
+			[
+				"inbound.length",
+				"bad key",
+				"outbound.quantity",
+				"i2p.streaming.maxWindowSize"
+			]
+		);
+		assert!(SAMOptions::from_str("i2cp.leaseSetClient.dh.0=alice").is_err());
+
+		// extras naming typed options get the same range checks
+		let err = SAMOptions::builder()
+			.option("inbound.length", "99")
+			.option("i2p.streaming.maxWindowSize", "256")
+			.build()
+			.unwrap_err();
+		let I2PError::InvalidOptions(errors) = &err else {
+			panic!("unexpected error {err}");
+		};
+		let keys: Vec<_> = errors.iter().map(|e| e.key.as_str()).collect();
+		assert_eq!(keys, ["inbound.length", "i2p.streaming.maxWindowSize"]);
+	}
+
+	#[test]
+	fn test_serde() {
+		let json = r#"{"inbound.length": 2, "inbound": {"quantity": 3},
+			"i2cp.leaseSetEncType": [4], "i2cp.gzip": false, "TO_PORT": 80}"#;
+		let options: SAMOptions = serde_json::from_str(json).unwrap();
+		assert_eq!(inbound(&options).length, Some(2));
+		assert_eq!(inbound(&options).quantity, Some(3));
+		assert_eq!(options.to_port, Some(80));
+		assert_eq!(options.pairs()["i2cp.leaseSetEncType"], "4");
+
+		let toml = "
+			SIGNATURE_TYPE = \"EdDSA_SHA512_Ed25519\"
+			inbound.length = 1
+			outbound.length = 1
+			i2cp.leaseSetType = 3
+			i2cp.reduceOnIdle = true
+		";
+		let options: SAMOptions = toml::from_str(toml).unwrap();
+		assert_eq!(inbound(&options).length, Some(1));
+		let reparsed: SAMOptions = toml::from_str(&toml::to_string(&options).unwrap()).unwrap();
+		assert_eq!(reparsed.to_string(), options.to_string());
+
+		let err = serde_json::from_str::<SAMOptions>(r#"{"inbound.length": "long"}"#);
+		assert!(err.unwrap_err().to_string().contains("inbound.length=long"));
+	}
+
+	#[test]
+	fn test_tunnel_configs() {
+		let config = "
+			# i2ptunnel.config
+			tunnel.0.name=I2P HTTP Proxy
+			tunnel.0.option.inbound.length=3
+			tunnel.1.name=eepsite
+			tunnel.1.type=httpserver
+			tunnel.1.option.inbound.length=2
+			tunnel.1.option.outbound.quantity=4
+			tunnel.1.option.i2cp.leaseSetEncType=4,0
+			tunnel.1.option.i2p.streaming.maxConnsPerMinute=10
+		";
+		let options = SAMOptions::from_i2ptunnel_config(config, "eepsite").unwrap();
+		assert_eq!(inbound(&options).length, Some(2));
+		assert_eq!(options.pairs()["outbound.quantity"], "4");
+		let config = "name=eepsite\noption.inbound.length=1\n";
+		let options = SAMOptions::from_i2ptunnel_config(config, "eepsite").unwrap();
+		assert_eq!(inbound(&options).length, Some(1));
+		assert!(SAMOptions::from_i2ptunnel_config(config, "irc").is_err());
+
+		let config = "
+			[irc]
+			type = client
+			inbound.length = 3
+
+			; our site
+			[eepsite]
+			type = http
+			host = 127.0.0.1
+			port = 8080
+			keys = eepsite.dat
+			signaturetype = 7
+			inbound.length = 1
+			i2cp.leaseSetType = 3
+		";
+		let options = SAMOptions::from_i2pd_tunnels_conf(config, "eepsite").unwrap();
+		assert_eq!(inbound(&options).length, Some(1));
+		assert_eq!(options.pairs()["i2cp.leaseSetType"], "3");
+		assert!(SAMOptions::from_i2pd_tunnels_conf(config, "proxy").is_err());
+	}
 }
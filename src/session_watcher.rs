@@ -5,17 +5,25 @@
 //! any calls to accept which result in an error will cause the existing session and i2plistener to be dropped,
 //! before they are recreated and an error is returned information the caller to try the operation again
 //!
+//! [SessionSupervisor] goes further: it recreates its session in the
+//! background of the calls, with backoff, keeping the destination and
+//! nickname, and tries accepts and connects again on the new session.
+//!
 
-use anyhow::Result;
-use std::net::Shutdown;
+use crate::error::Result;
+use std::io;
+use std::net::{Shutdown, SocketAddr};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
 
 use crate::{
-	net::{I2pListener, I2pSocketAddr},
-	sam::{nickname, SessionStyle, StreamConnect},
+	net::{I2pListener, I2pSocketAddr, I2pStream, ToI2pSocketAddrs},
+	sam::{nickname, Forwarding, SessionStyle, StreamConnect},
 	sam_options::SAMOptions,
-	I2PError, Session,
+	I2PError, Session, ShutdownHandle,
 };
-use log::error;
+use log::{error, info, warn};
 
 /// SamSessionWatcher provides the ability to gracefully handle
 /// runtime errors by restarting the sam session, and recreating the listener
@@ -64,11 +72,11 @@ impl SamSessionWatcher {
 					self.session.sam.conn.shutdown(Shutdown::Both)?;
 				}
 				self.recreate()?;
-				Err(I2PError::SessionRecreated.into())
+				Err(I2PError::SessionRecreated)
 			}
 		}
 	}
-	pub fn forward(self: &mut Box<Self>, host: &str, port: &str) -> Result<(StreamConnect, I2pSocketAddr)> {
+	pub fn forward(self: &mut Box<Self>, host: &str, port: u16) -> Result<Forwarding> {
 		match self.listener.forward.forward(host, port) {
 			Ok(res) => Ok(res),
 			Err(err) => {
@@ -77,7 +85,7 @@ impl SamSessionWatcher {
 					self.session.sam.conn.shutdown(Shutdown::Both)?;
 				}
 				self.recreate()?;
-				Err(I2PError::SessionRecreated.into())
+				Err(I2PError::SessionRecreated)
 			}
 		}
 	}
@@ -105,3 +113,603 @@ impl SamSessionWatcher {
 		Ok((session, listener))
 	}
 }
+
+/// The states of a [SessionSupervisor]'s session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionState {
+	/// The first session is being created.
+	Connecting,
+	/// The session is up and its last operation succeeded.
+	Ready,
+	/// The session is up, but peers couldn't be reached, e.g. while its
+	/// tunnels are built.
+	Degraded,
+	/// The session was lost and is being created again.
+	Recreating,
+	/// Creating the session failed, and was given up. The next operation
+	/// starts over.
+	Failed,
+}
+
+/// How a [SessionSupervisor] waits between attempts.
+///
+/// The delay starts at `initial_delay` and doubles after each failed
+/// attempt, up to `max_delay`. Each wait is then picked at random between
+/// half the delay and the delay, so that clients of a restarted router
+/// don't all come back at once.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+	pub initial_delay: Duration,
+	pub max_delay: Duration,
+	/// Attempts at creating the session before giving up, None to never give
+	/// up. Operations wait on the attempts in the meantime.
+	pub max_attempts: Option<u32>,
+	/// Times an operation is tried again after failing.
+	pub max_retries: u32,
+}
+
+impl Default for Backoff {
+	fn default() -> Backoff {
+		Backoff {
+			initial_delay: Duration::from_secs(1),
+			max_delay: Duration::from_secs(60),
+			max_attempts: None,
+			max_retries: 3,
+		}
+	}
+}
+
+impl Backoff {
+	/// The waits of accept loops, whose errors, such as running out of file
+	/// descriptors, tend to pass quickly.
+	pub(crate) fn accept() -> Backoff {
+		Backoff {
+			initial_delay: Duration::from_millis(10),
+			max_delay: Duration::from_secs(1),
+			max_attempts: None,
+			max_retries: 0,
+		}
+	}
+
+	/// The wait after `attempt` failed attempts.
+	pub(crate) fn delay(&self, attempt: u32) -> Duration {
+		let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+		let delay = self
+			.initial_delay
+			.saturating_mul(factor)
+			.min(self.max_delay);
+		delay / 2 + delay.mul_f64(rand::random::<f64>() / 2.0)
+	}
+}
+
+/// A STREAM session kept up by the supervisor: whenever it is lost, it is
+/// created again with the same destination and nickname, waiting longer
+/// after each failed attempt, see [Backoff].
+///
+/// [accept](SessionSupervisor::accept) and
+/// [connect](SessionSupervisor::connect) are tried again when they fail
+/// because the session was lost, once it is back, or because a peer
+/// couldn't be reached. Other errors, such as an unknown host, are
+/// returned right away. The session is created by the first operation, or
+/// by [start](SessionSupervisor::start).
+///
+/// While the session is created, every operation waits for it,
+/// [session](SessionSupervisor::session) included: with no
+/// [max_attempts](Backoff::max_attempts), for as long as the bridge can't
+/// be reached. [shutdown](SessionSupervisor::shutdown) ends the waits.
+///
+/// Changes of the session's [SessionState] are published to the receivers
+/// returned by [states](SessionSupervisor::states).
+///
+/// # Examples
+///
+/// ```no_run
+/// use i2p::session_watcher::SessionSupervisor;
+///
+/// let supervisor =
+///     SessionSupervisor::new(i2p::sam::DEFAULT_API, "TRANSIENT", Default::default()).unwrap();
+/// let states = supervisor.states();
+/// std::thread::spawn(move || {
+///     for state in states {
+///         println!("session is {:?}", state);
+///     }
+/// });
+/// loop {
+///     let (stream, addr) = supervisor.accept().unwrap();
+///     // ...
+/// }
+/// ```
+pub struct SessionSupervisor {
+	sam_api: SocketAddr,
+	nickname: String,
+	options: SAMOptions,
+	backoff: Backoff,
+	/// The private keys, once the bridge generated them for TRANSIENT.
+	destination: Mutex<String>,
+	/// The session in use, None until it is first created.
+	current: Mutex<Option<Arc<Supervised>>>,
+	status: Mutex<Status>,
+	/// Signalled on shutdown, ending the waits between attempts.
+	stopped: Condvar,
+}
+
+struct Supervised {
+	session: Session,
+	listener: I2pListener,
+}
+
+struct Status {
+	state: SessionState,
+	watchers: Vec<Sender<SessionState>>,
+	/// Shuts the session in use down.
+	session: Option<ShutdownHandle>,
+	shutdown: bool,
+}
+
+/// Why an operation failed.
+enum Failure {
+	/// Interrupted by a signal, tried again right away.
+	Interrupted,
+	/// The stream's own socket failed, the session is fine.
+	Stream,
+	/// A peer couldn't be reached, the session is fine.
+	Peer,
+	/// The session was lost.
+	Session,
+	/// Trying again won't help.
+	Fatal,
+}
+
+impl SessionSupervisor {
+	/// Creates a supervisor of STREAM sessions with `destination`, private
+	/// keys or `TRANSIENT`, and `options`, retrying as [Backoff::default]
+	/// does. No session is created yet.
+	pub fn new<A: std::net::ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		options: SAMOptions,
+	) -> Result<SessionSupervisor> {
+		Self::with_backoff(sam_addr, destination, options, Backoff::default())
+	}
+
+	/// Same as `new`, retrying as `backoff` says.
+	pub fn with_backoff<A: std::net::ToSocketAddrs>(
+		sam_addr: A,
+		destination: &str,
+		options: SAMOptions,
+		backoff: Backoff,
+	) -> Result<SessionSupervisor> {
+		options.validate()?;
+		let sam_api = sam_addr
+			.to_socket_addrs()?
+			.next()
+			.ok_or(I2PError::UnresolvableAddress)?;
+		Ok(SessionSupervisor {
+			sam_api,
+			nickname: nickname(),
+			options,
+			backoff,
+			destination: Mutex::new(destination.to_string()),
+			current: Mutex::default(),
+			status: Mutex::new(Status {
+				state: SessionState::Connecting,
+				watchers: Vec::new(),
+				session: None,
+				shutdown: false,
+			}),
+			stopped: Condvar::new(),
+		})
+	}
+
+	/// Creates the session if it isn't yet.
+	pub fn start(&self) -> Result<()> {
+		self.current().map(|_| ())
+	}
+
+	/// Returns a duplicate of the session in use, creating it if needed.
+	/// Streams opened on it aren't supervised.
+	pub fn session(&self) -> Result<Session> {
+		self.current()?.session.duplicate()
+	}
+
+	pub fn state(&self) -> SessionState {
+		lock(&self.status).state
+	}
+
+	/// Returns a receiver of the current state, then of every change.
+	pub fn states(&self) -> Receiver<SessionState> {
+		let (sender, receiver) = mpsc::channel();
+		let mut status = lock(&self.status);
+		let _ = sender.send(status.state);
+		status.watchers.push(sender);
+		receiver
+	}
+
+	/// Accepts a stream on the session, as [I2pListener::accept] does.
+	pub fn accept(&self) -> Result<(I2pStream, I2pSocketAddr)> {
+		self.retry(|supervised| supervised.listener.accept())
+	}
+
+	/// Opens a stream to `addr` on the session, as
+	/// [I2pStream::connect_with_session] does.
+	pub fn connect<A: ToI2pSocketAddrs>(&self, addr: A) -> Result<I2pStream> {
+		let addr = addr
+			.to_socket_addrs()?
+			.next()
+			.ok_or(I2PError::UnresolvableAddress)?;
+		self.retry(|supervised| I2pStream::connect_with_session(&supervised.session, &addr))
+	}
+
+	/// Shuts the session down for good, interrupting the operations and
+	/// waits in progress, which fail with [I2PError::Shutdown].
+	pub fn shutdown(&self) {
+		let mut status = lock(&self.status);
+		status.shutdown = true;
+		if let Some(session) = status.session.take() {
+			session.shutdown();
+		}
+		self.stopped.notify_all();
+	}
+
+	pub fn is_shutdown(&self) -> bool {
+		lock(&self.status).shutdown
+	}
+
+	fn retry<T>(&self, op: impl Fn(&Supervised) -> Result<T>) -> Result<T> {
+		let mut supervised = self.current()?;
+		let mut retries = 0;
+		loop {
+			let err = match op(&supervised) {
+				Ok(value) => {
+					self.set_state(SessionState::Ready);
+					return Ok(value);
+				}
+				Err(err) => err,
+			};
+			if self.is_shutdown() {
+				return Err(I2PError::Shutdown);
+			}
+			// shut down by another operation recreating it
+			if supervised.session.shutdown_handle().is_shutdown() {
+				supervised = self.current()?;
+				continue;
+			}
+			let failure = classify(&err);
+			if matches!(failure, Failure::Interrupted) {
+				continue;
+			}
+			if matches!(failure, Failure::Fatal) || retries >= self.backoff.max_retries {
+				return Err(err);
+			}
+			retries += 1;
+			match failure {
+				Failure::Stream => {
+					warn!("{} stream failed, trying again: {}", self.nickname, err);
+				}
+				Failure::Peer => {
+					warn!("{} failed on a peer, trying again: {}", self.nickname, err);
+					self.set_state(SessionState::Degraded);
+					self.wait(self.backoff.delay(retries))?;
+				}
+				_ => {
+					error!("{} lost its session, recreating it: {}", self.nickname, err);
+					supervised = self.recreate(&supervised)?;
+				}
+			}
+		}
+	}
+
+	/// The session in use, created if there is none yet.
+	fn current(&self) -> Result<Arc<Supervised>> {
+		let mut current = lock(&self.current);
+		match &*current {
+			Some(supervised) => Ok(supervised.clone()),
+			None => self.establish(&mut current, SessionState::Connecting),
+		}
+	}
+
+	/// Replaces the session `failed`, unless another operation did already.
+	fn recreate(&self, failed: &Arc<Supervised>) -> Result<Arc<Supervised>> {
+		let mut current = lock(&self.current);
+		if let Some(supervised) = &*current {
+			if !Arc::ptr_eq(supervised, failed) {
+				return Ok(supervised.clone());
+			}
+		}
+		// destroys the session on the bridge, freeing its destination and
+		// nickname, and interrupts the accepts pending on it
+		failed.session.shutdown_handle().shutdown();
+		*current = None;
+		self.establish(&mut current, SessionState::Recreating)
+	}
+
+	/// Creates a session, trying again as the backoff allows.
+	fn establish(
+		&self,
+		current: &mut MutexGuard<'_, Option<Arc<Supervised>>>,
+		state: SessionState,
+	) -> Result<Arc<Supervised>> {
+		self.set_state(state);
+		let mut attempts = 0;
+		loop {
+			if self.is_shutdown() {
+				return Err(I2PError::Shutdown);
+			}
+			attempts += 1;
+			let err = match self.create() {
+				Ok(supervised) => {
+					let supervised = Arc::new(supervised);
+					let mut status = lock(&self.status);
+					if status.shutdown {
+						supervised.session.shutdown_handle().shutdown();
+						return Err(I2PError::Shutdown);
+					}
+					status.session = Some(supervised.session.shutdown_handle());
+					drop(status);
+					info!("{} session ready", self.nickname);
+					self.set_state(SessionState::Ready);
+					**current = Some(supervised.clone());
+					return Ok(supervised);
+				}
+				Err(err) => err,
+			};
+			let recoverable = err.is_retryable()
+				|| matches!(
+					err,
+					I2PError::SAMDuplicatedDest(_) | I2PError::SAMDuplicatedId(_)
+				);
+			if !recoverable || self.backoff.max_attempts.is_some_and(|max| attempts >= max) {
+				error!("{} giving up creating its session: {}", self.nickname, err);
+				self.set_state(SessionState::Failed);
+				return Err(err);
+			}
+			warn!(
+				"{} failed to create its session, trying again: {}",
+				self.nickname, err
+			);
+			self.wait(self.backoff.delay(attempts))?;
+		}
+	}
+
+	fn create(&self) -> Result<Supervised> {
+		let destination = lock(&self.destination).clone();
+		let session = Session::create(
+			self.sam_api,
+			&destination,
+			&self.nickname,
+			SessionStyle::Stream,
+			self.options.clone(),
+		)?;
+		// the next sessions keep the destination the bridge generated
+		if let Some(keys) = session.private_keys() {
+			*lock(&self.destination) = keys;
+		}
+		let listener = I2pListener::bind_with_session(&session)?;
+		Ok(Supervised { session, listener })
+	}
+
+	/// Waits for `delay`, failing if shut down in the meantime.
+	fn wait(&self, delay: Duration) -> Result<()> {
+		let status = lock(&self.status);
+		let (status, _) = self
+			.stopped
+			.wait_timeout_while(status, delay, |status| !status.shutdown)
+			.unwrap_or_else(PoisonError::into_inner);
+		match status.shutdown {
+			true => Err(I2PError::Shutdown),
+			false => Ok(()),
+		}
+	}
+
+	fn set_state(&self, state: SessionState) {
+		let mut status = lock(&self.status);
+		if status.state == state {
+			return;
+		}
+		status.state = state;
+		status
+			.watchers
+			.retain(|watcher| watcher.send(state).is_ok());
+	}
+}
+
+impl Drop for SessionSupervisor {
+	fn drop(&mut self) {
+		self.shutdown();
+	}
+}
+
+/// Only errors telling that the bridge lost the session, or can't be
+/// reached anymore, call for a new session. Each stream has its own socket,
+/// so other I/O errors are tried again on the same session: should it be
+/// gone, the bridge answers the next try with `INVALID_ID`.
+fn classify(err: &I2PError) -> Failure {
+	match err {
+		I2PError::SAMInvalidId(_) | I2PError::SessionRecreated => Failure::Session,
+		I2PError::Io(io_err) => match io_err.kind() {
+			io::ErrorKind::Interrupted => Failure::Interrupted,
+			io::ErrorKind::ConnectionRefused => Failure::Session,
+			_ if err.is_retryable() => Failure::Stream,
+			_ => Failure::Fatal,
+		},
+		_ if !err.is_retryable() => Failure::Fatal,
+		I2PError::SAMCantReachPeer(_)
+		| I2PError::SAMPeerNotFound(_)
+		| I2PError::SAMLeaseSetNotFound(_)
+		| I2PError::SAMTimeout(_)
+		| I2PError::SAMAlreadyAccepting(_)
+		| I2PError::SilentStreamClosed => Failure::Peer,
+		_ => Failure::Session,
+	}
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock_bridge::MockBridge;
+	use std::net::TcpListener;
+	use std::thread;
+
+	fn backoff(max_attempts: Option<u32>, max_retries: u32) -> Backoff {
+		Backoff {
+			initial_delay: Duration::from_millis(10),
+			max_delay: Duration::from_millis(50),
+			max_attempts,
+			max_retries,
+		}
+	}
+
+	#[test]
+	fn backoff_delays() {
+		let backoff = Backoff {
+			initial_delay: Duration::from_millis(100),
+			max_delay: Duration::from_secs(1),
+			..Default::default()
+		};
+		for (attempt, delay) in [(1, 100), (3, 400), (10, 1000)] {
+			let delay = Duration::from_millis(delay);
+			let wait = backoff.delay(attempt);
+			assert!(wait >= delay / 2 && wait <= delay, "{attempt}: {wait:?}");
+		}
+	}
+
+	#[test]
+	fn supervisor_recreates_session() {
+		let bridge = MockBridge::start();
+		let supervisor = Arc::new(
+			SessionSupervisor::with_backoff(
+				bridge.addr(),
+				"TRANSIENT",
+				SAMOptions::default(),
+				backoff(Some(20), 2),
+			)
+			.unwrap(),
+		);
+		let states = supervisor.states();
+		supervisor.start().unwrap();
+		let nickname = supervisor.nickname.clone();
+
+		// no peer is accepting
+		let err = supervisor.connect("peer.i2p:80").unwrap_err();
+		assert!(matches!(err, I2PError::SAMCantReachPeer(_)));
+		assert_eq!(supervisor.state(), SessionState::Degraded);
+		let err = supervisor.connect("missing.i2p:80").unwrap_err();
+		assert!(matches!(err, I2PError::SAMKeyNotFound(_)));
+
+		let accepting = supervisor.clone();
+		let accept = thread::spawn(move || accepting.accept());
+		bridge.wait_accepts(&nickname, 1);
+		// the bridge loses the session, dropping the pending accept
+		let first = lock(&supervisor.current).clone().unwrap();
+		first
+			.session
+			.try_clone()
+			.unwrap()
+			.shutdown(Shutdown::Both)
+			.unwrap();
+		while lock(&supervisor.current)
+			.as_ref()
+			.is_none_or(|current| Arc::ptr_eq(current, &first))
+		{
+			thread::sleep(Duration::from_millis(10));
+		}
+		bridge.wait_accepts(&nickname, 1);
+
+		let client = Session::transient(bridge.addr()).unwrap();
+		let _stream = I2pStream::connect_with_session(&client, "peer.i2p:80").unwrap();
+		accept.join().unwrap().unwrap();
+
+		let creates: Vec<_> = bridge
+			.commands("SESSION CREATE")
+			.into_iter()
+			.filter(|c| c.contains(&format!("ID={nickname} ")))
+			.collect();
+		assert_eq!(creates.len(), 2);
+		assert!(creates[0].contains("DESTINATION=TRANSIENT "));
+		assert!(creates[1].contains(&format!("DESTINATION={} ", MockBridge::dest())));
+		assert_eq!(
+			states.try_iter().collect::<Vec<_>>(),
+			[
+				SessionState::Connecting,
+				SessionState::Ready,
+				SessionState::Degraded,
+				SessionState::Recreating,
+				SessionState::Ready,
+			]
+		);
+	}
+
+	#[test]
+	fn supervisor_gives_up() {
+		// nothing listens there anymore
+		let sam_api = TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap();
+		let supervisor = SessionSupervisor::with_backoff(
+			sam_api,
+			"TRANSIENT",
+			SAMOptions::default(),
+			backoff(Some(3), 0),
+		)
+		.unwrap();
+		let states = supervisor.states();
+		let err = supervisor.start().unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+		assert_eq!(
+			states.try_iter().collect::<Vec<_>>(),
+			[SessionState::Connecting, SessionState::Failed]
+		);
+
+		let supervisor = Arc::new(
+			SessionSupervisor::with_backoff(
+				sam_api,
+				"TRANSIENT",
+				SAMOptions::default(),
+				Backoff {
+					initial_delay: Duration::from_secs(60),
+					..Default::default()
+				},
+			)
+			.unwrap(),
+		);
+		let starting = supervisor.clone();
+		let start = thread::spawn(move || starting.start());
+		thread::sleep(Duration::from_millis(100));
+		// waits for the session being created
+		let waiting = supervisor.clone();
+		let session = thread::spawn(move || waiting.session());
+		thread::sleep(Duration::from_millis(100));
+		supervisor.shutdown();
+		assert!(matches!(start.join().unwrap(), Err(I2PError::Shutdown)));
+		assert!(matches!(session.join().unwrap(), Err(I2PError::Shutdown)));
+	}
+
+	#[test]
+	fn failures() {
+		let io = |kind| I2PError::Io(io::Error::from(kind).into());
+		assert!(matches!(
+			classify(&io(io::ErrorKind::Interrupted)),
+			Failure::Interrupted
+		));
+		// a single stream failed
+		for kind in [io::ErrorKind::UnexpectedEof, io::ErrorKind::TimedOut] {
+			assert!(matches!(classify(&io(kind)), Failure::Stream));
+		}
+		// the bridge lost the session, or is gone
+		assert!(matches!(
+			classify(&io(io::ErrorKind::ConnectionRefused)),
+			Failure::Session
+		));
+		let invalid_id = I2PError::SAMInvalidId(String::new());
+		assert!(matches!(classify(&invalid_id), Failure::Session));
+		assert!(matches!(
+			classify(&io(io::ErrorKind::InvalidData)),
+			Failure::Fatal
+		));
+	}
+}
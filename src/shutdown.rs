@@ -0,0 +1,442 @@
+//! Cancellation of the blocking operations of a session.
+//!
+//! Accepting and connecting streams block on SAM sockets until the router
+//! answers, which may take minutes. A [ShutdownHandle], obtained from
+//! [Session::shutdown_handle](crate::sam::Session::shutdown_handle) or
+//! [I2pListener::shutdown_handle](crate::net::I2pListener::shutdown_handle),
+//! interrupts them from another thread or task.
+
+use crate::error::Result;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::I2PError;
+
+/// How long waking up a listener with a connection may take.
+const WAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a wake-up connection that nothing accepted is remembered.
+const WAKE_EXPIRY: Duration = Duration::from_secs(60);
+
+lazy_static! {
+	static ref WAKES: (Mutex<Wakes>, Condvar) = Default::default();
+}
+
+static NEXT_WAITER: AtomicU64 = AtomicU64::new(0);
+
+/// The wake-up connections sent to listeners, so that accepts can tell them
+/// from streams.
+#[derive(Default)]
+struct Wakes {
+	/// Wake-ups still connecting.
+	connecting: usize,
+	/// The waiter each wake-up is for, by the addresses of its connection.
+	sent: HashMap<(SocketAddr, SocketAddr), (u64, Instant)>,
+	/// The listener address of each waiter.
+	waiters: HashMap<u64, SocketAddr>,
+}
+
+/// A handle shutting down a session and everything waiting on it.
+///
+/// Shutting down closes the session's control socket, which makes the SAM
+/// bridge destroy the session, and interrupts every pending accept and
+/// connect, which then fail with [I2PError::Shutdown]. [Incoming] iterators
+/// of the session's listeners end. Streams already established are left
+/// open.
+///
+/// Handles are cheap to clone, and every duplicate of a session shares the
+/// same one.
+///
+/// [Incoming]: crate::net::Incoming
+///
+/// # Examples
+///
+/// ```no_run
+/// use i2p::net::I2pListener;
+/// use i2p::Session;
+///
+/// let session = Session::transient(i2p::sam::DEFAULT_API).unwrap();
+/// let listener = I2pListener::bind_with_session(&session).unwrap();
+/// let handle = listener.shutdown_handle();
+///
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_secs(60));
+///     handle.shutdown();
+/// });
+/// for stream in listener.incoming() {
+///     // ...
+/// }
+/// // the listener was shut down
+/// ```
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+	inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+	shutdown: AtomicBool,
+	pending: Mutex<HashMap<u64, Interrupt>>,
+	next_id: AtomicU64,
+	#[cfg(feature = "tokio")]
+	notify: tokio::sync::Notify,
+}
+
+/// How to interrupt a blocking operation.
+enum Interrupt {
+	/// Shut a socket down, failing reads and writes on it.
+	Socket(TcpStream),
+	/// Connect to a listener, waking up a waiter's accept.
+	Wake(SocketAddr, u64),
+}
+
+impl ShutdownHandle {
+	pub fn new() -> ShutdownHandle {
+		ShutdownHandle::default()
+	}
+
+	/// Shuts the session down. Calling it again has no effect.
+	pub fn shutdown(&self) {
+		if self.inner.shutdown.swap(true, Ordering::SeqCst) {
+			return;
+		}
+		// waking listeners up takes connecting to them, which must not hold
+		// up registrations meanwhile
+		let pending: Vec<Interrupt> = match self.inner.pending.lock() {
+			Ok(mut pending) => pending.drain().map(|(_, interrupt)| interrupt).collect(),
+			Err(_) => Vec::new(),
+		};
+		for interrupt in pending {
+			match interrupt {
+				Interrupt::Socket(socket) => {
+					let _ = socket.shutdown(Shutdown::Both);
+				}
+				Interrupt::Wake(addr, waiter) => wake(addr, waiter),
+			}
+		}
+		#[cfg(feature = "tokio")]
+		self.inner.notify.notify_waiters();
+	}
+
+	pub fn is_shutdown(&self) -> bool {
+		self.inner.shutdown.load(Ordering::SeqCst)
+	}
+
+	/// Completes once the session is shut down, e.g. to stop an async
+	/// accept loop with `tokio::select!`.
+	#[cfg(feature = "tokio")]
+	pub async fn wait(&self) {
+		let notified = self.inner.notify.notified();
+		tokio::pin!(notified);
+		notified.as_mut().enable();
+		if self.is_shutdown() {
+			return;
+		}
+		notified.await;
+	}
+
+	/// Shuts `socket` down along with the session, for as long as the
+	/// returned guard lives.
+	pub(crate) fn register_socket(&self, socket: &TcpStream) -> Result<Registration> {
+		self.register(Interrupt::Socket(socket.try_clone()?))
+	}
+
+	/// Wakes up the accept of `waiter` when the session is shut down, for
+	/// as long as the returned guard lives.
+	pub(crate) fn register_waiter(&self, waiter: &Waiter) -> Result<Registration> {
+		self.register(Interrupt::Wake(waiter.addr, waiter.id))
+	}
+
+	fn register(&self, interrupt: Interrupt) -> Result<Registration> {
+		let id = self.inner.next_id.fetch_add(1, Ordering::SeqCst);
+		if let Ok(mut pending) = self.inner.pending.lock() {
+			// checked under the lock so a concurrent shutdown can't miss it
+			if self.is_shutdown() {
+				return Err(I2PError::Shutdown);
+			}
+			pending.insert(id, interrupt);
+		}
+		Ok(Registration {
+			handle: self.clone(),
+			id,
+		})
+	}
+
+	/// Runs a blocking operation on `socket`, a handle to the socket the
+	/// operation uses, which is shut down if the session is in the meantime.
+	pub(crate) fn interruptible<T, F>(&self, socket: TcpStream, f: F) -> Result<T>
+	where
+		F: FnOnce() -> Result<T>,
+	{
+		let _registration = self.register(Interrupt::Socket(socket))?;
+		self.check(f())
+	}
+
+	/// Fails an operation that completed, or was interrupted, after a
+	/// shutdown.
+	pub(crate) fn check<T>(&self, res: Result<T>) -> Result<T> {
+		if self.is_shutdown() {
+			return Err(I2PError::Shutdown);
+		}
+		res
+	}
+}
+
+impl fmt::Debug for ShutdownHandle {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("ShutdownHandle")
+			.field("shutdown", &self.is_shutdown())
+			.finish()
+	}
+}
+
+/// An accept on a listener, which wake-up connections are addressed to.
+///
+/// Accepts on clones of the listener may take a wake-up meant for another
+/// one, and then pass it on, see [sort](Waiter::sort).
+pub(crate) struct Waiter {
+	id: u64,
+	addr: SocketAddr,
+}
+
+impl Waiter {
+	pub(crate) fn new(listener: &TcpListener) -> Result<Waiter> {
+		let addr = wake_addr(listener)?;
+		let id = NEXT_WAITER.fetch_add(1, Ordering::SeqCst);
+		lock(&WAKES.0).waiters.insert(id, addr);
+		Ok(Waiter { id, addr })
+	}
+
+	/// Returns `conn`, accepted by this waiter, unless it is a wake-up. A
+	/// wake-up meant for another accept still waiting is passed on to it.
+	pub(crate) fn sort(&self, conn: TcpStream) -> Option<TcpStream> {
+		let (Ok(from), Ok(to)) = (conn.peer_addr(), conn.local_addr()) else {
+			return Some(conn);
+		};
+		let (wakes, cvar) = &*WAKES;
+		let mut wakes = lock(wakes);
+		// a wake-up may be accepted before its sender knows its address
+		let deadline = Instant::now() + WAKE_TIMEOUT;
+		loop {
+			if let Some((waiter, _)) = wakes.sent.remove(&(from, to)) {
+				let other = wakes.waiters.get(&waiter).copied();
+				drop(wakes);
+				match other {
+					Some(addr) if waiter != self.id => wake(addr, waiter),
+					_ => {}
+				}
+				return None;
+			}
+			let timeout = deadline.saturating_duration_since(Instant::now());
+			if wakes.connecting == 0 || timeout.is_zero() {
+				return Some(conn);
+			}
+			wakes = cvar
+				.wait_timeout(wakes, timeout)
+				.unwrap_or_else(PoisonError::into_inner)
+				.0;
+		}
+	}
+}
+
+impl Drop for Waiter {
+	fn drop(&mut self) {
+		lock(&WAKES.0).waiters.remove(&self.id);
+	}
+}
+
+/// Wakes up the accept of a waiter once a deadline has passed, unless
+/// dropped before.
+pub(crate) struct WakeTimer {
+	_cancel: mpsc::Sender<()>,
+}
+
+impl WakeTimer {
+	pub(crate) fn start(waiter: &Waiter, deadline: Instant) -> WakeTimer {
+		let (addr, id) = (waiter.addr, waiter.id);
+		let (cancel, cancelled) = mpsc::channel::<()>();
+		thread::spawn(move || {
+			let timeout = deadline.saturating_duration_since(Instant::now());
+			if let Err(RecvTimeoutError::Timeout) = cancelled.recv_timeout(timeout) {
+				wake(addr, id);
+			}
+		});
+		WakeTimer { _cancel: cancel }
+	}
+}
+
+/// Wakes up the accept of `waiter` on the listener at `addr` with a
+/// connection, recorded for [Waiter::sort] to recognize.
+fn wake(addr: SocketAddr, waiter: u64) {
+	let (wakes, cvar) = &*WAKES;
+	lock(wakes).connecting += 1;
+	let from = TcpStream::connect_timeout(&addr, WAKE_TIMEOUT).and_then(|conn| conn.local_addr());
+	let mut wakes = lock(wakes);
+	wakes.connecting -= 1;
+	if let Ok(from) = from {
+		wakes
+			.sent
+			.retain(|_, (_, sent)| sent.elapsed() < WAKE_EXPIRY);
+		wakes.sent.insert((from, addr), (waiter, Instant::now()));
+	}
+	cvar.notify_all();
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns the address to connect to in order to wake up an accept on
+/// `listener`.
+fn wake_addr(listener: &TcpListener) -> Result<SocketAddr> {
+	let mut addr = listener.local_addr()?;
+	if addr.ip().is_unspecified() {
+		addr.set_ip(match addr {
+			SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+			SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+		});
+	}
+	Ok(addr)
+}
+
+/// Keeps an interrupt registered with a [ShutdownHandle] until dropped.
+pub(crate) struct Registration {
+	handle: ShutdownHandle,
+	id: u64,
+}
+
+impl Registration {
+	/// Registers `socket`, a duplicate of the registered one, with the same
+	/// handle.
+	pub(crate) fn duplicate(&self, socket: &TcpStream) -> Result<Registration> {
+		self.handle.register_socket(socket)
+	}
+}
+
+impl Drop for Registration {
+	fn drop(&mut self) {
+		if let Ok(mut pending) = self.handle.inner.pending.lock() {
+			pending.remove(&self.id);
+		}
+	}
+}
+
+impl fmt::Debug for Registration {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("Registration")
+			.field("id", &self.id)
+			.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock_bridge::MockBridge;
+	use crate::net::{I2pAddr, I2pListener, I2pSocketAddr, I2pStream};
+	use crate::sam::Session;
+	use std::io::{Read, Write};
+	use std::thread;
+	use std::time::Instant;
+
+	#[test]
+	fn interrupts_pending_operations() {
+		let handle = ShutdownHandle::new();
+		let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+		let peer = TcpListener::bind("127.0.0.1:0").unwrap();
+		let mut socket = TcpStream::connect(peer.local_addr().unwrap()).unwrap();
+
+		let reader = handle.clone();
+		let read = thread::spawn(move || {
+			reader.interruptible(socket.try_clone().unwrap(), || {
+				let n = socket.read(&mut [0u8; 16])?;
+				Err(I2PError::Io(std::io::Error::other(format!("read {n}")).into()))
+			})
+		});
+		let waker = handle.clone();
+		let accept = thread::spawn(move || {
+			let waiter = Waiter::new(&listener).unwrap();
+			let _registration = waker.register_waiter(&waiter).unwrap();
+			waker.check(listener.accept().map(|_| ()).map_err(I2PError::from))
+		});
+
+		while handle.inner.pending.lock().unwrap().len() < 2 {
+			thread::sleep(Duration::from_millis(10));
+		}
+		handle.shutdown();
+		for res in [read.join().unwrap(), accept.join().unwrap()] {
+			let err = res.unwrap_err();
+			assert!(matches!(err, I2PError::Shutdown));
+		}
+		assert!(handle.inner.pending.lock().unwrap().is_empty());
+		let socket = TcpStream::connect(peer.local_addr().unwrap()).unwrap();
+		assert!(handle.register_socket(&socket).is_err());
+	}
+
+	#[test]
+	fn session_shutdown() {
+		let bridge = MockBridge::start();
+		let session = Session::transient(bridge.addr()).unwrap();
+		let listener = I2pListener::bind_with_session(&session).unwrap();
+		let pool = I2pListener::bind_with_session(&session)
+			.unwrap()
+			.accept_pool(2, 2)
+			.unwrap();
+		let incoming = thread::spawn(move || listener.incoming().count());
+		bridge.wait_accepts(&session.nickname, 3);
+
+		session.shutdown_handle().shutdown();
+		assert_eq!(incoming.join().unwrap(), 0);
+		assert_eq!(pool.incoming().count(), 0);
+		assert!(session.duplicate().unwrap().naming_lookup("a.i2p").is_err());
+	}
+
+	#[test]
+	fn wake_ups_reach_their_accept() {
+		let bridge = MockBridge::start();
+		let session = Session::transient(bridge.addr()).unwrap();
+		let listener = I2pListener::bind_forwarding_with_session(&session, "127.0.0.1:0").unwrap();
+		let other = listener.try_clone().unwrap();
+		let waiting = thread::spawn(move || other.accept().map(|(_, peer)| peer));
+		thread::sleep(Duration::from_millis(100));
+
+		// the timer's wake-up may be taken by the other accept first
+		let start = Instant::now();
+		let err = listener
+			.accept_timeout(Duration::from_millis(200))
+			.unwrap_err();
+		assert!(err.is_timeout(), "{err:?}");
+		assert!(start.elapsed() < Duration::from_secs(2));
+		assert!(!waiting.is_finished());
+
+		let mut client = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		client.write_all(b"hi").unwrap();
+		assert!(waiting.join().unwrap().is_ok());
+	}
+
+	#[test]
+	fn connect_shutdown() {
+		// nothing accepts on this bridge, connects wait for a peer
+		let bridge = MockBridge::start();
+		let session = Session::transient(bridge.addr()).unwrap();
+		let handle = session.shutdown_handle();
+		let start = Instant::now();
+		let connect = thread::spawn(move || {
+			let addr = I2pSocketAddr::new(I2pAddr::new("nowhere.i2p"), 0);
+			I2pStream::connect_with_session(&session, addr)
+		});
+		thread::sleep(Duration::from_millis(300));
+		handle.shutdown();
+
+		let err = connect.join().unwrap().unwrap_err();
+		assert!(matches!(err, I2PError::Shutdown));
+		assert!(start.elapsed() < Duration::from_secs(2));
+	}
+}
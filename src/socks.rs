@@ -0,0 +1,434 @@
+//! A local SOCKS proxy tunnelling into I2P.
+//!
+//! [SocksProxy] listens on a local TCP port and speaks SOCKS5 (RFC 1928,
+//! without authentication) as well as SOCKS4a. Each `CONNECT` to a `.i2p`
+//! host is bridged into an [I2pStream] opened from a shared [Session], so
+//! unmodified applications can reach eepsites without going through the
+//! router's own proxy. Hostnames are looked up in the proxy's [AddressBook]
+//! first, then by the SAM bridge's `NAMING LOOKUP`. There is no outproxy
+//! support: IP addresses and clearnet hostnames are refused.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use i2p::socks::SocksProxy;
+//! use i2p::Session;
+//!
+//! let session = Session::transient(i2p::sam::DEFAULT_API).unwrap();
+//! let proxy = SocksProxy::bind("127.0.0.1:4447", session).unwrap();
+//! proxy.run().unwrap();
+//! ```
+
+use log::{debug, error, info};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+
+use crate::error::{I2PError, Result};
+use crate::net::{AddressBook, I2pAddr, I2pSocketAddr, I2pStream};
+use crate::sam::Session;
+use crate::session_watcher::Backoff;
+use crate::utils::relay;
+
+const SOCKS4: u8 = 0x04;
+const SOCKS5: u8 = 0x05;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// SOCKS5 reply codes, from RFC 1928 section 6.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Reply {
+	Succeeded = 0x00,
+	GeneralFailure = 0x01,
+	NotAllowed = 0x02,
+	NetworkUnreachable = 0x03,
+	HostUnreachable = 0x04,
+	ConnectionRefused = 0x05,
+	TtlExpired = 0x06,
+	CommandNotSupported = 0x07,
+	AddressTypeNotSupported = 0x08,
+}
+
+impl Reply {
+	/// Picks the reply best describing a refused request or a failed
+	/// stream connect.
+	pub fn from_error(err: &I2PError) -> Reply {
+		match err {
+			I2PError::SocksNotAllowed { .. } => Reply::NotAllowed,
+			I2PError::SocksCommandNotSupported { .. } => Reply::CommandNotSupported,
+			I2PError::SocksAddressTypeNotSupported(_) => Reply::AddressTypeNotSupported,
+			I2PError::SAMKeyNotFound(_)
+			| I2PError::SAMPeerNotFound(_)
+			| I2PError::SAMInvalidKey(_)
+			| I2PError::SAMCantReachPeer(_) => Reply::HostUnreachable,
+			I2PError::SAMTimeout(_) => Reply::TtlExpired,
+			_ => Reply::GeneralFailure,
+		}
+	}
+}
+
+/// The destination a client asked to be connected to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+	/// The SOCKS version spoken by the client, 4 or 5.
+	pub version: u8,
+	pub host: String,
+	pub port: u16,
+}
+
+impl Request {
+	pub fn addr(&self) -> I2pSocketAddr {
+		I2pSocketAddr::new(I2pAddr::new(&self.host), self.port)
+	}
+}
+
+/// A SOCKS5 and SOCKS4a proxy server bridging into I2P.
+pub struct SocksProxy {
+	listener: TcpListener,
+	session: Arc<Session>,
+	address_book: Arc<AddressBook>,
+}
+
+impl SocksProxy {
+	/// Binds the proxy to a local address. Streams are opened through
+	/// `session`.
+	pub fn bind<A: ToSocketAddrs>(addr: A, session: Session) -> Result<SocksProxy> {
+		Ok(SocksProxy {
+			listener: TcpListener::bind(addr)?,
+			session: Arc::new(session),
+			address_book: Arc::new(AddressBook::new()),
+		})
+	}
+
+	/// Uses `address_book` to resolve hostnames before asking the router,
+	/// e.g. to share entries learned by an `HttpProxy`.
+	pub fn address_book(mut self, address_book: Arc<AddressBook>) -> SocksProxy {
+		self.address_book = address_book;
+		self
+	}
+
+	pub fn local_addr(&self) -> Result<SocketAddr> {
+		self.listener.local_addr().map_err(|e| e.into())
+	}
+
+	/// Accepts clients forever, serving each one on its own thread. Failed
+	/// accepts are logged and tried again after a growing delay.
+	pub fn run(&self) -> Result<()> {
+		let backoff = Backoff::accept();
+		let mut failures = 0;
+		for client in self.listener.incoming() {
+			let client = match client {
+				Ok(client) => client,
+				Err(err) => {
+					failures += 1;
+					error!("socks accept failed: {}", err);
+					thread::sleep(backoff.delay(failures));
+					continue;
+				}
+			};
+			failures = 0;
+			let session = self.session.clone();
+			let address_book = self.address_book.clone();
+			thread::spawn(move || {
+				let peer = client.peer_addr();
+				if let Err(err) = handle_client(&session, &address_book, client) {
+					error!("socks client {:?} failed: {:#}", peer, err);
+				}
+			});
+		}
+		Ok(())
+	}
+}
+
+/// Serves a single SOCKS client: negotiates, connects and relays until
+/// either side closes.
+pub fn handle_client(
+	session: &Session,
+	address_book: &AddressBook,
+	mut client: TcpStream,
+) -> Result<()> {
+	let request = match negotiate(&mut client) {
+		Ok(request) => request,
+		Err(err) => {
+			if let Some(version) = refused_version(&err) {
+				write_reply(&mut client, version, Reply::from_error(&err))?;
+			}
+			return Err(err);
+		}
+	};
+	info!("socks{} CONNECT {}:{}", request.version, request.host, request.port);
+
+	let addr = address_book.resolve(&request.addr());
+	let stream = match I2pStream::connect_with_session(session, &addr) {
+		Ok(stream) => stream,
+		Err(err) => {
+			write_reply(&mut client, request.version, Reply::from_error(&err))?;
+			return Err(err);
+		}
+	};
+	write_reply(&mut client, request.version, Reply::Succeeded)?;
+
+	let (sent, received) = relay(client, stream)?;
+	debug!(
+		"socks connection to {}:{} closed, {} bytes sent, {} received",
+		request.host, request.port, sent, received
+	);
+	Ok(())
+}
+
+/// The SOCKS version to reply in when the handshake failed with a refusal
+/// rather than a protocol error.
+fn refused_version(err: &I2PError) -> Option<u8> {
+	match err {
+		I2PError::SocksNotAllowed { version }
+		| I2PError::SocksCommandNotSupported { version, .. } => Some(*version),
+		I2PError::SocksAddressTypeNotSupported(_) => Some(SOCKS5),
+		_ => None,
+	}
+}
+
+fn protocol_error(msg: &str) -> I2PError {
+	I2PError::SocksProtocol(msg.to_string())
+}
+
+fn read_u8<S: Read>(stream: &mut S) -> io::Result<u8> {
+	let mut buf = [0u8; 1];
+	stream.read_exact(&mut buf)?;
+	Ok(buf[0])
+}
+
+fn read_port<S: Read>(stream: &mut S) -> io::Result<u16> {
+	let mut buf = [0u8; 2];
+	stream.read_exact(&mut buf)?;
+	Ok(u16::from_be_bytes(buf))
+}
+
+/// Reads a NUL terminated string, as used by SOCKS4.
+fn read_cstring<S: Read>(stream: &mut S) -> Result<String> {
+	let mut bytes = Vec::new();
+	loop {
+		match read_u8(stream)? {
+			0 => break,
+			_ if bytes.len() >= 255 => return Err(protocol_error("SOCKS4 string too long")),
+			b => bytes.push(b),
+		}
+	}
+	String::from_utf8(bytes).map_err(|_| protocol_error("SOCKS4 string is not UTF-8"))
+}
+
+fn check_host(version: u8, host: &str) -> Result<()> {
+	if host.to_ascii_lowercase().ends_with(".i2p") {
+		Ok(())
+	} else {
+		debug!("refusing non-I2P host {}", host);
+		Err(I2PError::SocksNotAllowed { version })
+	}
+}
+
+/// Performs the SOCKS handshake up to, but excluding, the final reply.
+pub fn negotiate<S: Read + Write>(stream: &mut S) -> Result<Request> {
+	match read_u8(stream)? {
+		SOCKS5 => negotiate_v5(stream),
+		SOCKS4 => negotiate_v4(stream),
+		v => Err(protocol_error(&format!("unsupported SOCKS version {v}"))),
+	}
+}
+
+fn negotiate_v5<S: Read + Write>(stream: &mut S) -> Result<Request> {
+	let mut methods = vec![0u8; read_u8(stream)? as usize];
+	stream.read_exact(&mut methods)?;
+	if !methods.contains(&0x00) {
+		// no acceptable methods
+		stream.write_all(&[SOCKS5, 0xff])?;
+		return Err(protocol_error("client does not support unauthenticated access"));
+	}
+	stream.write_all(&[SOCKS5, 0x00])?;
+
+	let mut header = [0u8; 4];
+	stream.read_exact(&mut header)?;
+	let [version, cmd, _reserved, atyp] = header;
+	if version != SOCKS5 {
+		return Err(protocol_error("bad SOCKS5 request version"));
+	}
+	let host = match atyp {
+		ATYP_DOMAIN => {
+			let mut host = vec![0u8; read_u8(stream)? as usize];
+			stream.read_exact(&mut host)?;
+			String::from_utf8(host).map_err(|_| protocol_error("hostname is not UTF-8"))?
+		}
+		ATYP_IPV4 | ATYP_IPV6 => {
+			let mut addr = vec![0u8; if atyp == ATYP_IPV4 { 4 } else { 16 }];
+			stream.read_exact(&mut addr)?;
+			read_port(stream)?;
+			return Err(I2PError::SocksNotAllowed { version: SOCKS5 });
+		}
+		_ => return Err(I2PError::SocksAddressTypeNotSupported(atyp)),
+	};
+	let port = read_port(stream)?;
+	if cmd != CMD_CONNECT {
+		return Err(I2PError::SocksCommandNotSupported {
+			version: SOCKS5,
+			command: cmd,
+		});
+	}
+	check_host(SOCKS5, &host)?;
+	Ok(Request {
+		version: SOCKS5,
+		host,
+		port,
+	})
+}
+
+fn negotiate_v4<S: Read + Write>(stream: &mut S) -> Result<Request> {
+	let cmd = read_u8(stream)?;
+	let port = read_port(stream)?;
+	let mut ip = [0u8; 4];
+	stream.read_exact(&mut ip)?;
+	let _user_id = read_cstring(stream)?;
+	// SOCKS4a signals a hostname with an address of 0.0.0.x, x != 0
+	if ip[..3] != [0, 0, 0] || ip[3] == 0 {
+		return Err(I2PError::SocksNotAllowed { version: SOCKS4 });
+	}
+	let host = read_cstring(stream)?;
+	if cmd != CMD_CONNECT {
+		return Err(I2PError::SocksCommandNotSupported {
+			version: SOCKS4,
+			command: cmd,
+		});
+	}
+	check_host(SOCKS4, &host)?;
+	Ok(Request {
+		version: SOCKS4,
+		host,
+		port,
+	})
+}
+
+/// Writes the final reply of the handshake. The bound address is always
+/// reported as unspecified, as it has no meaning inside I2P.
+pub fn write_reply<S: Write>(stream: &mut S, version: u8, reply: Reply) -> io::Result<()> {
+	if version == SOCKS4 {
+		let code = if reply == Reply::Succeeded { 0x5a } else { 0x5b };
+		stream.write_all(&[0x00, code, 0, 0, 0, 0, 0, 0])
+	} else {
+		stream.write_all(&[SOCKS5, reply as u8, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+
+	/// An in-memory client: reads from a script, records what was written.
+	struct Client {
+		input: Cursor<Vec<u8>>,
+		output: Vec<u8>,
+	}
+
+	impl Client {
+		fn new(input: &[u8]) -> Client {
+			Client {
+				input: Cursor::new(input.to_vec()),
+				output: Vec::new(),
+			}
+		}
+	}
+
+	impl Read for Client {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			self.input.read(buf)
+		}
+	}
+
+	impl Write for Client {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.output.write(buf)
+		}
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	fn refusal(err: I2PError) -> Reply {
+		assert!(refused_version(&err).is_some(), "expected a refusal, got {err:?}");
+		Reply::from_error(&err)
+	}
+
+	#[test]
+	fn socks5_connect() {
+		let mut input = vec![5, 2, 0x02, 0x00, 5, 1, 0, ATYP_DOMAIN, 11];
+		input.extend_from_slice(b"example.i2p");
+		input.extend_from_slice(&80u16.to_be_bytes());
+		let mut client = Client::new(&input);
+		let request = negotiate(&mut client).unwrap();
+		assert_eq!(
+			request,
+			Request {
+				version: 5,
+				host: "example.i2p".to_string(),
+				port: 80
+			}
+		);
+		assert_eq!(client.output, [5, 0]);
+
+		write_reply(&mut client, request.version, Reply::Succeeded).unwrap();
+		assert_eq!(&client.output[2..], [5, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+	}
+
+	#[test]
+	fn socks5_refusals() {
+		let mut client = Client::new(&[5, 1, 0x02]);
+		assert!(negotiate(&mut client).is_err());
+		assert_eq!(client.output, [5, 0xff]);
+
+		let mut client = Client::new(&[5, 1, 0, 5, 1, 0, ATYP_IPV4, 127, 0, 0, 1, 0, 80]);
+		assert_eq!(refusal(negotiate(&mut client).unwrap_err()), Reply::NotAllowed);
+
+		let mut input = vec![5, 1, 0, 5, 1, 0, ATYP_DOMAIN, 11];
+		input.extend_from_slice(b"example.com\x00\x50");
+		let mut client = Client::new(&input);
+		assert_eq!(refusal(negotiate(&mut client).unwrap_err()), Reply::NotAllowed);
+
+		let mut input = vec![5, 1, 0, 5, 2, 0, ATYP_DOMAIN, 11];
+		input.extend_from_slice(b"example.i2p\x00\x50");
+		let mut client = Client::new(&input);
+		assert_eq!(
+			refusal(negotiate(&mut client).unwrap_err()),
+			Reply::CommandNotSupported
+		);
+
+		let mut client = Client::new(&[5, 1, 0, 5, 1, 0, 0x09]);
+		assert_eq!(
+			refusal(negotiate(&mut client).unwrap_err()),
+			Reply::AddressTypeNotSupported
+		);
+	}
+
+	#[test]
+	fn socks4a_connect() {
+		let mut input = vec![4, 1, 0x1f, 0x90, 0, 0, 0, 1];
+		input.extend_from_slice(b"user\0example.i2p\0");
+		let mut client = Client::new(&input);
+		let request = negotiate(&mut client).unwrap();
+		assert_eq!(request.version, 4);
+		assert_eq!(request.addr().to_string(), "example.i2p:8080");
+
+		write_reply(&mut client, 4, Reply::HostUnreachable).unwrap();
+		assert_eq!(client.output, [0, 0x5b, 0, 0, 0, 0, 0, 0]);
+
+		// plain SOCKS4 with an IP address
+		let mut client = Client::new(&[4, 1, 0, 80, 10, 0, 0, 1, 0]);
+		assert_eq!(refusal(negotiate(&mut client).unwrap_err()), Reply::NotAllowed);
+	}
+
+	#[test]
+	fn bad_version() {
+		let mut client = Client::new(&[3]);
+		assert!(negotiate(&mut client).is_err());
+	}
+}
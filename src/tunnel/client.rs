@@ -0,0 +1,202 @@
+use crate::error::Result;
+use log::{debug, error, info};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use super::{accept_loop, TunnelHandle};
+use crate::error::I2PError;
+use crate::net::{I2pSocketAddr, StreamPool, ToI2pSocketAddrs};
+use crate::sam::Session;
+use crate::utils::relay;
+
+/// Pre-opened streams older than this are closed instead of being used, as
+/// the remote end may have dropped them in the meantime.
+pub const POOLED_STREAM_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// How often the stream pool is checked and refilled, on top of refilling it
+/// whenever a pooled stream is used.
+const POOL_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A client tunnel exposing a remote I2P destination on a local TCP port.
+///
+/// Every connection accepted on the local port is relayed over a new
+/// [I2pStream] to the target. Streams can be opened ahead of time to hide
+/// the latency of building them, and the number of simultaneous connections
+/// can be capped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use i2p::tunnel::ClientTunnel;
+/// use i2p::Session;
+///
+/// let session = Session::transient(i2p::sam::DEFAULT_API).unwrap();
+/// let tunnel = ClientTunnel::bind("127.0.0.1:8080", session, "example.i2p:80")
+///     .unwrap()
+///     .pool_size(2)
+///     .max_connections(32);
+///
+/// let handle = tunnel.handle();
+/// std::thread::spawn(move || tunnel.run().unwrap());
+/// // ...
+/// handle.shutdown();
+/// ```
+pub struct ClientTunnel {
+	listener: TcpListener,
+	session: Session,
+	target: I2pSocketAddr,
+	pool_size: usize,
+	max_connections: usize,
+	handle: TunnelHandle,
+}
+
+impl ClientTunnel {
+	/// Binds the tunnel to a local address. Streams to `target` are opened
+	/// through `session`.
+	pub fn bind<A: ToSocketAddrs, B: ToI2pSocketAddrs>(
+		addr: A,
+		session: Session,
+		target: B,
+	) -> Result<ClientTunnel> {
+		let target = target
+			.to_socket_addrs()?
+			.next()
+			.ok_or(I2PError::UnresolvableAddress)?;
+		let listener = TcpListener::bind(addr)?;
+		Ok(ClientTunnel {
+			handle: TunnelHandle::new(&listener)?,
+			listener,
+			session,
+			target,
+			pool_size: 0,
+			max_connections: 0,
+		})
+	}
+
+	/// Sets the number of streams kept open ahead of time. Zero, the
+	/// default, opens a stream only once a client connects.
+	pub fn pool_size(mut self, size: usize) -> ClientTunnel {
+		self.pool_size = size;
+		self
+	}
+
+	/// Sets the maximum number of connections relayed at once. Clients over
+	/// the limit are disconnected right away. Zero, the default, means no
+	/// limit.
+	pub fn max_connections(mut self, max: usize) -> ClientTunnel {
+		self.max_connections = max;
+		self
+	}
+
+	pub fn local_addr(&self) -> Result<SocketAddr> {
+		self.listener.local_addr().map_err(|e| e.into())
+	}
+
+	pub fn target(&self) -> &I2pSocketAddr {
+		&self.target
+	}
+
+	/// Returns a handle to shut the tunnel down from another thread.
+	pub fn handle(&self) -> TunnelHandle {
+		self.handle.clone()
+	}
+
+	/// Accepts and relays clients until the tunnel is shut down, then waits
+	/// for the remaining connections to finish. Pre-opened streams are
+	/// closed once this returns.
+	pub fn run(&self) -> Result<()> {
+		info!("client tunnel {} -> {}", self.local_addr()?, self.target);
+		let pool = StreamPool::with_session(&self.session)?
+			.max_idle_per_dest(self.pool_size)
+			.max_idle_time(POOLED_STREAM_MAX_AGE);
+		if self.pool_size > 0 {
+			// also refills the pool whenever a stream is taken out of it
+			pool.keep_warm(&self.target, self.pool_size)?;
+			pool.spawn_maintenance(POOL_MAINTENANCE_INTERVAL);
+		}
+
+		// the maintenance thread only holds a weak handle, the pool goes away
+		// with the last connection
+		let target = self.target.clone();
+		let result = accept_loop(
+			&self.listener,
+			&self.handle,
+			self.max_connections,
+			move |client| {
+				let peer = client.peer_addr();
+				if let Err(err) = serve(&pool, &target, client) {
+					error!("client tunnel connection {:?} failed: {:#}", peer, err);
+				}
+			},
+		);
+		result.map_err(|e| e.into())
+	}
+}
+
+/// Relays a client over a pooled stream, or a fresh one if none is ready.
+fn serve(pool: &StreamPool, target: &I2pSocketAddr, client: TcpStream) -> Result<()> {
+	// the relay owns the stream until one side closes, so it never goes back
+	// to the pool
+	let stream = pool.get(target)?.into_inner();
+	let (sent, received) = relay(client, stream)?;
+	debug!(
+		"client tunnel to {} closed, {} bytes sent, {} received",
+		target, sent, received
+	);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock_bridge::MockBridge;
+	use crate::net::I2pListener;
+	use std::io::{Read, Write};
+	use std::thread;
+
+	#[test]
+	fn pooled_streams() {
+		let bridge = MockBridge::start();
+		let session = Session::transient(bridge.addr()).unwrap();
+		let listener = I2pListener::bind_with_session(&session).unwrap();
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let mut stream = stream.unwrap();
+				thread::spawn(move || {
+					let mut buf = [0u8; 4];
+					while stream.read_exact(&mut buf).is_ok() {
+						stream.write_all(&buf).unwrap();
+					}
+				});
+			}
+		});
+
+		let tunnel = ClientTunnel::bind("127.0.0.1:0", session.duplicate().unwrap(), "echo.i2p:7")
+			.unwrap()
+			.pool_size(1);
+		let addr = tunnel.local_addr().unwrap();
+		let handle = tunnel.handle();
+		let run = thread::spawn(move || tunnel.run());
+		let connects = || bridge.commands("STREAM CONNECT").len();
+		while connects() < 1 {
+			thread::sleep(Duration::from_millis(10));
+		}
+		thread::sleep(Duration::from_millis(100));
+
+		// served over the pre-opened stream, which is then replaced
+		let mut client = TcpStream::connect(addr).unwrap();
+		client.write_all(b"ping").unwrap();
+		let mut buf = [0u8; 4];
+		client.read_exact(&mut buf).unwrap();
+		assert_eq!(&buf, b"ping");
+		while connects() < 2 {
+			thread::sleep(Duration::from_millis(10));
+		}
+		thread::sleep(Duration::from_millis(100));
+		assert_eq!(connects(), 2);
+
+		drop(client);
+		handle.shutdown();
+		run.join().unwrap().unwrap();
+	}
+}
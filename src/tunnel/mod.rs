@@ -0,0 +1,176 @@
+//! Long-running tunnels between local TCP ports and I2P destinations, in the
+//! spirit of the router's own i2ptunnel.
+//!
+//! A [ClientTunnel] listens on a local port and forwards every connection to
+//! a fixed remote destination. A [ServerTunnel] does the opposite, relaying
+//! the incoming streams of a session to a local TCP service.
+
+use log::error;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use crate::error::I2PError;
+use crate::session_watcher::Backoff;
+
+mod client;
+mod server;
+
+pub use self::client::ClientTunnel;
+pub use self::server::{ProxyProtocol, ServerTunnel, PP2_TYPE_I2P_DEST};
+
+/// A handle stopping a running tunnel from another thread.
+///
+/// Shutting down stops accepting new connections; connections already being
+/// relayed are left to finish before `run` returns.
+#[derive(Debug, Clone)]
+pub struct TunnelHandle {
+	shutdown: Arc<AtomicBool>,
+	active: Arc<AtomicUsize>,
+	local_addr: SocketAddr,
+}
+
+impl TunnelHandle {
+	fn new(listener: &TcpListener) -> io::Result<TunnelHandle> {
+		let mut local_addr = listener.local_addr()?;
+		// wake-up connections can't be sent to a wildcard address everywhere
+		if local_addr.ip().is_unspecified() {
+			local_addr.set_ip(match local_addr {
+				SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+				SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+			});
+		}
+		Ok(TunnelHandle {
+			shutdown: Arc::new(AtomicBool::new(false)),
+			active: Arc::new(AtomicUsize::new(0)),
+			local_addr,
+		})
+	}
+
+	/// Asks the tunnel to stop.
+	pub fn shutdown(&self) {
+		if !self.shutdown.swap(true, Ordering::SeqCst) {
+			// unblock the pending accept
+			let _ = TcpStream::connect(self.local_addr);
+		}
+	}
+
+	pub fn is_shutdown(&self) -> bool {
+		self.shutdown.load(Ordering::SeqCst)
+	}
+
+	/// Returns the number of connections currently being relayed.
+	pub fn active_connections(&self) -> usize {
+		self.active.load(Ordering::SeqCst)
+	}
+}
+
+/// Accepts connections on `listener` until `handle` is shut down, passing
+/// each one to `serve` on its own thread. At most `max_connections` are
+/// served at once (zero means no limit); clients over the limit are closed
+/// right away. Failed accepts are tried again after a growing delay, unless
+/// the error isn't [retryable](I2PError::is_retryable), which stops the
+/// loop. Returns once every connection has been served.
+fn accept_loop<F>(
+	listener: &TcpListener,
+	handle: &TunnelHandle,
+	max_connections: usize,
+	serve: F,
+) -> io::Result<()>
+where
+	F: Fn(TcpStream) + Clone + Send + 'static,
+{
+	let backoff = Backoff::accept();
+	let mut failures = 0;
+	let mut result = Ok(());
+	let mut workers: Vec<JoinHandle<()>> = Vec::new();
+	for client in listener.incoming() {
+		if handle.is_shutdown() {
+			break;
+		}
+		let client = match client {
+			Ok(client) => client,
+			Err(err) => {
+				let err = I2PError::from(err);
+				if !err.is_retryable() {
+					error!("tunnel accept failed, stopping: {}", err);
+					result = Err(err.into());
+					break;
+				}
+				failures += 1;
+				error!("tunnel accept failed: {}", err);
+				std::thread::sleep(backoff.delay(failures));
+				continue;
+			}
+		};
+		failures = 0;
+		workers.retain(|worker| !worker.is_finished());
+		if max_connections > 0 && handle.active_connections() >= max_connections {
+			error!(
+				"refusing {:?}: {} connections already active",
+				client.peer_addr(),
+				max_connections
+			);
+			continue;
+		}
+
+		handle.active.fetch_add(1, Ordering::SeqCst);
+		let active = handle.active.clone();
+		let serve = serve.clone();
+		workers.push(std::thread::spawn(move || {
+			serve(client);
+			active.fetch_sub(1, Ordering::SeqCst);
+		}));
+	}
+	for worker in workers {
+		let _ = worker.join();
+	}
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{Read, Write};
+	use std::sync::mpsc;
+	use std::thread;
+	use std::time::Duration;
+
+	#[test]
+	fn accept_loop_limits_and_shutdown() {
+		let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+		let handle = TunnelHandle::new(&listener).unwrap();
+		let addr = handle.local_addr;
+		let (release, released) = mpsc::channel::<()>();
+		let released = Arc::new(std::sync::Mutex::new(released));
+
+		let loop_handle = handle.clone();
+		let tunnel = thread::spawn(move || {
+			accept_loop(&listener, &loop_handle, 1, move |mut client| {
+				let _ = released.lock().unwrap().recv();
+				client.write_all(b"served").unwrap();
+			})
+		});
+
+		let mut first = TcpStream::connect(addr).unwrap();
+		while handle.active_connections() == 0 {
+			thread::sleep(Duration::from_millis(10));
+		}
+		// over the limit: closed without being served
+		let mut second = TcpStream::connect(addr).unwrap();
+		let mut buf = Vec::new();
+		second.read_to_end(&mut buf).unwrap();
+		assert!(buf.is_empty());
+
+		handle.shutdown();
+		assert!(handle.is_shutdown());
+		// the in-flight connection still completes
+		release.send(()).unwrap();
+		first.read_to_end(&mut buf).unwrap();
+		assert_eq!(buf, b"served");
+		tunnel.join().unwrap().unwrap();
+		assert_eq!(handle.active_connections(), 0);
+	}
+}
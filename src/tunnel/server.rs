@@ -0,0 +1,360 @@
+use crate::error::Result;
+use log::{debug, error, info, warn};
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{accept_loop, TunnelHandle};
+use crate::error::I2PError;
+use crate::net::{AccessFilter, I2pAddr, I2pSocketAddr};
+use crate::sam::{Session, StreamForward, StreamHeader};
+use crate::utils::relay_tcp;
+
+/// PROXY protocol v2 TLV type carrying the peer's `.b32.i2p` address, taken
+/// from the range reserved for custom use.
+pub const PP2_TYPE_I2P_DEST: u8 = 0xE0;
+
+const PP2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// How long the header of a forwarded stream may take to arrive.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Versions of the HAProxy PROXY protocol a [ServerTunnel] can announce
+/// peers with.
+///
+/// I2P peers have no IP address, so the header uses the protocol's
+/// `UNKNOWN` (v1) or `UNSPEC` (v2) family and carries the peer's b32 address
+/// instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocol {
+	/// A text line, `PROXY UNKNOWN <b32> <from port> <to port>\r\n`. Servers
+	/// ignore the rest of an `UNKNOWN` line, but may log it.
+	V1,
+	/// A binary header with the b32 address in a [PP2_TYPE_I2P_DEST] TLV.
+	V2,
+}
+
+impl ProxyProtocol {
+	/// Builds the header announcing a stream from `peer` to `to_port`.
+	pub fn header(&self, peer: &I2pSocketAddr, to_port: u16) -> Vec<u8> {
+		let b32 = peer.dest().string();
+		match self {
+			ProxyProtocol::V1 => {
+				format!("PROXY UNKNOWN {} {} {}\r\n", b32, peer.port(), to_port).into_bytes()
+			}
+			ProxyProtocol::V2 => {
+				let mut header = PP2_SIGNATURE.to_vec();
+				// version 2, PROXY command, UNSPEC family and protocol
+				header.extend_from_slice(&[0x21, 0x00]);
+				header.extend_from_slice(&(3 + b32.len() as u16).to_be_bytes());
+				header.push(PP2_TYPE_I2P_DEST);
+				header.extend_from_slice(&(b32.len() as u16).to_be_bytes());
+				header.extend_from_slice(b32.as_bytes());
+				header
+			}
+		}
+	}
+}
+
+/// A server tunnel publishing a local TCP service on an I2P destination.
+///
+/// The tunnel asks the SAM bridge to forward the session's incoming streams
+/// to a local listener, strips the header line the bridge sends ahead of
+/// each one and relays the stream to the target service. On
+/// [silent](Session::set_silent) sessions the bridge sends no header line,
+/// and the streams are relayed as they come.
+///
+/// Streams the session's access list or connection limits refuse are
+/// closed without reaching the target, as for
+/// [I2pListener](crate::net::I2pListener).
+///
+/// # Examples
+///
+/// ```no_run
+/// use i2p::tunnel::{ProxyProtocol, ServerTunnel};
+/// use i2p::Session;
+///
+/// let session = Session::transient(i2p::sam::DEFAULT_API).unwrap();
+/// let tunnel = ServerTunnel::bind("127.0.0.1:0", session, "127.0.0.1:8080")
+///     .unwrap()
+///     .proxy_protocol(ProxyProtocol::V2);
+/// tunnel.run().unwrap();
+/// ```
+pub struct ServerTunnel {
+	listener: TcpListener,
+	forward: Arc<StreamForward>,
+	target: SocketAddr,
+	proxy_protocol: Option<ProxyProtocol>,
+	max_connections: usize,
+	silent: bool,
+	handle: TunnelHandle,
+}
+
+impl ServerTunnel {
+	/// Binds the listener receiving forwarded streams to `addr`, which must
+	/// be reachable from the SAM bridge. Streams of `session` are relayed
+	/// to `target`.
+	pub fn bind<A: ToSocketAddrs, B: ToSocketAddrs>(
+		addr: A,
+		session: Session,
+		target: B,
+	) -> Result<ServerTunnel> {
+		let target = target
+			.to_socket_addrs()?
+			.next()
+			.ok_or(I2PError::UnresolvableAddress)?;
+		let listener = TcpListener::bind(addr)?;
+		Ok(ServerTunnel {
+			handle: TunnelHandle::new(&listener)?,
+			listener,
+			forward: Arc::new(StreamForward::with_session(&session)?),
+			target,
+			proxy_protocol: None,
+			max_connections: 0,
+			silent: session.is_silent(),
+		})
+	}
+
+	/// Prepends a PROXY protocol header to every connection to the target.
+	/// Silent sessions don't tell the peer's address, so no header is sent
+	/// for them.
+	pub fn proxy_protocol(mut self, version: ProxyProtocol) -> ServerTunnel {
+		self.proxy_protocol = Some(version);
+		self
+	}
+
+	/// Sets the maximum number of streams relayed at once. Streams over the
+	/// limit are closed right away. Zero, the default, means no limit.
+	pub fn max_connections(mut self, max: usize) -> ServerTunnel {
+		self.max_connections = max;
+		self
+	}
+
+	pub fn local_addr(&self) -> Result<SocketAddr> {
+		self.listener.local_addr().map_err(|e| e.into())
+	}
+
+	pub fn target(&self) -> &SocketAddr {
+		&self.target
+	}
+
+	/// Returns a handle to shut the tunnel down from another thread.
+	pub fn handle(&self) -> TunnelHandle {
+		self.handle.clone()
+	}
+
+	/// Relays incoming streams until the tunnel is shut down, then waits for
+	/// the remaining connections to finish.
+	pub fn run(&self) -> Result<()> {
+		let forwarding = self.forward.forward_to(&self.listener)?;
+		info!(
+			"server tunnel {} -> {}",
+			self.forward.local_addr()?.0,
+			self.target
+		);
+
+		let forward = self.forward.clone();
+		let target = self.target;
+		let silent = self.silent;
+		let proxy_protocol = match self.proxy_protocol {
+			Some(_) if silent => {
+				warn!("the session is silent, no PROXY protocol header will be sent");
+				None
+			}
+			proxy_protocol => proxy_protocol,
+		};
+		let result = accept_loop(
+			&self.listener,
+			&self.handle,
+			self.max_connections,
+			move |conn| {
+				let access = forward.access_filter();
+				if let Err(err) = serve(conn, &access, target, proxy_protocol, silent) {
+					error!("server tunnel stream failed: {:#}", err);
+				}
+			},
+		);
+		drop(forwarding);
+		result.map_err(|e| e.into())
+	}
+}
+
+/// Relays a single forwarded stream to `target` if `access` allows its peer.
+/// Streams of silent sessions come without a header, and thus without a peer
+/// address.
+fn serve(
+	mut conn: TcpStream,
+	access: &AccessFilter,
+	target: SocketAddr,
+	proxy_protocol: Option<ProxyProtocol>,
+	silent: bool,
+) -> Result<()> {
+	let (peer, to_port) = if silent {
+		(I2pSocketAddr::new(I2pAddr::new(""), 0), 0)
+	} else {
+		// the bridge sends the header right away, don't let a stalled one
+		// hold the connection forever
+		conn.set_read_timeout(Some(HEADER_TIMEOUT))?;
+		let header = StreamHeader::read(&mut conn)?;
+		conn.set_read_timeout(None)?;
+		(header.peer_addr()?, header.to_port)
+	};
+	if !access.allows(&peer) {
+		debug!("dropping server tunnel stream from {}", peer.dest());
+		return Ok(());
+	}
+	debug!("server tunnel stream from {}", peer);
+
+	let mut upstream = TcpStream::connect(target)?;
+	if let Some(version) = proxy_protocol {
+		upstream.write_all(&version.header(&peer, to_port))?;
+	}
+	let (to_peer, from_peer) = relay_tcp(upstream, conn)?;
+	debug!(
+		"server tunnel stream from {} closed, {} bytes sent, {} received",
+		peer, to_peer, from_peer
+	);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock_bridge::MockBridge;
+	use crate::net::{I2pStream, BASE64_I2P};
+	use crate::sam::SessionStyle;
+	use crate::sam_options::SAMOptions;
+	use std::io::Read;
+	use std::thread;
+
+	#[test]
+	fn proxy_protocol_headers() {
+		let peer = I2pSocketAddr::new(I2pAddr::new("abc.b32.i2p"), 1234);
+		assert_eq!(
+			ProxyProtocol::V1.header(&peer, 80),
+			b"PROXY UNKNOWN abc.b32.i2p 1234 80\r\n"
+		);
+		let mut expected = b"\r\n\r\n\0\r\nQUIT\n\x21\x00\x00\x0e\xe0\x00\x0b".to_vec();
+		expected.extend_from_slice(b"abc.b32.i2p");
+		assert_eq!(ProxyProtocol::V2.header(&peer, 80), expected);
+	}
+
+	#[test]
+	fn serve_forwarded_stream() {
+		let dest = BASE64_I2P.encode(&[0u8; 387]);
+		let peer = I2pAddr::from_b64(&dest).unwrap();
+		let target = TcpListener::bind("127.0.0.1:0").unwrap();
+		let target_addr = target.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			let (mut conn, _) = target.accept().unwrap();
+			let mut received = Vec::new();
+			conn.read_to_end(&mut received).unwrap();
+			conn.write_all(b"pong").unwrap();
+			received
+		});
+
+		// stands in for the SAM bridge
+		let forwarded = TcpListener::bind("127.0.0.1:0").unwrap();
+		let mut bridge = TcpStream::connect(forwarded.local_addr().unwrap()).unwrap();
+		let (conn, _) = forwarded.accept().unwrap();
+		let tunnel = thread::spawn(move || {
+			serve(
+				conn,
+				&AccessFilter::default(),
+				target_addr,
+				Some(ProxyProtocol::V1),
+				false,
+			)
+		});
+
+		bridge
+			.write_all(format!("{dest} FROM_PORT=1234 TO_PORT=80\nping").as_bytes())
+			.unwrap();
+		bridge.shutdown(std::net::Shutdown::Write).unwrap();
+		let mut reply = Vec::new();
+		bridge.read_to_end(&mut reply).unwrap();
+		assert_eq!(reply, b"pong");
+
+		tunnel.join().unwrap().unwrap();
+		let received = server.join().unwrap();
+		assert_eq!(
+			String::from_utf8(received).unwrap(),
+			format!("PROXY UNKNOWN {} 1234 80\r\nping", peer)
+		);
+	}
+
+	#[test]
+	fn silent_session() {
+		let target = TcpListener::bind("127.0.0.1:0").unwrap();
+		let target_addr = target.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			let (mut conn, _) = target.accept().unwrap();
+			let mut received = [0u8; 4];
+			conn.read_exact(&mut received).unwrap();
+			conn.write_all(b"pong").unwrap();
+			received
+		});
+
+		let bridge = MockBridge::start();
+		let mut session = Session::transient(bridge.addr()).unwrap();
+		session.set_silent(true);
+		let tunnel = ServerTunnel::bind("127.0.0.1:0", session.duplicate().unwrap(), target_addr)
+			.unwrap()
+			.proxy_protocol(ProxyProtocol::V1);
+		let handle = tunnel.handle();
+		let run = thread::spawn(move || tunnel.run());
+		while bridge.commands("STREAM FORWARD").is_empty() {
+			thread::sleep(std::time::Duration::from_millis(10));
+		}
+
+		// the payload is relayed as is, without a header to strip or add
+		let mut client = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		client.write_all(b"ping").unwrap();
+		let mut reply = [0u8; 4];
+		client.read_exact(&mut reply).unwrap();
+		assert_eq!(&reply, b"pong");
+		assert_eq!(&server.join().unwrap(), b"ping");
+
+		drop(client);
+		handle.shutdown();
+		run.join().unwrap().unwrap();
+	}
+
+	#[test]
+	fn refused_streams() {
+		let target = TcpListener::bind("127.0.0.1:0").unwrap();
+		target.set_nonblocking(true).unwrap();
+		let target_addr = target.local_addr().unwrap();
+
+		// every session of the bridge has the same destination
+		let bridge = MockBridge::start();
+		let bridge_addr = I2pAddr::from_b64(&MockBridge::dest()).unwrap();
+		let options = SAMOptions::builder()
+			.deny(vec![bridge_addr])
+			.build()
+			.unwrap();
+		let session = Session::create(
+			bridge.addr(),
+			"TRANSIENT",
+			"refused",
+			SessionStyle::Stream,
+			options,
+		)
+		.unwrap();
+		let tunnel =
+			ServerTunnel::bind("127.0.0.1:0", session.duplicate().unwrap(), target_addr).unwrap();
+		let handle = tunnel.handle();
+		let run = thread::spawn(move || tunnel.run());
+		while bridge.commands("STREAM FORWARD").is_empty() {
+			thread::sleep(std::time::Duration::from_millis(10));
+		}
+
+		let mut client = I2pStream::connect_with_session(&session, "peer.i2p:80").unwrap();
+		assert_eq!(client.read(&mut [0u8]).unwrap(), 0);
+		assert!(target.accept().is_err());
+
+		handle.shutdown();
+		run.join().unwrap().unwrap();
+	}
+}
@@ -1,4 +1,64 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+
+use crate::net::I2pStream;
+
 pub fn rand_string(length: usize) -> String {
 	use rand::distributions::{Alphanumeric, DistString};
 	Alphanumeric.sample_string(&mut rand::thread_rng(), length)
 }
+
+/// Copies data between a local TCP connection and an I2P stream in both
+/// directions, until both sides are done. When one side stops sending, the
+/// write half of the other side is shut down so the close propagates.
+///
+/// Returns the number of bytes sent to, and received from, the I2P stream.
+pub fn relay(local: TcpStream, stream: I2pStream) -> io::Result<(u64, u64)> {
+	let local_reader = local.try_clone()?;
+	let stream_writer = stream.try_clone()?;
+	relay_halves(local_reader, stream_writer, stream, local)
+}
+
+/// Same as [relay], between two TCP connections such as the ones the SAM
+/// bridge opens for forwarded streams.
+pub fn relay_tcp(local: TcpStream, remote: TcpStream) -> io::Result<(u64, u64)> {
+	relay_halves(local.try_clone()?, remote.try_clone()?, remote, local)
+}
+
+/// A connection whose write half can be closed on its own.
+trait HalfClose: Read + Write + Send + 'static {
+	fn shutdown_write(&self);
+}
+
+impl HalfClose for TcpStream {
+	fn shutdown_write(&self) {
+		let _ = self.shutdown(Shutdown::Write);
+	}
+}
+
+impl HalfClose for I2pStream {
+	fn shutdown_write(&self) {
+		let _ = self.shutdown(Shutdown::Write);
+	}
+}
+
+fn relay_halves<L: HalfClose, R: HalfClose>(
+	mut local_reader: L,
+	mut remote_writer: R,
+	mut remote: R,
+	mut local: L,
+) -> io::Result<(u64, u64)> {
+	let upload = thread::spawn(move || {
+		let sent = io::copy(&mut local_reader, &mut remote_writer);
+		remote_writer.shutdown_write();
+		sent
+	});
+	let received = io::copy(&mut remote, &mut local);
+	local.shutdown_write();
+
+	let sent = upload
+		.join()
+		.map_err(|_| io::Error::other("relay thread panicked"))??;
+	Ok((sent, received?))
+}